@@ -1,5 +1,8 @@
 pub use c3p0_common::*;
 
+#[cfg(feature = "derive")]
+pub use c3p0_macro::C3p0Model;
+
 #[cfg(any(feature = "in_memory"))]
 pub mod in_memory {
     pub use c3p0_in_memory::*;
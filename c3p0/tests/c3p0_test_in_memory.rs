@@ -8,7 +8,9 @@ use once_cell::sync::OnceCell;
 
 pub type C3p0Impl = InMemoryC3p0Pool;
 
+mod tests_change_feed;
 mod tests_json;
+mod tests_lock;
 pub mod utils;
 
 pub type MaybeType = (C3p0Impl, String);
@@ -33,4 +35,14 @@ pub mod db_specific {
     pub fn db_type() -> utils::DbType {
         utils::DbType::InMemory
     }
+
+    /// Returns a new pool; the in-memory pool has one connection
+    pub async fn new_pool(_data: &MaybeType, _max_size: u32) -> C3p0Impl {
+        InMemoryC3p0Pool::new()
+    }
+
+    /// The in-memory change feed has no connections to terminate
+    pub async fn terminate_listeners(_pool: &C3p0Impl, _channel: &str) -> Result<(), C3p0Error> {
+        Ok(())
+    }
 }
@@ -14,7 +14,9 @@ use std::time::Duration;
 pub type C3p0Impl = PgC3p0Pool;
 
 mod tests;
+mod tests_change_feed;
 mod tests_json;
+mod tests_lock;
 mod utils;
 
 pub type MaybeType = (
@@ -28,6 +30,18 @@ async fn init() -> MaybeType {
         .get_or_init(|| clients::Cli::default())
         .run(images::postgres::Postgres::default());
 
+    let config = pool_config(&node, None);
+    let pool = PgC3p0Pool::new(config.create_pool(NoTls).unwrap())
+        .with_listener_config(config.get_pg_config().unwrap())
+        .with_listener_tls(NoTls);
+
+    (pool, node)
+}
+
+fn pool_config(
+    node: &Container<'static, clients::Cli, images::postgres::Postgres>,
+    max_size: Option<usize>,
+) -> deadpool::postgres::Config {
     let mut config = deadpool::postgres::Config::default();
     config.user = Some("postgres".to_owned());
     config.password = Some("postgres".to_owned());
@@ -35,14 +49,14 @@ async fn init() -> MaybeType {
     config.host = Some(format!("127.0.0.1"));
     config.port = Some(node.get_host_port(5432).unwrap());
     let mut pool_config = deadpool::managed::PoolConfig::default();
+    if let Some(max_size) = max_size {
+        pool_config.max_size = max_size;
+    }
     pool_config.timeouts.create = Some(Duration::from_secs(5));
     pool_config.timeouts.recycle = Some(Duration::from_secs(5));
     pool_config.timeouts.wait = Some(Duration::from_secs(5));
     config.pool = Some(pool_config);
-
-    let pool = PgC3p0Pool::new(config.create_pool(NoTls).unwrap());
-
-    (pool, node)
+    config
 }
 
 pub async fn data(serial: bool) -> Data<'static, MaybeType> {
@@ -60,6 +74,22 @@ pub mod db_specific {
         utils::DbType::Pg
     }
 
+    /// Returns a new pool of the database of `data` with at most `max_size` connections
+    pub async fn new_pool(data: &MaybeType, max_size: u32) -> C3p0Impl {
+        let config = pool_config(&data.1, Some(max_size as usize));
+        PgC3p0Pool::new(config.create_pool(NoTls).unwrap())
+    }
+
+    /// Terminates the connections listening to the channel of a change feed
+    pub async fn terminate_listeners(pool: &C3p0Impl, channel: &str) -> Result<(), C3p0Error> {
+        let sql = format!(
+            r#"SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE query = 'LISTEN "{}"'"#,
+            channel
+        );
+        pool.connection(|mut conn| async move { conn.batch_execute(&sql).await })
+            .await
+    }
+
     pub fn row_to_string(row: &Row) -> Result<String, Box<dyn std::error::Error>> {
         let value: String = row.get(0);
         Ok(value)
@@ -14,6 +14,7 @@ pub type C3p0Impl = SqlxMySqlC3p0Pool;
 
 //mod tests;
 mod tests_json;
+mod tests_lock;
 mod utils;
 
 pub type MaybeType = (
@@ -37,19 +38,25 @@ async fn init() -> MaybeType {
         .get_or_init(|| clients::Cli::default())
         .run(mysql_image);
 
-    let options = MySqlConnectOptions::new()
+    let pool = MySqlPool::connect_with(connect_options(&node))
+        .await
+        .unwrap();
+
+    let pool = SqlxMySqlC3p0Pool::new(pool);
+
+    (pool, node)
+}
+
+fn connect_options(
+    node: &Container<'static, clients::Cli, images::generic::GenericImage>,
+) -> MySqlConnectOptions {
+    MySqlConnectOptions::new()
         .username("mysql")
         .password("mysql")
         .database("mysql")
         .host("127.0.0.1")
         .port(node.get_host_port(3306).unwrap())
-        .ssl_mode(MySqlSslMode::Disabled);
-
-    let pool = MySqlPool::connect_with(options).await.unwrap();
-
-    let pool = SqlxMySqlC3p0Pool::new(pool);
-
-    (pool, node)
+        .ssl_mode(MySqlSslMode::Disabled)
 }
 
 pub async fn data(serial: bool) -> Data<'static, MaybeType> {
@@ -67,6 +74,16 @@ pub mod db_specific {
         utils::DbType::MySql
     }
 
+    /// Returns a new pool of the database of `data` with at most `max_size` connections
+    pub async fn new_pool(data: &MaybeType, max_size: u32) -> C3p0Impl {
+        let pool = MySqlPoolOptions::new()
+            .max_connections(max_size)
+            .connect_with(connect_options(&data.1))
+            .await
+            .unwrap();
+        SqlxMySqlC3p0Pool::new(pool)
+    }
+
     pub fn row_to_string(row: &MySqlRow) -> Result<String, Box<dyn std::error::Error>> {
         let value: String = row.get(0);
         Ok(value)
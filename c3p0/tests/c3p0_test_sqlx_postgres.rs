@@ -13,7 +13,9 @@ use futures::FutureExt;
 pub type C3p0Impl = SqlxPgC3p0Pool;
 
 //mod tests;
+mod tests_change_feed;
 mod tests_json;
+mod tests_lock;
 mod utils;
 
 pub type MaybeType = (
@@ -27,20 +29,24 @@ async fn init() -> MaybeType {
         .get_or_init(|| clients::Cli::default())
         .run(images::postgres::Postgres::default());
 
-    let options = PgConnectOptions::new()
-        .username("postgres")
-        .password("postgres")
-        .database("postgres")
-        .host("127.0.0.1")
-        .port(node.get_host_port(5432).unwrap());
-
-    let pool = PgPool::connect_with(options).await.unwrap();
+    let pool = PgPool::connect_with(connect_options(&node)).await.unwrap();
 
     let pool = SqlxPgC3p0Pool::new(pool);
 
     (pool, node)
 }
 
+fn connect_options(
+    node: &Container<'static, clients::Cli, images::postgres::Postgres>,
+) -> PgConnectOptions {
+    PgConnectOptions::new()
+        .username("postgres")
+        .password("postgres")
+        .database("postgres")
+        .host("127.0.0.1")
+        .port(node.get_host_port(5432).unwrap())
+}
+
 pub async fn data(serial: bool) -> Data<'static, MaybeType> {
     static DATA: OnceCell<MaybeSingleAsync<MaybeType>> = OnceCell::new();
     DATA.get_or_init(|| MaybeSingleAsync::new(|| init().boxed()))
@@ -56,6 +62,26 @@ pub mod db_specific {
         utils::DbType::Pg
     }
 
+    /// Returns a new pool of the database of `data` with at most `max_size` connections
+    pub async fn new_pool(data: &MaybeType, max_size: u32) -> C3p0Impl {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_size)
+            .connect_with(connect_options(&data.1))
+            .await
+            .unwrap();
+        SqlxPgC3p0Pool::new(pool)
+    }
+
+    /// Terminates the connections listening to the channel of a change feed
+    pub async fn terminate_listeners(pool: &C3p0Impl, channel: &str) -> Result<(), C3p0Error> {
+        let sql = format!(
+            r#"SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE query = 'LISTEN "{}"'"#,
+            channel
+        );
+        pool.connection(|mut conn| async move { conn.batch_execute(&sql).await })
+            .await
+    }
+
     pub fn row_to_string(row: &PgRow) -> Result<String, Box<dyn std::error::Error>> {
         let value: String = row.get(0);
         Ok(value)
@@ -52,3 +52,65 @@ fn should_upgrade_structs_on_load() -> Result<(), C3p0Error> {
         .await
     })
 }
+
+#[test]
+fn versioned_codec_should_upgrade_tagged_and_untagged_rows() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("USER_TABLE_{}", rand_string(8));
+
+            let untagged_jpo =
+                C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<UserVersion1>();
+
+            let jpo_v1 = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .build_with_codec(UserVersionCoded1 {});
+
+            let versioned_jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec(
+                VersionedCodec::<UserVersion1>::new("V1").with_version(
+                    "V2",
+                    |user: UserVersion1| UserVersion2 {
+                        username: user.username,
+                        email: user.email,
+                        age: 18,
+                    },
+                ),
+            );
+
+            assert!(versioned_jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let user_v1 = UserVersion1 {
+                username: "user_v1_name".to_owned(),
+                email: "user_v1_email@test.com".to_owned(),
+            };
+
+            let untagged_user = untagged_jpo.save(conn, user_v1.clone().into()).await?;
+            let tagged_user = jpo_v1.save(conn, user_v1.clone().into()).await?;
+
+            for user in &[untagged_user.id, tagged_user.id] {
+                let user_v2 = versioned_jpo.fetch_one_by_id(conn, user).await?;
+                assert_eq!(user_v1.username, user_v2.data.username);
+                assert_eq!(user_v1.email, user_v2.data.email);
+                assert_eq!(18, user_v2.data.age);
+            }
+
+            let mut user_v2 = versioned_jpo.fetch_one_by_id(conn, &tagged_user.id).await?;
+            user_v2.data.age = 40;
+            let user_v2 = versioned_jpo.update(conn, user_v2).await?;
+            assert_eq!(
+                user_v2,
+                C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                    .build_with_codec(UserVersionCoded2 {})
+                    .fetch_one_by_id(conn, &user_v2.id)
+                    .await?
+            );
+
+            assert!(versioned_jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,222 @@
+use crate::utils::*;
+use crate::*;
+use futures::StreamExt;
+use std::time::Duration;
+
+async fn next_change(changes: &mut ChangeStream) -> Result<ChangeEvent, C3p0Error> {
+    tokio::time::timeout(Duration::from_secs(5), changes.next())
+        .await
+        .expect("no change received")
+        .expect("the change stream ended")
+}
+
+#[test]
+fn should_publish_the_committed_changes() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8)).to_lowercase();
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .with_change_feed(true)
+            .build::<TestData>();
+
+        pool.transaction(|mut conn| async move { jpo.create_table_if_not_exists(&mut conn).await })
+            .await?;
+
+        let mut changes = pool.subscribe(&table_name).await?;
+
+        let model = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                let model = jpo
+                    .save(
+                        conn,
+                        TestData {
+                            first_name: "my_first_name".to_owned(),
+                            last_name: "my_last_name".to_owned(),
+                        }
+                        .into(),
+                    )
+                    .await?;
+                let model = jpo.update(conn, model).await?;
+                jpo.delete(conn, model).await
+            })
+            .await?;
+
+        let rollback: Result<(), C3p0Error> = pool
+            .transaction(|mut conn| async move {
+                jpo.save(
+                    &mut conn,
+                    TestData {
+                        first_name: "rolled_back".to_owned(),
+                        last_name: "rolled_back".to_owned(),
+                    }
+                    .into(),
+                )
+                .await?;
+                Err(C3p0Error::InternalError {
+                    cause: "rollback on purpose".to_owned(),
+                })
+            })
+            .await;
+        assert!(rollback.is_err());
+
+        let expected = vec![
+            (ChangeOperation::Insert, 0),
+            (ChangeOperation::Update, 1),
+            (ChangeOperation::Delete, 1),
+        ];
+        for (operation, version) in expected {
+            let change = next_change(&mut changes).await?;
+            assert_eq!(table_name, change.table_name);
+            assert_eq!(operation, change.operation);
+            assert_eq!(model.id, change.id);
+            assert_eq!(version, change.version);
+        }
+
+        // the rolled back insert is never published
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), changes.next())
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn should_not_publish_the_changes_of_other_tables() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8)).to_lowercase();
+        let other_table_name = format!("TEST_TABLE_{}", rand_string(8)).to_lowercase();
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .with_change_feed(true)
+            .build::<TestData>();
+        let other_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&other_table_name)
+            .with_change_feed(true)
+            .build::<TestData>();
+        let silent_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<TestData>();
+
+        let mut changes = pool.subscribe(&table_name).await?;
+
+        let saved = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                jpo.create_table_if_not_exists(conn).await?;
+                other_jpo.create_table_if_not_exists(conn).await?;
+                let data = TestData {
+                    first_name: "my_first_name".to_owned(),
+                    last_name: "my_last_name".to_owned(),
+                };
+                other_jpo.save(conn, data.clone().into()).await?;
+                jpo.save(conn, data.into()).await
+            })
+            .await?;
+
+        let change = next_change(&mut changes).await?;
+        assert_eq!(table_name, change.table_name);
+        assert_eq!(saved.id, change.id);
+
+        if db_specific::db_type() == DbType::InMemory {
+            // on Postgres the trigger notifies the changes made by any C3p0Json of the table,
+            // while in memory only the C3p0Json built with a change feed publishes them
+            pool.transaction(|mut conn| async move {
+                silent_jpo.delete_all(&mut conn).await?;
+                Ok::<_, C3p0Error>(())
+            })
+            .await?;
+            assert!(
+                tokio::time::timeout(Duration::from_millis(200), changes.next())
+                    .await
+                    .is_err()
+            );
+        }
+
+        Ok(())
+    })
+}
+
+#[test]
+fn should_publish_the_changes_of_the_tables_with_long_names() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        // the channel name exceeds the 63 bytes of a Postgres identifier
+        let table_name = format!(
+            "test_table_with_a_long_name_for_the_change_feed_{}",
+            rand_string(8)
+        )
+        .to_lowercase();
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .with_change_feed(true)
+            .build::<TestData>();
+
+        // the trigger is created only once
+        for _ in 0..2 {
+            pool.transaction(
+                |mut conn| async move { jpo.create_table_if_not_exists(&mut conn).await },
+            )
+            .await?;
+        }
+
+        let mut changes = pool.subscribe(&table_name).await?;
+
+        let saved = pool
+            .transaction(|mut conn| async move {
+                jpo.save(
+                    &mut conn,
+                    TestData {
+                        first_name: "my_first_name".to_owned(),
+                        last_name: "my_last_name".to_owned(),
+                    }
+                    .into(),
+                )
+                .await
+            })
+            .await?;
+
+        let change = next_change(&mut changes).await?;
+        assert_eq!(table_name, change.table_name);
+        assert_eq!(ChangeOperation::Insert, change.operation);
+        assert_eq!(saved.id, change.id);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), changes.next())
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn should_end_the_stream_with_an_error_when_the_listener_connection_is_lost(
+) -> Result<(), C3p0Error> {
+    test(async {
+        if db_specific::db_type() == DbType::InMemory {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8)).to_lowercase();
+        let mut changes = pool.subscribe(&table_name).await?;
+
+        db_specific::terminate_listeners(pool, &change_feed_channel(&table_name)).await?;
+
+        assert!(next_change(&mut changes).await.is_err());
+        assert!(tokio::time::timeout(Duration::from_secs(5), changes.next())
+            .await
+            .expect("the change stream did not end")
+            .is_none());
+
+        Ok(())
+    })
+}
@@ -0,0 +1,205 @@
+#![cfg(any(feature = "msgpack", feature = "cbor"))]
+
+use crate::utils::*;
+use crate::*;
+use serde_json::json;
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_binary_crud() -> Result<(), C3p0Error> {
+    test(async { binary_crud(MsgPackCodec {}).await })
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_binary_crud() -> Result<(), C3p0Error> {
+    test(async { binary_crud(CborCodec {}).await })
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn binary_should_update_with_optimistic_lock() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(table_name)
+                .build_binary_with_codec(MsgPackCodec {});
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let saved_model = jpo
+                .save(
+                    conn,
+                    NewModel::new(TestData {
+                        first_name: "my_first_name".to_owned(),
+                        last_name: "my_last_name".to_owned(),
+                    }),
+                )
+                .await?;
+
+            let mut updated_model = saved_model.clone();
+            updated_model.data.first_name = "second_first_name".to_owned();
+            let updated_model = jpo.update(conn, updated_model).await?;
+
+            assert_eq!(saved_model.version + 1, updated_model.version);
+            assert_eq!(
+                "second_first_name",
+                jpo.fetch_one_by_id(conn, &saved_model)
+                    .await?
+                    .data
+                    .first_name
+            );
+
+            match jpo.update(conn, saved_model.clone()).await {
+                Err(C3p0Error::OptimisticLockError { .. }) => {}
+                _ => panic!("Expected an OptimisticLockError"),
+            }
+
+            match jpo.delete(conn, saved_model).await {
+                Err(C3p0Error::OptimisticLockError { .. }) => {}
+                _ => panic!("Expected an OptimisticLockError"),
+            }
+
+            assert!(jpo.delete(conn, updated_model).await.is_ok());
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+async fn binary_crud<CODEC: BinaryCodec<TestData> + 'static>(
+    codec: CODEC,
+) -> Result<(), C3p0Error> {
+    let data = data(false).await;
+    let pool = &data.0;
+
+    pool.transaction(|mut conn| async move {
+        let conn = &mut conn;
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = C3p0JsonBuilder::<C3p0Impl>::new(table_name).build_binary_with_codec(codec);
+
+        assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+        assert_eq!(0, jpo.count_all(conn).await?);
+
+        let model = NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        let saved_model_0 = jpo.save(conn, model.clone()).await?;
+        let saved_model_1 = jpo.save(conn, model.clone()).await?;
+
+        let found_model = jpo.fetch_one_optional_by_id(conn, &saved_model_0).await?;
+        assert_eq!(Some(saved_model_0.clone()), found_model);
+        assert!(jpo.exists_by_id(conn, &saved_model_1).await?);
+
+        let models = jpo.fetch_all(conn).await?;
+        assert_eq!(vec![saved_model_0.clone(), saved_model_1.clone()], models);
+
+        assert_eq!(1, jpo.delete_by_id(conn, &saved_model_0).await?);
+        assert!(!jpo.exists_by_id(conn, &saved_model_0).await?);
+        assert_eq!(1, jpo.count_all(conn).await?);
+
+        assert_eq!(1, jpo.delete_all(conn).await?);
+        assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+        Ok(())
+    })
+    .await
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn binary_should_lock_only_the_selected_entries_by_max_field() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = C3p0JsonBuilder::<C3p0Impl>::new(table_name)
+            .build_binary_with_codec::<serde_json::Value, _>(MsgPackCodec {});
+
+        let jpo_ref = &jpo;
+        let saved = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                jpo_ref.create_table_if_not_exists(conn).await?;
+                let mut saved = vec![];
+                for due in &[30, 5, 10] {
+                    saved.push(
+                        jpo_ref
+                            .save(conn, NewModel::new(json!({ "due": due })))
+                            .await?,
+                    );
+                }
+                Ok::<_, C3p0Error>(saved)
+            })
+            .await?;
+        let saved = &saved;
+
+        let polled = pool
+            .transaction(|mut conn| async move {
+                let polled = jpo_ref
+                    .fetch_all_by_max_field_for_update(
+                        &mut conn,
+                        "due",
+                        10,
+                        None,
+                        1,
+                        &ForUpdate::SkipLocked,
+                    )
+                    .await?;
+
+                // MySQL 5.7 and the in-memory pool do not support a concurrent poller
+                if db_specific::db_type() == DbType::Pg {
+                    // The entry that is not selected is not locked
+                    let concurrent = pool
+                        .transaction(|mut conn| async move {
+                            jpo_ref
+                                .fetch_all_by_max_field_for_update(
+                                    &mut conn,
+                                    "due",
+                                    10,
+                                    None,
+                                    1,
+                                    &ForUpdate::SkipLocked,
+                                )
+                                .await
+                        })
+                        .await?;
+                    assert_eq!(vec![saved[2].clone()], concurrent);
+                }
+                Ok::<_, C3p0Error>(polled)
+            })
+            .await?;
+        assert_eq!(vec![saved[1].clone()], polled);
+
+        let all = pool
+            .transaction(|mut conn| async move {
+                jpo_ref
+                    .fetch_all_by_max_field_for_update(
+                        &mut conn,
+                        "due",
+                        30,
+                        Some(("due", &OrderBy::Desc)),
+                        5,
+                        &ForUpdate::Default,
+                    )
+                    .await
+            })
+            .await?;
+        assert_eq!(
+            vec![saved[0].clone(), saved[2].clone(), saved[1].clone()],
+            all
+        );
+
+        pool.transaction(
+            |mut conn| async move { jpo_ref.drop_table_if_exists(&mut conn, true).await },
+        )
+        .await
+    })
+}
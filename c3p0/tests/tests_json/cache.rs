@@ -0,0 +1,151 @@
+use crate::utils::*;
+use crate::*;
+
+fn new_test_data(first_name: &str) -> NewModel<TestData> {
+    NewModel::new(TestData {
+        first_name: first_name.to_owned(),
+        last_name: "".to_owned(),
+    })
+}
+
+#[test]
+fn should_cache_the_entries_fetched_by_id() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<TestData>();
+        let cached = &CachingC3p0Json::new(jpo.clone());
+
+        let model = &pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                cached.create_table_if_not_exists(conn).await?;
+                cached.save(conn, new_test_data("first")).await
+            })
+            .await?;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let model = model.clone();
+            assert_eq!(model, cached.fetch_one_by_id(conn, &model).await?);
+
+            // a write made elsewhere is not seen through the cache
+            let mut updated = model.clone();
+            updated.data.first_name = "second".to_owned();
+            let updated = jpo.update(conn, updated).await?;
+            assert_eq!(model, cached.fetch_one_by_id(conn, &model).await?);
+            assert!(cached.exists_by_id(conn, &model).await?);
+
+            // the update of the stale entry fails and invalidates it
+            assert!(matches!(
+                cached.update(conn, model.clone()).await,
+                Err(C3p0Error::OptimisticLockError { .. })
+            ));
+            assert_eq!(
+                Some(updated.clone()),
+                cached.fetch_one_optional_by_id(conn, &model).await?
+            );
+
+            // the updates and the deletions made through the cache invalidate it
+            let mut model = updated;
+            model.data.first_name = "third".to_owned();
+            let model = cached.update(conn, model).await?;
+            assert_eq!(model, cached.fetch_one_by_id(conn, &model).await?);
+
+            assert_eq!(1, cached.delete_by_id(conn, &model).await?);
+            assert!(cached
+                .fetch_one_optional_by_id(conn, &model)
+                .await?
+                .is_none());
+            assert!(!cached.exists_by_id(conn, &model).await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_invalidate_a_stale_entry_fetched_for_update() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<TestData>();
+        let cached = &CachingC3p0Json::new(jpo.clone());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            cached.create_table_if_not_exists(conn).await?;
+
+            let model = cached
+                .save(
+                    conn,
+                    NewModel::new(TestData {
+                        first_name: "first".to_owned(),
+                        last_name: "".to_owned(),
+                    }),
+                )
+                .await?;
+            cached.fetch_one_by_id(conn, &model).await?;
+            let updated = jpo.update(conn, model.clone()).await?;
+
+            assert_eq!(
+                updated,
+                cached
+                    .fetch_one_by_id_for_update(conn, &model, &ForUpdate::Default)
+                    .await?
+            );
+            assert_eq!(updated, cached.fetch_one_by_id(conn, &model).await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_not_cache_the_entries_written_by_a_rolled_back_transaction() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<TestData>();
+        let cached = &CachingC3p0Json::new(jpo.clone());
+
+        let model = &pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                cached.create_table_if_not_exists(conn).await?;
+                cached.save(conn, new_test_data("first")).await
+            })
+            .await?;
+
+        let result: Result<(), C3p0Error> = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                let mut updated = model.clone();
+                updated.data.first_name = "second".to_owned();
+                let updated = cached.update(conn, updated).await?;
+                // the transaction reads its uncommitted version
+                assert_eq!(updated, cached.fetch_one_by_id(conn, model).await?);
+
+                let saved = cached.save(conn, new_test_data("rolled back")).await?;
+                assert_eq!(saved, cached.fetch_one_by_id(conn, &saved).await?);
+                Err(C3p0Error::ResultNotFoundError)
+            })
+            .await;
+        assert!(result.is_err());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(model, &cached.fetch_one_by_id(conn, model).await?);
+            assert_eq!(model, &jpo.fetch_one_by_id(conn, model).await?);
+            assert_eq!(1, cached.count_all(conn).await?);
+            cached.drop_table_if_exists(conn, true).await
+        })
+        .await
+    })
+}
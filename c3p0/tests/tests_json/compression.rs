@@ -0,0 +1,189 @@
+#![cfg(any(feature = "zstd", feature = "lz4"))]
+
+use crate::utils::*;
+use crate::*;
+
+fn compression() -> Compression {
+    #[cfg(feature = "zstd")]
+    return Compression::zstd(256);
+    #[cfg(not(feature = "zstd"))]
+    return Compression::lz4(256);
+}
+
+fn test_data(len: usize) -> TestData {
+    TestData {
+        first_name: "my_first_name".repeat(len),
+        last_name: "my_last_name".repeat(len),
+    }
+}
+
+#[test]
+fn should_read_compressed_and_uncompressed_documents() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<TestData>();
+            let compressed_jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .with_compression(compression())
+                .build::<TestData>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let small = jpo.save(conn, test_data(1).into()).await?;
+            let big = jpo.save(conn, test_data(100).into()).await?;
+            let compressed_small = compressed_jpo.save(conn, test_data(1).into()).await?;
+            let compressed_big = compressed_jpo.save(conn, test_data(100).into()).await?;
+
+            for model in &[small, big, compressed_small, compressed_big] {
+                assert_eq!(model, &jpo.fetch_one_by_id(conn, model).await?);
+                assert_eq!(model, &compressed_jpo.fetch_one_by_id(conn, model).await?);
+            }
+
+            assert_eq!(4, jpo.fetch_all(conn).await?.len());
+            assert_eq!(
+                jpo.fetch_all(conn).await?,
+                compressed_jpo.fetch_all(conn).await?
+            );
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_update_compressed_documents() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .with_compression(compression())
+                .build::<TestData>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let mut model = jpo.save(conn, test_data(1).into()).await?;
+            model.data = test_data(200);
+            let model = jpo.update(conn, model).await?;
+
+            assert_eq!(model, jpo.fetch_one_by_id(conn, &model).await?);
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_not_query_the_fields_of_compressed_documents() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .with_compression(compression())
+                .build::<TestData>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+            jpo.save(conn, test_data(100).into()).await?;
+
+            assert!(matches!(
+                jpo.create_index_if_not_exists(conn, &C3p0Index::new("first_name", false))
+                    .await,
+                Err(C3p0Error::CompressionError { .. })
+            ));
+            let first_name = serde_json::json!(test_data(100).first_name);
+            assert!(matches!(
+                jpo.fetch_all_by_field(conn, "first_name", &first_name)
+                    .await,
+                Err(C3p0Error::CompressionError { .. })
+            ));
+            assert!(matches!(
+                jpo.fetch_all_by_field_prefix(conn, "first_name", "my")
+                    .await,
+                Err(C3p0Error::CompressionError { .. })
+            ));
+            assert!(matches!(
+                jpo.fetch_all_by_max_field_for_update(
+                    conn,
+                    "first_name",
+                    0,
+                    None,
+                    1,
+                    &ForUpdate::Default
+                )
+                .await,
+                Err(C3p0Error::CompressionError { .. })
+            ));
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_rewrite_the_uncompressed_documents_after_enabling_the_compression(
+) -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<TestData>();
+        let compressed_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .with_compression(compression())
+            .build::<TestData>();
+
+        let models = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+                let mut models = vec![];
+                for len in &[1, 100, 1, 200] {
+                    models.push(jpo.save(conn, test_data(*len).into()).await?);
+                }
+                Ok::<_, C3p0Error>(models)
+            })
+            .await?;
+
+        let progress = rewrite_all(pool, compressed_jpo, 3).await?;
+        assert_eq!(4, progress.processed);
+        assert_eq!(2, progress.stale);
+        assert_eq!(2, progress.rewritten);
+
+        assert_eq!(0, count_stale(pool, compressed_jpo, 3).await?);
+        assert_eq!(2, count_stale(pool, jpo, 3).await?);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            for model in models {
+                let rewritten = compressed_jpo.fetch_one_by_id(conn, &model).await?;
+                assert_eq!(model.data, rewritten.data);
+                let expected_version = if model.data.first_name.len() > 256 {
+                    model.version + 1
+                } else {
+                    model.version
+                };
+                assert_eq!(expected_version, rewritten.version);
+            }
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "encryption")]
+
+use crate::utils::*;
+use crate::*;
+use serde_json::Value;
+
+fn codec(key_provider: StaticKeyProvider) -> EncryptingCodec<DefaultJsonCodec, StaticKeyProvider> {
+    EncryptingCodec::new(DefaultJsonCodec {}, key_provider).with_encrypted_path("/last_name")
+}
+
+fn test_data() -> TestData {
+    TestData {
+        first_name: "my_first_name".to_owned(),
+        last_name: "my_last_name".to_owned(),
+    }
+}
+
+#[test]
+fn should_encrypt_the_configured_fields() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo =
+                C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec::<TestData, _>(
+                    codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32]))),
+                );
+            let plain_jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<Value>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let model = jpo.save(conn, test_data().into()).await?;
+            assert_eq!(model, jpo.fetch_one_by_id(conn, &model).await?);
+
+            let stored = plain_jpo.fetch_one_by_id(conn, &model.id).await?;
+            assert_eq!("my_first_name", stored.data["first_name"]);
+            assert!(stored.data["last_name"].is_object());
+            assert!(!stored.data.to_string().contains("my_last_name"));
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_reencrypt_all_after_key_rotation() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let old_jpo =
+            &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec::<TestData, _>(codec(
+                StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])),
+            ));
+        let rotated_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .build_with_codec::<TestData, _>(codec(
+                StaticKeyProvider::new("k2", EncryptionKey::new([2; 32]))
+                    .with_key("k1", EncryptionKey::new([1; 32])),
+            ));
+        let new_jpo =
+            &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec::<TestData, _>(codec(
+                StaticKeyProvider::new("k2", EncryptionKey::new([2; 32])),
+            ));
+
+        let old_ids = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                assert!(old_jpo.create_table_if_not_exists(conn).await.is_ok());
+                let mut old_ids = vec![];
+                for _ in 0..5 {
+                    old_ids.push(old_jpo.save(conn, test_data().into()).await?.id);
+                }
+                for _ in 0..2 {
+                    rotated_jpo.save(conn, test_data().into()).await?;
+                }
+                assert!(new_jpo.fetch_all(conn).await.is_err());
+                Ok::<_, C3p0Error>(old_ids)
+            })
+            .await?;
+        let old_ids = &old_ids;
+
+        // only the entries encrypted with the old key are rewritten
+        let progress = reencrypt_all(pool, rotated_jpo, 2).await?;
+        assert_eq!(5, progress.rewritten);
+        assert_eq!(0, progress.conflicts);
+        assert_eq!(0, reencrypt_all(pool, rotated_jpo, 2).await?.rewritten);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let models = new_jpo.fetch_all(conn).await?;
+            assert_eq!(7, models.len());
+            for model in models {
+                let expected_version = if old_ids.contains(&model.id) { 1 } else { 0 };
+                assert_eq!(expected_version, model.version);
+                assert_eq!(test_data(), model.data);
+            }
+            assert!(new_jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_rewrite_only_the_values_not_encrypted_with_the_current_key() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let old_jpo =
+            &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec::<TestData, _>(codec(
+                StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])),
+            ));
+        let new_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+            .build_with_codec::<TestData, _>(codec(
+                StaticKeyProvider::new("k2", EncryptionKey::new([2; 32]))
+                    .with_key("k1", EncryptionKey::new([1; 32])),
+            ));
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            assert!(old_jpo.create_table_if_not_exists(conn).await.is_ok());
+            old_jpo.save(conn, test_data().into()).await?;
+            old_jpo.save(conn, test_data().into()).await?;
+            new_jpo.save(conn, test_data().into()).await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        // the value encrypted with the current key is current even if
+        // encrypting it again gives a different value
+        assert_eq!(2, count_stale(pool, new_jpo, 10).await?);
+
+        let progress = rewrite_all(pool, new_jpo, 10).await?;
+        assert_eq!(3, progress.processed);
+        assert_eq!(2, progress.rewritten);
+        assert_eq!(0, count_stale(pool, new_jpo, 10).await?);
+
+        pool.transaction(|mut conn| async move {
+            assert!(new_jpo.drop_table_if_exists(&mut conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,211 @@
+use crate::utils::*;
+use crate::*;
+
+#[test]
+fn should_append_and_read_the_streams() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("EVENT_TABLE_{}", rand_string(8));
+        let store = &C3p0EventStore::new(
+            C3p0JsonBuilder::<C3p0Impl>::new(table_name.clone()).build::<StoredEvent<TestData>>(),
+            C3p0JsonBuilder::<C3p0Impl>::new(format!("{}_STREAMS", table_name))
+                .build::<EventStream>(),
+        );
+
+        let event = |first_name: &str| TestData {
+            first_name: first_name.to_owned(),
+            last_name: "".to_owned(),
+        };
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            store.create_tables_if_not_exists(conn).await?;
+            assert_eq!(0, store.stream_version(conn, "order-1").await?);
+
+            let events = store
+                .append(
+                    conn,
+                    "order-1",
+                    ExpectedVersion::Exact(0),
+                    vec![event("created"), event("paid")],
+                )
+                .await?;
+            assert_eq!(
+                vec![1, 2],
+                events
+                    .iter()
+                    .map(|event| event.data.stream_version)
+                    .collect::<Vec<_>>()
+            );
+            store
+                .append(
+                    conn,
+                    "order-2",
+                    ExpectedVersion::Any,
+                    vec![event("created")],
+                )
+                .await?;
+            store
+                .append(
+                    conn,
+                    "order-1",
+                    ExpectedVersion::Exact(2),
+                    vec![event("shipped")],
+                )
+                .await?;
+            assert_eq!(3, store.stream_version(conn, "order-1").await?);
+
+            assert!(matches!(
+                store
+                    .append(
+                        conn,
+                        "order-1",
+                        ExpectedVersion::Exact(2),
+                        vec![event("lost")]
+                    )
+                    .await,
+                Err(C3p0Error::OptimisticLockError { .. })
+            ));
+            assert!(matches!(
+                store
+                    .append(
+                        conn,
+                        "order-2",
+                        ExpectedVersion::Exact(0),
+                        vec![event("lost")]
+                    )
+                    .await,
+                Err(C3p0Error::OptimisticLockError { .. })
+            ));
+
+            let names = |events: Vec<Model<StoredEvent<TestData>>>| {
+                events
+                    .into_iter()
+                    .map(|event| event.data.event.first_name)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                vec!["created", "paid", "shipped"],
+                names(store.read_stream(conn, "order-1", 0).await?)
+            );
+            assert_eq!(
+                vec!["paid", "shipped"],
+                names(store.read_stream(conn, "order-1", 2).await?)
+            );
+            assert!(store.read_stream(conn, "order-3", 0).await?.is_empty());
+
+            let first_page = store.read_all(conn, IdType::MIN, 3).await?;
+            assert_eq!(
+                vec!["created", "paid", "created"],
+                names(first_page.clone())
+            );
+            let last_id = first_page.last().unwrap().id;
+            assert_eq!(
+                vec!["shipped"],
+                names(store.read_all(conn, last_id, 3).await?)
+            );
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_save_and_load_the_snapshots() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("SNAPSHOT_TABLE_{}", rand_string(8));
+        let snapshots = &C3p0SnapshotStore::new(
+            C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<Snapshot<u64>>(),
+        );
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            snapshots.create_table_if_not_exists(conn).await?;
+            assert!(snapshots.load(conn, "order-1").await?.is_none());
+
+            snapshots.save(conn, "order-1", 10, 100).await?;
+            snapshots.save(conn, "order-1", 20, 200).await?;
+            // an older snapshot does not replace a newer one
+            let snapshot = snapshots.save(conn, "order-1", 15, 150).await?;
+            assert_eq!(20, snapshot.data.stream_version);
+
+            let snapshot = snapshots.load(conn, "order-1").await?.unwrap();
+            assert_eq!(20, snapshot.data.stream_version);
+            assert_eq!(200, snapshot.data.state);
+            assert_eq!(1, snapshots.json().count_all(conn).await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_not_serialize_the_appends_to_different_streams() -> Result<(), C3p0Error> {
+    test(async {
+        // The in-memory pool has a single connection
+        if db_specific::db_type() == DbType::InMemory {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("EVENT_TABLE_{}", rand_string(8));
+        let store = &C3p0EventStore::new(
+            C3p0JsonBuilder::<C3p0Impl>::new(table_name.clone()).build::<StoredEvent<TestData>>(),
+            C3p0JsonBuilder::<C3p0Impl>::new(format!("{}_STREAMS", table_name))
+                .build::<EventStream>(),
+        );
+        let event = || TestData {
+            first_name: "created".to_owned(),
+            last_name: "".to_owned(),
+        };
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            store.create_tables_if_not_exists(conn).await
+        })
+        .await?;
+
+        pool.transaction(|mut conn| async move {
+            store
+                .append(&mut conn, "order-1", ExpectedVersion::Any, vec![event()])
+                .await?;
+
+            // The lock of the first stream does not block the appends to the second one
+            let append = pool.transaction(|mut conn| async move {
+                store
+                    .append(
+                        &mut conn,
+                        "order-2",
+                        ExpectedVersion::Exact(0),
+                        vec![event()],
+                    )
+                    .await
+            });
+            assert!(
+                tokio::time::timeout(std::time::Duration::from_secs(5), append)
+                    .await
+                    .expect("the append to the second stream should not wait")
+                    .is_ok()
+            );
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(1, store.stream_version(conn, "order-1").await?);
+            assert_eq!(1, store.stream_version(conn, "order-2").await?);
+            assert_eq!(2, store.read_all(conn, IdType::MIN, 10).await?.len());
+            store.json().drop_table_if_exists(conn, true).await?;
+            store.streams_json().drop_table_if_exists(conn, true).await
+        })
+        .await
+    })
+}
@@ -114,6 +114,44 @@ fn basic_crud() -> Result<(), C3p0Error> {
     })
 }
 
+#[test]
+fn should_reject_the_duplicates_of_a_unique_index() -> Result<(), C3p0Error> {
+    test(async {
+        // the in-memory indexes are not enforced
+        if db_specific::db_type() == DbType::InMemory {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+            let index = C3p0Index::new("first_name", true);
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+            assert!(jpo.create_index_if_not_exists(conn, &index).await.is_ok());
+            assert!(jpo.create_index_if_not_exists(conn, &index).await.is_ok());
+
+            let model = |first_name: &str| {
+                NewModel::new(TestData {
+                    first_name: first_name.to_owned(),
+                    last_name: "my_last_name".to_owned(),
+                })
+            };
+            jpo.save(conn, model("first")).await?;
+            jpo.save(conn, model("second")).await?;
+            assert_eq!(2, jpo.count_all(conn).await?);
+
+            assert!(jpo.save(conn, model("first")).await.is_err());
+            Ok(())
+        })
+        .await
+    })
+}
+
 #[test]
 fn should_fetch_all() -> Result<(), C3p0Error> {
     test(async {
@@ -149,6 +187,53 @@ fn should_fetch_all() -> Result<(), C3p0Error> {
     })
 }
 
+#[test]
+fn should_fetch_all_after_id() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let model = NewModel::new(TestData {
+                first_name: "my_first_name".to_owned(),
+                last_name: "my_last_name".to_owned(),
+            });
+
+            let saved_model_0 = jpo.save(conn, model.clone()).await.unwrap();
+            let saved_model_1 = jpo.save(conn, model.clone()).await.unwrap();
+            let saved_model_2 = jpo.save(conn, model.clone()).await.unwrap();
+
+            let models = jpo.fetch_all_after_id(conn, IdType::MIN, 2).await.unwrap();
+            assert_eq!(2, models.len());
+            assert_eq!(saved_model_0.id, models[0].id);
+            assert_eq!(saved_model_1.id, models[1].id);
+
+            let models = jpo
+                .fetch_all_after_id(conn, saved_model_1.id, 2)
+                .await
+                .unwrap();
+            assert_eq!(1, models.len());
+            assert_eq!(saved_model_2, models[0]);
+
+            let models = jpo
+                .fetch_all_after_id(conn, saved_model_2.id, 2)
+                .await
+                .unwrap();
+            assert!(models.is_empty());
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
 #[test]
 fn should_delete_all() -> Result<(), C3p0Error> {
     test(async {
@@ -501,3 +586,58 @@ fn json_should_perform_for_update_fetches() -> Result<(), C3p0Error> {
         Ok(())
     })
 }
+
+#[test]
+fn should_fail_the_nowait_fetches_of_the_locked_rows_with_a_db_error() -> Result<(), C3p0Error> {
+    test(async {
+        // The in-memory pool has a single connection and MySql 5.7 has no NOWAIT
+        if db_specific::db_type() == DbType::InMemory
+            || db_specific::db_type() == DbType::MySql
+            || db_specific::db_type() == DbType::TiDB
+        {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = &pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                jpo.create_table_if_not_exists(conn).await?;
+                jpo.save(
+                    conn,
+                    NewModel::new(TestData {
+                        first_name: "my_first_name".to_owned(),
+                        last_name: "my_last_name".to_owned(),
+                    }),
+                )
+                .await
+            })
+            .await?;
+
+        pool.transaction(|mut conn| async move {
+            jpo.fetch_one_optional_by_id_for_update(&mut conn, model, &ForUpdate::Default)
+                .await?;
+
+            let fetch = pool.transaction(|mut conn| async move {
+                jpo.fetch_one_optional_by_id_for_update(&mut conn, model, &ForUpdate::NoWait)
+                    .await
+            });
+            let result = tokio::time::timeout(std::time::Duration::from_secs(5), fetch)
+                .await
+                .expect("the NOWAIT fetch should not wait");
+            match result {
+                Err(C3p0Error::DbError { .. }) => {}
+                _ => panic!("Expected a DbError, found {:?}", result),
+            }
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        pool.transaction(|mut conn| async move { jpo.drop_table_if_exists(&mut conn, true).await })
+            .await
+    })
+}
@@ -0,0 +1,113 @@
+#![cfg(feature = "json_schema")]
+
+use crate::utils::*;
+use crate::*;
+use serde_json::{json, Value};
+
+fn schema() -> JsonSchema {
+    JsonSchema::new(json!({
+        "type": "object",
+        "required": ["first_name", "last_name"],
+        "properties": {
+            "first_name": {"type": "string", "minLength": 1},
+            "last_name": {"type": "string"}
+        }
+    }))
+    .unwrap()
+}
+
+#[test]
+fn should_validate_on_save_and_update() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .with_schema(schema())
+                .build::<TestData>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            let mut model = jpo
+                .save(
+                    conn,
+                    TestData {
+                        first_name: "my_first_name".to_owned(),
+                        last_name: "my_last_name".to_owned(),
+                    }
+                    .into(),
+                )
+                .await?;
+
+            let invalid = TestData {
+                first_name: "".to_owned(),
+                last_name: "my_last_name".to_owned(),
+            };
+
+            match jpo.save(conn, invalid.clone().into()).await {
+                Err(C3p0Error::ValidationError { paths, .. }) => {
+                    assert_eq!(vec!["/first_name".to_owned()], paths)
+                }
+                _ => panic!("Expected a ValidationError"),
+            }
+
+            model.data = invalid;
+            match jpo.update(conn, model.clone()).await {
+                Err(C3p0Error::ValidationError { paths, .. }) => {
+                    assert_eq!(vec!["/first_name".to_owned()], paths)
+                }
+                _ => panic!("Expected a ValidationError"),
+            }
+
+            let stored = jpo.fetch_all(conn).await?;
+            assert_eq!(1, stored.len());
+            assert_eq!(0, stored[0].version);
+            assert_eq!("my_first_name", stored[0].data.first_name);
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_add_the_schema_check_constraint() -> Result<(), C3p0Error> {
+    test(async {
+        if db_specific::db_type() != DbType::Pg {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("TEST_TABLE_{}", rand_string(8));
+            let jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name)
+                .with_schema(schema())
+                .with_schema_check_constraint(true)
+                .build::<TestData>();
+            let unchecked_jpo = C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<Value>();
+
+            assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+
+            assert!(unchecked_jpo
+                .save(
+                    conn,
+                    json!({"first_name": "name", "last_name": "name"}).into()
+                )
+                .await
+                .is_ok());
+            assert!(unchecked_jpo
+                .save(conn, json!({"first_name": "name"}).into())
+                .await
+                .is_err());
+            Ok(())
+        })
+        .await
+    })
+}
@@ -1,5 +1,8 @@
 use crate::utils::*;
 use crate::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[test]
 fn json_should_commit_transaction() {
@@ -126,3 +129,386 @@ fn transaction_should_return_internal_error() {
         }
     });
 }
+
+#[test]
+fn connection_should_commit_every_statement() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        let result: Result<(), C3p0Error> = c3p0
+            .connection(|mut conn| async move {
+                let conn = &mut conn;
+                assert!(jpo.create_table_if_not_exists(conn).await.is_ok());
+                let saved = jpo.save(conn, model.clone()).await?;
+                assert_eq!(saved, jpo.fetch_one_by_id(conn, &saved).await?);
+                jpo.save(conn, model.clone()).await?;
+                Err(C3p0Error::ResultNotFoundError)?
+            })
+            .await;
+
+        assert!(result.is_err());
+
+        c3p0.connection::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(2, jpo.count_all(conn).await?);
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+#[test]
+fn should_start_transactions_with_options() {
+    test(async {
+        // TiDB supports neither the serializable isolation nor the read-only transactions
+        if db_specific::db_type() == DbType::TiDB {
+            return;
+        }
+
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = &NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        c3p0.transaction_with_options::<_, C3p0Error, _, _>(
+            &TxOptions::new().with_isolation(IsolationLevel::Serializable),
+            |mut conn| async move {
+                let conn = &mut conn;
+                jpo.create_table_if_not_exists(conn).await?;
+                jpo.save(conn, model.clone()).await?;
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+
+        let read_only = &TxOptions::new()
+            .with_isolation(IsolationLevel::Serializable)
+            .with_read_only(true)
+            .with_deferrable(true);
+
+        let result: Result<(), C3p0Error> = c3p0
+            .transaction_with_options(read_only, |mut conn| async move {
+                jpo.save(&mut conn, model.clone()).await?;
+                Ok(())
+            })
+            .await;
+        assert!(result.is_err());
+
+        c3p0.transaction_with_options::<_, C3p0Error, _, _>(read_only, |mut conn| async move {
+            assert_eq!(1, jpo.count_all(&mut conn).await?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+#[test]
+fn savepoint_should_rollback_only_its_changes() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = &NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            jpo.create_table_if_not_exists(conn).await?;
+            let saved = jpo.save(conn, model.clone()).await?;
+
+            let result: Result<(), C3p0Error> = conn
+                .savepoint(|mut conn| async move {
+                    jpo.save(&mut conn, model.clone()).await?;
+                    // The failed statement is rolled back with the savepoint
+                    jpo.save_with_id(&mut conn, saved).await?;
+                    Ok(())
+                })
+                .await;
+            assert!(result.is_err());
+            assert_eq!(1, jpo.count_all(conn).await?);
+
+            conn.savepoint::<_, C3p0Error, _, _>(|mut conn| async move {
+                jpo.save(&mut conn, model.clone()).await?;
+
+                let result: Result<(), C3p0Error> = conn
+                    .savepoint(|mut conn| async move {
+                        jpo.save(&mut conn, model.clone()).await?;
+                        Err(C3p0Error::ResultNotFoundError)
+                    })
+                    .await;
+                assert!(result.is_err());
+
+                assert_eq!(2, jpo.count_all(&mut conn).await?);
+                Ok(())
+            })
+            .await?;
+
+            assert_eq!(2, jpo.count_all(conn).await?);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        c3p0.connection::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(2, jpo.count_all(conn).await?);
+
+            let result: Result<(), C3p0Error> = conn.savepoint(|_| async { Ok(()) }).await;
+            assert!(result.is_err());
+
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+#[test]
+fn should_retry_the_transactions_failed_with_a_retryable_error() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = &NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            jpo.create_table_if_not_exists(&mut conn).await?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let retryable_error = || C3p0Error::DbError {
+            db: "test",
+            cause: "serialization failure".to_owned(),
+            code: Some("40001".to_owned()),
+        };
+        let policy = &RetryPolicy::new()
+            .with_max_attempts(3)
+            .with_initial_backoff(Duration::from_millis(1));
+
+        let attempts = &AtomicU32::new(0);
+        let result = c3p0
+            .transaction_with_retry::<_, C3p0Error, _, _>(policy, |mut conn| async move {
+                jpo.save(&mut conn, model.clone()).await?;
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(retryable_error())
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+
+        let attempts = &AtomicU32::new(0);
+        let result = c3p0
+            .transaction_with_retry::<(), C3p0Error, _, _>(policy, |mut conn| async move {
+                jpo.save(&mut conn, model.clone()).await?;
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(retryable_error())
+            })
+            .await;
+        assert!(result.unwrap_err().is_retryable());
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+
+        let attempts = &AtomicU32::new(0);
+        let result = c3p0
+            .transaction_with_retry::<(), C3p0Error, _, _>(policy, |_| async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(C3p0Error::ResultNotFoundError)
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(1, jpo.count_all(conn).await?);
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+#[test]
+fn should_run_the_hooks_when_the_transaction_ends() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let log = Arc::new(Mutex::new(vec![]));
+        let record = |name: &'static str| {
+            let log = log.clone();
+            move || async move { log.lock().unwrap().push(name) }
+        };
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            conn.on_commit(record("committed"));
+            conn.on_rollback(record("not rolled back"));
+
+            let result: Result<(), C3p0Error> = conn
+                .savepoint(|conn| async move {
+                    conn.on_commit(record("not committed in savepoint"));
+                    conn.on_rollback(record("rolled back savepoint"));
+                    Err(C3p0Error::ResultNotFoundError)
+                })
+                .await;
+            assert!(result.is_err());
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            vec!["rolled back savepoint", "committed"],
+            *log.lock().unwrap()
+        );
+
+        log.lock().unwrap().clear();
+        let result: Result<(), C3p0Error> = c3p0
+            .transaction(|conn| async move {
+                conn.on_commit(record("not committed"));
+                conn.on_rollback(record("rolled back"));
+                Err(C3p0Error::ResultNotFoundError)
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(vec!["rolled back"], *log.lock().unwrap());
+
+        log.lock().unwrap().clear();
+        let result: Result<(), C3p0Error> = c3p0
+            .connection(|conn| async move {
+                conn.on_commit(record("committed without transaction"));
+                conn.on_rollback(record("never rolled back"));
+                Err(C3p0Error::ResultNotFoundError)
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(vec!["committed without transaction"], *log.lock().unwrap());
+    });
+}
+
+#[test]
+fn should_roll_back_the_transactions_that_exceed_the_timeout() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = &NewModel::new(TestData {
+            first_name: "my_first_name".to_owned(),
+            last_name: "my_last_name".to_owned(),
+        });
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            jpo.create_table_if_not_exists(&mut conn).await?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let rolled_back = Arc::new(AtomicU32::new(0));
+        let options = &TxOptions::new().with_timeout(Duration::from_millis(100));
+        let result: Result<(), C3p0Error> = c3p0
+            .transaction_with_options(options, |mut conn| {
+                let rolled_back = rolled_back.clone();
+                async move {
+                    conn.on_rollback(move || async move {
+                        rolled_back.fetch_add(1, Ordering::SeqCst);
+                    });
+                    jpo.save(&mut conn, model.clone()).await?;
+                    tokio::time::delay_for(Duration::from_secs(10)).await;
+                    Ok(())
+                }
+            })
+            .await;
+        match result {
+            Err(C3p0Error::TimeoutError { .. }) => {}
+            _ => panic!("Expected a TimeoutError, found {:?}", result),
+        }
+        assert_eq!(1, rolled_back.load(Ordering::SeqCst));
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(0, jpo.count_all(conn).await?);
+            assert!(jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+        .unwrap();
+    });
+}
+
+#[test]
+fn should_roll_back_the_transaction_that_keeps_its_connection() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            jpo.create_table_if_not_exists(&mut conn).await
+        })
+        .await
+        .unwrap();
+
+        let result = c3p0
+            .transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+                let model = NewModel::new(TestData {
+                    first_name: "my_first_name".to_owned(),
+                    last_name: "my_last_name".to_owned(),
+                });
+                jpo.save(&mut conn, model).await?;
+                Ok(conn)
+            })
+            .await;
+        match result {
+            Err(C3p0Error::InternalError { .. }) => {}
+            _ => panic!("the transaction should fail"),
+        }
+
+        c3p0.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            assert_eq!(0, jpo.count_all(&mut conn).await?);
+            jpo.drop_table_if_exists(&mut conn, true).await
+        })
+        .await
+        .unwrap();
+    });
+}
@@ -0,0 +1,183 @@
+use crate::utils::*;
+use crate::*;
+use std::time::Duration;
+
+#[test]
+fn should_set_get_and_delete_the_values() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("KV_TABLE_{}", rand_string(8));
+        let kv = &C3p0Kv::new(C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<KvEntry<u32>>());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            kv.create_table_if_not_exists(conn).await?;
+
+            assert_eq!(None, kv.get_value(conn, "one").await?);
+
+            let entry = kv.set(conn, "one", 1).await?;
+            assert_eq!(0, entry.version);
+            assert_eq!(Some(1), kv.get_value(conn, "one").await?);
+
+            let entry = kv.set(conn, "one", 11).await?;
+            assert_eq!(1, entry.version);
+            assert_eq!(Some(11), kv.get_value(conn, "one").await?);
+            assert_eq!(1, kv.json().count_all(conn).await?);
+
+            assert!(kv.delete(conn, "one").await?);
+            assert!(!kv.delete(conn, "one").await?);
+            assert_eq!(None, kv.get(conn, "one").await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_compare_and_set_on_version() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("KV_TABLE_{}", rand_string(8));
+        let kv =
+            &C3p0Kv::new(C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<KvEntry<String>>());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            kv.create_table_if_not_exists(conn).await?;
+
+            let entry = kv
+                .compare_and_set(conn, "key", "first".to_owned(), None, None)
+                .await?;
+            assert!(matches!(
+                kv.compare_and_set(conn, "key", "second".to_owned(), None, None)
+                    .await,
+                Err(C3p0Error::OptimisticLockError { .. })
+            ));
+            assert!(matches!(
+                kv.compare_and_set(
+                    conn,
+                    "key",
+                    "second".to_owned(),
+                    Some(entry.version + 1),
+                    None
+                )
+                .await,
+                Err(C3p0Error::OptimisticLockError { .. })
+            ));
+
+            kv.compare_and_set(conn, "key", "second".to_owned(), Some(entry.version), None)
+                .await?;
+            assert_eq!(Some("second".to_owned()), kv.get_value(conn, "key").await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_list_the_values_by_key_prefix() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("KV_TABLE_{}", rand_string(8));
+        let kv = &C3p0Kv::new(C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<KvEntry<u32>>());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            kv.create_table_if_not_exists(conn).await?;
+            kv.set(conn, "flags.b", 2).await?;
+            kv.set(conn, "flags.a", 1).await?;
+            kv.set(conn, "flags_c", 3).await?;
+            kv.set(conn, "settings.a", 4).await?;
+            kv.set(conn, "100%", 5).await?;
+            kv.set(conn, "1000", 6).await?;
+
+            let keys = |entries: Vec<Model<KvEntry<u32>>>| {
+                entries
+                    .into_iter()
+                    .map(|entry| entry.data.key)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                vec!["flags.a", "flags.b"],
+                keys(kv.list_by_prefix(conn, "flags.").await?)
+            );
+            assert_eq!(vec!["100%"], keys(kv.list_by_prefix(conn, "100%").await?));
+            assert_eq!(6, kv.list_by_prefix(conn, "").await?.len());
+            assert!(kv.list_by_prefix(conn, "other").await?.is_empty());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_expire_the_values() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("KV_TABLE_{}", rand_string(8));
+        let kv = &C3p0Kv::new(C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<KvEntry<u32>>());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            kv.create_table_if_not_exists(conn).await?;
+            kv.set_with_ttl(conn, "expired", 1, Duration::from_millis(0))
+                .await?;
+            kv.set_with_ttl(conn, "alive", 2, Duration::from_secs(3600))
+                .await?;
+            kv.set(conn, "forever", 3).await?;
+
+            assert_eq!(None, kv.get_value(conn, "expired").await?);
+            assert_eq!(Some(2), kv.get_value(conn, "alive").await?);
+            assert_eq!(2, kv.list_by_prefix(conn, "").await?.len());
+
+            // an expired key can be created again
+            kv.compare_and_set(conn, "expired", 4, None, None).await?;
+            assert_eq!(Some(4), kv.get_value(conn, "expired").await?);
+
+            kv.set_with_ttl(conn, "expired", 5, Duration::from_millis(0))
+                .await?;
+            assert_eq!(1, kv.delete_expired(conn).await?);
+            assert_eq!(2, kv.json().count_all(conn).await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_not_write_outside_of_a_transaction() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("KV_TABLE_{}", rand_string(8));
+        let kv = &C3p0Kv::new(C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<KvEntry<u32>>());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            kv.create_table_if_not_exists(conn).await?;
+            kv.set(conn, "one", 1).await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        pool.connection(|mut conn| async move {
+            let conn = &mut conn;
+            assert!(kv.set(conn, "one", 11).await.is_err());
+            assert!(kv.set(conn, "two", 2).await.is_err());
+            assert!(kv.delete(conn, "one").await.is_err());
+            assert_eq!(Some(1), kv.get_value(conn, "one").await?);
+            assert_eq!(None, kv.get_value(conn, "two").await?);
+            kv.json().drop_table_if_exists(conn, true).await
+        })
+        .await
+    })
+}
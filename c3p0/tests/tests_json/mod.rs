@@ -1,2 +1,16 @@
+pub mod binary;
+pub mod cache;
+pub mod compression;
+pub mod encryption;
+pub mod event_store;
 pub mod json;
+pub mod json_schema;
 pub mod json_transaction;
+pub mod kv;
+pub mod model;
+pub mod outbox;
+pub mod pool;
+pub mod queue;
+pub mod replica;
+pub mod rewrite;
+pub mod transfer;
@@ -0,0 +1,102 @@
+#![cfg(feature = "derive")]
+
+use crate::utils::*;
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, C3p0Model)]
+#[c3p0(table = "ACCOUNT_TABLE")]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    #[c3p0(unique)]
+    pub user_name: String,
+    #[c3p0(index)]
+    pub country: String,
+    pub age: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, C3p0Model)]
+pub struct UserAccount {
+    #[serde(rename = "mail")]
+    #[c3p0(unique)]
+    pub email: String,
+}
+
+fn account(user_name: &str, country: &str) -> Account {
+    Account {
+        user_name: user_name.to_owned(),
+        country: country.to_owned(),
+        age: 30,
+    }
+}
+
+#[test]
+fn should_derive_the_model_metadata() {
+    assert_eq!("ACCOUNT_TABLE", Account::TABLE_NAME);
+    assert_eq!(
+        vec![
+            C3p0Index::new("userName", true),
+            C3p0Index::new("country", false)
+        ],
+        Account::indexes()
+    );
+
+    assert_eq!("user_account", UserAccount::TABLE_NAME);
+    assert_eq!(vec![C3p0Index::new("mail", true)], UserAccount::indexes());
+}
+
+#[test]
+fn should_find_by_the_indexed_fields() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let table_name = format!("ACCOUNT_TABLE_{}", rand_string(8));
+            let repository =
+                AccountRepository::new(C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build());
+
+            assert!(repository.create_table_if_not_exists(conn).await.is_ok());
+            assert!(repository.create_table_if_not_exists(conn).await.is_ok());
+
+            let first = repository
+                .save(conn, account("first", "Italy").into())
+                .await?;
+            repository
+                .save(conn, account("second", "Italy").into())
+                .await?;
+            repository
+                .save(conn, account("third", "Spain").into())
+                .await?;
+
+            let found = repository
+                .find_by_user_name(conn, &"first".to_owned())
+                .await?;
+            assert_eq!(Some(first.id), found.as_ref().map(|model| model.id));
+            assert_eq!(Some(&first.data), found.as_ref().map(|model| &model.data));
+            assert!(repository
+                .find_by_user_name(conn, &"fourth".to_owned())
+                .await?
+                .is_none());
+
+            let italians = repository
+                .find_all_by_country(conn, &"Italy".to_owned())
+                .await?;
+            assert_eq!(
+                vec!["first", "second"],
+                italians
+                    .iter()
+                    .map(|model| model.data.user_name.as_str())
+                    .collect::<Vec<_>>()
+            );
+            assert!(repository
+                .find_all_by_country(conn, &"France".to_owned())
+                .await?
+                .is_empty());
+
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,173 @@
+use crate::utils::*;
+use crate::*;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Default)]
+struct TestPublisher {
+    published: Mutex<Vec<String>>,
+    failures_left: Mutex<u32>,
+}
+
+#[async_trait::async_trait]
+impl OutboxPublisher for TestPublisher {
+    async fn publish(
+        &self,
+        message: &Model<OutboxMessage>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut failures_left = self.failures_left.lock().unwrap();
+        if *failures_left > 0 {
+            *failures_left -= 1;
+            return Err(format!("cannot publish {}", message.data.topic).into());
+        }
+        self.published
+            .lock()
+            .unwrap()
+            .push(message.data.payload["name"].as_str().unwrap().to_owned());
+        Ok(())
+    }
+}
+
+#[test]
+fn should_relay_only_the_committed_messages() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("OUTBOX_TABLE_{}", rand_string(8));
+        let outbox = &C3p0Outbox::new(
+            pool.clone(),
+            C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build(),
+        )
+        .with_batch_size(2);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            outbox.create_table_if_not_exists(conn).await?;
+            for name in &["one", "two", "three"] {
+                outbox
+                    .enqueue(conn, "users", &json!({ "name": name }))
+                    .await?;
+            }
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        let rollback: Result<(), C3p0Error> = pool
+            .transaction(|mut conn| async move {
+                outbox
+                    .enqueue(&mut conn, "users", &json!({"name": "rolled_back"}))
+                    .await?;
+                Err(C3p0Error::InternalError {
+                    cause: "rollback on purpose".to_owned(),
+                })
+            })
+            .await;
+        assert!(rollback.is_err());
+
+        let publisher = TestPublisher::default();
+        let report = outbox.relay_all(&publisher).await?;
+        assert_eq!(3, report.claimed);
+        assert_eq!(3, report.sent);
+        assert_eq!(
+            vec!["one", "two", "three"],
+            *publisher.published.lock().unwrap()
+        );
+
+        assert_eq!(RelayReport::default(), outbox.relay_all(&publisher).await?);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            for message in outbox.json().fetch_all(conn).await? {
+                assert!(message.data.is_sent());
+                assert_eq!(1, message.version);
+            }
+
+            // the messages sent recently are kept
+            assert_eq!(0, outbox.purge_sent(conn, Duration::from_secs(60)).await?);
+            let pending = outbox
+                .enqueue(conn, "users", &json!({"name": "pending"}))
+                .await?;
+            assert_eq!(3, outbox.purge_sent(conn, Duration::from_secs(0)).await?);
+            let messages = outbox.json().fetch_all(conn).await?;
+            assert_eq!(
+                vec![pending.id],
+                messages.iter().map(|m| m.id).collect::<Vec<_>>()
+            );
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_retry_the_failed_messages() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("OUTBOX_TABLE_{}", rand_string(8));
+        let outbox = &C3p0Outbox::new(
+            pool.clone(),
+            C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build(),
+        )
+        .with_max_attempts(3)
+        .with_retry_delay(Duration::from_secs(0), Duration::from_secs(0));
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            outbox.create_table_if_not_exists(conn).await?;
+            outbox
+                .enqueue(conn, "users", &json!({"name": "one"}))
+                .await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        let publisher = TestPublisher::default();
+        *publisher.failures_left.lock().unwrap() = 2;
+
+        for _ in 0..2 {
+            let report = outbox.relay_batch(&publisher).await?;
+            assert_eq!(1, report.claimed);
+            assert_eq!(1, report.retried);
+        }
+        let report = outbox.relay_batch(&publisher).await?;
+        assert_eq!(1, report.sent);
+        assert_eq!(vec!["one"], *publisher.published.lock().unwrap());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let message = &outbox.json().fetch_all(conn).await?[0];
+            assert!(message.data.is_sent());
+            assert_eq!(2, message.data.attempts);
+            assert_eq!(
+                Some("cannot publish users".to_owned()),
+                message.data.last_error
+            );
+
+            // a message that keeps failing is given up after max_attempts
+            outbox
+                .enqueue(conn, "users", &json!({"name": "two"}))
+                .await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        *publisher.failures_left.lock().unwrap() = 10;
+        for _ in 0..2 {
+            assert_eq!(1, outbox.relay_batch(&publisher).await?.retried);
+        }
+        assert_eq!(1, outbox.relay_batch(&publisher).await?.failed);
+        assert_eq!(0, outbox.relay_batch(&publisher).await?.claimed);
+
+        pool.transaction(|mut conn| async move {
+            let message = &outbox.json().fetch_all(&mut conn).await?[1];
+            assert!(message.data.is_failed());
+            assert_eq!(3, message.data.attempts);
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,63 @@
+use crate::utils::*;
+use crate::*;
+use std::time::Duration;
+
+#[test]
+fn should_return_the_status_of_the_pool() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        c3p0.wait_until_ready(Duration::from_secs(5)).await.unwrap();
+        c3p0.ping().await.unwrap();
+
+        let status = c3p0
+            .transaction(|_conn| async move { Ok::<_, C3p0Error>(c3p0.status()) })
+            .await
+            .unwrap();
+        assert!(status.in_use >= 1);
+        assert!(status.size >= status.in_use);
+        assert_eq!(status.size, status.idle + status.in_use);
+    });
+}
+
+#[test]
+fn should_free_the_connections_of_the_transactions_that_exceed_the_timeout() {
+    test(async {
+        let data = data(false).await;
+        let max_size = 2;
+        let c3p0 = &db_specific::new_pool(&data, max_size).await;
+
+        let options = &TxOptions::new().with_timeout(Duration::from_millis(50));
+        for _ in 0..max_size {
+            let result: Result<(), C3p0Error> = c3p0
+                .transaction_with_options(options, |_conn| async {
+                    tokio::time::delay_for(Duration::from_secs(10)).await;
+                    Ok(())
+                })
+                .await;
+            match result {
+                Err(C3p0Error::TimeoutError { .. }) => {}
+                _ => panic!("Expected a TimeoutError, found {:?}", result),
+            }
+        }
+
+        // The connections are closed in the background
+        c3p0.wait_until_ready(Duration::from_secs(5)).await.unwrap();
+        let mut status = c3p0.status();
+        for _ in 0..50 {
+            if status.in_use == 0 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            status = c3p0.status();
+        }
+        assert_eq!(0, status.in_use);
+        assert!(status.size <= max_size as usize);
+
+        tokio::time::timeout(Duration::from_secs(5), c3p0.close())
+            .await
+            .expect("The pool should close");
+        assert!(c3p0.ping().await.is_err());
+    });
+}
@@ -0,0 +1,188 @@
+use crate::utils::*;
+use crate::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn queue(
+    pool: &C3p0Impl,
+) -> C3p0JobQueue<
+    TestData,
+    DefaultJsonCodec,
+    C3p0Impl,
+    impl C3p0Json<Job<TestData>, DefaultJsonCodec, Conn = <C3p0Impl as C3p0Pool>::Conn>,
+> {
+    let table_name = format!("QUEUE_TABLE_{}", rand_string(8));
+    C3p0JobQueue::new(
+        pool.clone(),
+        C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build(),
+        C3p0JsonBuilder::<C3p0Impl>::new(format!("{}_DEAD", table_name)).build(),
+    )
+}
+
+fn payload(first_name: &str) -> TestData {
+    TestData {
+        first_name: first_name.to_owned(),
+        last_name: "job".to_owned(),
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+#[test]
+fn should_claim_the_due_jobs_by_priority() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+        let queue = &queue(pool);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            queue.create_tables_if_not_exists(conn).await?;
+            queue.enqueue(conn, payload("low").into()).await?;
+            queue
+                .enqueue(conn, NewJob::new(payload("high")).with_priority(10))
+                .await?;
+            queue
+                .enqueue(
+                    conn,
+                    NewJob::new(payload("later"))
+                        .with_priority(100)
+                        .with_run_at(now_millis() + 3_600_000),
+                )
+                .await?;
+            queue
+                .enqueue(conn, NewJob::new(payload("medium")).with_priority(5))
+                .await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        let claimed = queue.claim(2).await?;
+        assert_eq!(
+            vec!["high", "medium"],
+            claimed
+                .iter()
+                .map(|job| job.data.payload.first_name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(claimed.iter().all(|job| job.data.attempts == 1));
+
+        // the claimed jobs are hidden, the future job is not due
+        let claimed_again = queue.claim(10).await?;
+        assert_eq!(1, claimed_again.len());
+        assert_eq!("low", claimed_again[0].data.payload.first_name);
+        assert!(queue.claim(10).await?.is_empty());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            for job in claimed.into_iter().chain(claimed_again) {
+                queue.complete(conn, job).await?;
+            }
+            assert_eq!(1, queue.json().count_all(conn).await?);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_retry_and_dead_letter_the_failed_jobs() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+        let queue = &queue(pool)
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::from_secs(0), Duration::from_secs(0));
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            queue.create_tables_if_not_exists(conn).await?;
+            queue.enqueue(conn, payload("failing").into()).await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        let job = queue.claim(1).await?.remove(0);
+        pool.transaction(|mut conn| async move {
+            match queue.fail(&mut conn, job, "first error").await? {
+                FailedJob::Retried(job) => {
+                    assert_eq!(1, job.data.attempts);
+                    Ok::<_, C3p0Error>(())
+                }
+                FailedJob::DeadLettered(_) => panic!("the job should be retried"),
+            }
+        })
+        .await?;
+
+        let job = queue.claim(1).await?.remove(0);
+        assert_eq!(2, job.data.attempts);
+        assert_eq!(Some("first error".to_owned()), job.data.last_error);
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            match queue.fail(conn, job, "second error").await? {
+                FailedJob::DeadLettered(job) => {
+                    assert_eq!(Some("second error".to_owned()), job.data.last_error)
+                }
+                FailedJob::Retried(_) => panic!("the job should be dead lettered"),
+            }
+            assert_eq!(0, queue.json().count_all(conn).await?);
+            assert_eq!(1, queue.dead_letter_json().count_all(conn).await?);
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        assert!(queue.claim(1).await?.is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn should_reclaim_the_jobs_after_the_visibility_timeout() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+        let queue = &queue(pool)
+            .with_max_attempts(2)
+            .with_visibility_timeout(Duration::from_millis(0));
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            queue.create_tables_if_not_exists(conn).await?;
+            queue.enqueue(conn, payload("crashing").into()).await?;
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        // the first worker crashes
+        let stale_job = queue.claim(1).await?.remove(0);
+
+        let job = queue.claim(1).await?.remove(0);
+        assert_eq!(stale_job.id, job.id);
+        assert_eq!(2, job.data.attempts);
+
+        let result = pool
+            .transaction(|mut conn| async move { queue.complete(&mut conn, stale_job).await })
+            .await;
+        assert!(matches!(result, Err(C3p0Error::OptimisticLockError { .. })));
+
+        // the last attempt times out too
+        assert!(queue.claim(1).await?.is_empty());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(0, queue.json().count_all(conn).await?);
+            let dead_jobs = queue.dead_letter_json().fetch_all(conn).await?;
+            assert_eq!(1, dead_jobs.len());
+            assert_eq!(
+                Some("Visibility timeout expired".to_owned()),
+                dead_jobs[0].data.last_error
+            );
+            Ok(())
+        })
+        .await
+    })
+}
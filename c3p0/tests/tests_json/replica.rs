@@ -0,0 +1,46 @@
+use crate::utils::*;
+use crate::*;
+use std::time::Duration;
+
+#[test]
+fn should_read_the_models_from_the_replicas() {
+    test(async {
+        let data = data(false).await;
+        let c3p0 = &data.0;
+
+        // The primary is its own replica, so the writes are immediately visible
+        let pool = &ReplicatedC3p0Pool::new(c3p0.clone(), vec![c3p0.clone()])
+            .with_read_your_writes(Duration::from_secs(1));
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build();
+
+        let model = pool
+            .transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+                jpo.create_table_if_not_exists(&mut conn).await?;
+                let model = NewModel::new(TestData {
+                    first_name: "my_first_name".to_owned(),
+                    last_name: "my_last_name".to_owned(),
+                });
+                jpo.save(&mut conn, model).await
+            })
+            .await
+            .unwrap();
+
+        pool.read_transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            let conn = &mut conn;
+            assert_eq!(1, jpo.count_all(conn).await?);
+            let fetched = jpo.fetch_one_optional_by_id(conn, &model).await?.unwrap();
+            assert_eq!(model.data, fetched.data);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        pool.transaction::<_, C3p0Error, _, _>(|mut conn| async move {
+            jpo.drop_table_if_exists(&mut conn, true).await
+        })
+        .await
+        .unwrap();
+    });
+}
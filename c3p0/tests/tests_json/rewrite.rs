@@ -0,0 +1,142 @@
+use crate::utils::codec::*;
+use crate::utils::*;
+use crate::*;
+use serde_json::Value;
+
+fn versioned_codec() -> VersionedCodec<UserVersion2> {
+    VersionedCodec::<UserVersion1>::new("V1").with_version("V2", |user: UserVersion1| {
+        UserVersion2 {
+            username: user.username,
+            email: user.email,
+            age: 18,
+        }
+    })
+}
+
+fn user_v1(index: usize) -> UserVersion1 {
+    UserVersion1 {
+        username: format!("user_{}", index),
+        email: format!("user_{}@test.com", index),
+    }
+}
+
+#[test]
+fn should_rewrite_only_the_stale_documents() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("USER_TABLE_{}", rand_string(8));
+        let untagged_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<UserVersion1>();
+        let value_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<Value>();
+        let versioned_jpo =
+            &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec(versioned_codec());
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            assert!(versioned_jpo.create_table_if_not_exists(conn).await.is_ok());
+            for index in 0..5 {
+                untagged_jpo.save(conn, user_v1(index).into()).await?;
+            }
+            for index in 5..7 {
+                let user = versioned_jpo
+                    .save(
+                        conn,
+                        NewModel::new(UserVersion2 {
+                            username: format!("user_{}", index),
+                            email: format!("user_{}@test.com", index),
+                            age: 40,
+                        }),
+                    )
+                    .await?;
+                assert_eq!(0, user.version);
+            }
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        assert_eq!(5, count_stale(pool, versioned_jpo, 2).await?);
+
+        let mut reported = vec![];
+        let progress = rewrite_all_with_progress(
+            pool,
+            versioned_jpo,
+            2,
+            RewriteProgress::default(),
+            |progress| reported.push(progress.clone()),
+        )
+        .await?;
+
+        assert_eq!(7, progress.processed);
+        assert_eq!(5, progress.stale);
+        assert_eq!(5, progress.rewritten);
+        assert_eq!(0, progress.conflicts);
+        assert_eq!(4, reported.len());
+        assert_eq!(Some(&progress), reported.last());
+        assert_eq!(2, reported[0].processed);
+
+        assert_eq!(0, count_stale(pool, versioned_jpo, 2).await?);
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            for model in value_jpo.fetch_all(conn).await? {
+                assert_eq!("V2", model.data["@json_tag"]);
+                let expected_version = if model.data["age"] == 18 { 1 } else { 0 };
+                assert_eq!(expected_version, model.version);
+            }
+            assert!(versioned_jpo.drop_table_if_exists(conn, true).await.is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_resume_from_the_last_progress() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("USER_TABLE_{}", rand_string(8));
+        let untagged_jpo = &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build::<UserVersion1>();
+        let versioned_jpo =
+            &C3p0JsonBuilder::<C3p0Impl>::new(&table_name).build_with_codec(versioned_codec());
+
+        let ids = pool
+            .transaction(|mut conn| async move {
+                let conn = &mut conn;
+                assert!(untagged_jpo.create_table_if_not_exists(conn).await.is_ok());
+                let mut ids = vec![];
+                for index in 0..4 {
+                    ids.push(untagged_jpo.save(conn, user_v1(index).into()).await?.id);
+                }
+                Ok::<_, C3p0Error>(ids)
+            })
+            .await?;
+
+        let interrupted = RewriteProgress {
+            last_id: Some(ids[1]),
+            processed: 2,
+            ..RewriteProgress::default()
+        };
+
+        let progress =
+            rewrite_all_with_progress(pool, versioned_jpo, 10, interrupted, |_| {}).await?;
+        assert_eq!(4, progress.processed);
+        assert_eq!(2, progress.rewritten);
+        assert_eq!(2, count_stale(pool, versioned_jpo, 10).await?);
+
+        let progress = rewrite_all(pool, versioned_jpo, 10).await?;
+        assert_eq!(2, progress.rewritten);
+        assert_eq!(0, count_stale(pool, versioned_jpo, 10).await?);
+
+        pool.transaction(|mut conn| async move {
+            assert!(versioned_jpo
+                .drop_table_if_exists(&mut conn, true)
+                .await
+                .is_ok());
+            Ok(())
+        })
+        .await
+    })
+}
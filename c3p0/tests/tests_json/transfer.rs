@@ -0,0 +1,128 @@
+use crate::utils::*;
+use crate::*;
+
+fn test_data(first_name: &str) -> TestData {
+    TestData {
+        first_name: first_name.to_owned(),
+        last_name: "".to_owned(),
+    }
+}
+
+#[test]
+fn should_save_with_id() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<TestData>();
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            jpo.create_table_if_not_exists(conn).await?;
+
+            let model = Model {
+                id: 1000,
+                version: 7,
+                data: test_data("one"),
+            };
+            assert_eq!(model, jpo.save_with_id(conn, model.clone()).await?);
+            assert_eq!(model, jpo.fetch_one_by_id(conn, &model).await?);
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        // an id cannot be used twice
+        let result: Result<_, C3p0Error> = pool
+            .transaction(|mut conn| async move {
+                jpo.save_with_id(
+                    &mut conn,
+                    Model {
+                        id: 1000,
+                        version: 0,
+                        data: test_data("other"),
+                    },
+                )
+                .await
+            })
+            .await;
+        assert!(result.is_err());
+
+        // the ids generated afterwards are greater than the saved one
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            let saved = jpo.save(conn, NewModel::new(test_data("two"))).await?;
+            assert!(saved.id > 1000);
+            Ok(())
+        })
+        .await
+    })
+}
+
+#[test]
+fn should_export_and_import_a_table() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let source = &C3p0JsonBuilder::<C3p0Impl>::new(table_name.clone()).build::<TestData>();
+        let target =
+            &C3p0JsonBuilder::<C3p0Impl>::new(format!("{}_COPY", table_name)).build::<TestData>();
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            source.create_table_if_not_exists(conn).await?;
+            target.create_table_if_not_exists(conn).await?;
+
+            let first = source.save(conn, NewModel::new(test_data("one"))).await?;
+            let first = source.update(conn, first).await?;
+            let second = source.save(conn, NewModel::new(test_data("two"))).await?;
+
+            let mut lines = vec![];
+            assert_eq!(2, export_table(conn, source, &mut lines).await?);
+            assert_eq!(2, String::from_utf8(lines.clone()).unwrap().lines().count());
+
+            // the entries keep their ids and versions
+            let report =
+                import_table(conn, target, &lines[..], ImportMode::KeepIdsSkipExisting).await?;
+            assert_eq!(2, report.saved);
+            assert_eq!(first, target.fetch_one_by_id(conn, &first).await?);
+            assert_eq!(second, target.fetch_one_by_id(conn, &second).await?);
+
+            let mut changed = second.clone();
+            changed.data.first_name = "changed".to_owned();
+            let changed = target.update(conn, changed).await?;
+
+            let report =
+                import_table(conn, target, &lines[..], ImportMode::KeepIdsSkipExisting).await?;
+            assert_eq!(0, report.saved);
+            assert_eq!(2, report.skipped);
+            assert_eq!(changed, target.fetch_one_by_id(conn, &second).await?);
+
+            let report = import_table(
+                conn,
+                target,
+                &lines[..],
+                ImportMode::KeepIdsOverwriteExisting,
+            )
+            .await?;
+            assert_eq!(2, report.saved);
+            assert_eq!(2, report.overwritten);
+            // the overwritten entries are updated, so their version is incremented
+            let overwritten = target.fetch_one_by_id(conn, &second).await?;
+            assert_eq!(second.data, overwritten.data);
+            assert_eq!(changed.version + 1, overwritten.version);
+
+            let report = import_table(conn, target, &lines[..], ImportMode::RegenerateIds).await?;
+            assert_eq!(2, report.saved);
+            assert_eq!(4, target.count_all(conn).await?);
+            let imported = target.fetch_all(conn).await?;
+            assert!(imported[2].id > second.id);
+            assert_eq!(first.version, imported[2].version);
+            assert_eq!(second.data, imported[3].data);
+            Ok(())
+        })
+        .await
+    })
+}
@@ -0,0 +1,175 @@
+use crate::utils::*;
+use crate::*;
+use std::time::Duration;
+
+#[test]
+fn should_hold_a_session_lock_until_released() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let key = LockKey::from(format!("lock_{}", rand_string(8)));
+
+        let lock = pool
+            .try_session_lock(&key)
+            .await?
+            .expect("lock not acquired");
+        assert_eq!(&key, lock.key());
+        assert!(pool.try_session_lock(&key).await?.is_none());
+        assert!(pool
+            .session_lock_with_timeout(&key, Duration::from_millis(100))
+            .await?
+            .is_none());
+
+        lock.release().await?;
+        let lock = pool
+            .try_session_lock(&key)
+            .await?
+            .expect("lock not released");
+
+        // a dropped lock is released too
+        drop(lock);
+        assert!(pool
+            .session_lock_with_timeout(&key, Duration::from_secs(5))
+            .await?
+            .is_some());
+        Ok(())
+    })
+}
+
+#[test]
+fn should_release_the_transaction_locks_when_the_transaction_ends() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let key = &LockKey::from(rand::random::<i64>());
+
+        pool.transaction(|mut conn| async move {
+            conn.lock(key).await?;
+            assert!(conn.try_lock(key).await?);
+            assert!(pool.try_session_lock(key).await?.is_none());
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+        pool.try_session_lock(key)
+            .await?
+            .expect("lock not released on commit")
+            .release()
+            .await?;
+
+        let result: Result<(), C3p0Error> = pool
+            .transaction(|mut conn| async move {
+                assert!(conn.try_lock(key).await?);
+                Err(C3p0Error::InternalError {
+                    cause: "rollback on purpose".to_owned(),
+                })
+            })
+            .await;
+        assert!(result.is_err());
+        pool.try_session_lock(key)
+            .await?
+            .expect("lock not released on rollback")
+            .release()
+            .await?;
+        Ok(())
+    })
+}
+
+#[test]
+fn should_not_abort_the_transaction_on_lock_timeout() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let key = &LockKey::from(format!("lock_{}", rand_string(8)));
+        let other_key = &LockKey::from(format!("lock_{}", rand_string(8)));
+        let table_name = format!("TEST_TABLE_{}", rand_string(8));
+        let jpo = &C3p0JsonBuilder::<C3p0Impl>::new(table_name).build::<TestData>();
+
+        let session_lock = pool
+            .try_session_lock(key)
+            .await?
+            .expect("lock not acquired");
+
+        pool.transaction(|mut conn| async move {
+            let conn = &mut conn;
+            jpo.create_table_if_not_exists(conn).await?;
+            assert!(!conn.try_lock(key).await?);
+            assert!(
+                !conn
+                    .lock_with_timeout(key, Duration::from_millis(100))
+                    .await?
+            );
+            assert!(
+                conn.lock_with_timeout(other_key, Duration::from_millis(100))
+                    .await?
+            );
+            assert_eq!(0, jpo.count_all(conn).await?);
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        session_lock.release().await
+    })
+}
+
+#[test]
+fn should_refuse_the_transaction_locks_outside_of_a_transaction() -> Result<(), C3p0Error> {
+    test(async {
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let key = &LockKey::from(format!("lock_{}", rand_string(8)));
+
+        pool.connection(|mut conn| async move {
+            assert!(conn.lock(key).await.is_err());
+            assert!(conn.try_lock(key).await.is_err());
+            assert!(conn
+                .lock_with_timeout(key, Duration::from_millis(100))
+                .await
+                .is_err());
+            Ok::<_, C3p0Error>(())
+        })
+        .await?;
+
+        pool.try_session_lock(key)
+            .await?
+            .expect("lock acquired outside of a transaction")
+            .release()
+            .await
+    })
+}
+
+#[test]
+fn should_fail_with_a_timeout_error_when_the_lock_timeout_expires() -> Result<(), C3p0Error> {
+    test(async {
+        // The MySQL named locks ignore the lock wait timeout
+        if db_specific::db_type() == DbType::MySql || db_specific::db_type() == DbType::TiDB {
+            return Ok(());
+        }
+
+        let data = data(false).await;
+        let pool = &data.0;
+
+        let key = &LockKey::from(format!("lock_{}", rand_string(8)));
+
+        let session_lock = pool
+            .try_session_lock(key)
+            .await?
+            .expect("lock not acquired");
+
+        let result: Result<(), C3p0Error> = pool
+            .transaction_with_options(
+                &TxOptions::new().with_lock_timeout(Duration::from_millis(100)),
+                |mut conn| async move { conn.lock(key).await },
+            )
+            .await;
+        match result {
+            Err(C3p0Error::TimeoutError { .. }) => {}
+            _ => panic!("Expected a TimeoutError, found {:?}", result),
+        }
+
+        session_lock.release().await
+    })
+}
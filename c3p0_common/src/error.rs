@@ -10,12 +10,28 @@ pub enum C3p0Error {
         cause: String,
         code: Option<String>,
     },
+    #[error("TimeoutError. DB: {db}. DB specific error code: [{code:?}]. Msg: {cause}")]
+    TimeoutError {
+        db: &'static str,
+        cause: String,
+        code: Option<String>,
+    },
     #[error("RowMapperError: [{cause}]")]
     RowMapperError { cause: String },
     #[error("OptimisticLockError: [{message}]")]
     OptimisticLockError { message: String },
     #[error("JsonProcessingError: [{cause}]")]
     JsonProcessingError { cause: serde_json::error::Error },
+    #[error("BinaryProcessingError: [{cause}]")]
+    BinaryProcessingError { cause: String },
+    #[error("CompressionError: [{cause}]")]
+    CompressionError { cause: String },
+    #[error("EncryptionError: [{cause}]")]
+    EncryptionError { cause: String },
+    #[error("ValidationError: [{message}]. Violating paths: {paths:?}")]
+    ValidationError { message: String, paths: Vec<String> },
+    #[error("VersioningError: [{cause}]")]
+    VersioningError { cause: String },
     #[error("IteratorError: [{message}]")]
     IteratorError { message: String },
     #[error("PoolError: pool [{pool}] for [{db}] returned error: [{cause}]")]
@@ -45,6 +61,41 @@ pub enum C3p0Error {
     },
 }
 
+/// The MySQL error numbers of the statements exceeding the max execution time (3024)
+/// and of the lock wait timeouts (1205)
+const TIMEOUT_CODES: &[&str] = &["3024", "1205"];
+
+/// The Postgres SQLSTATEs of the timeouts, shared with other errors, and the message
+/// of the timeout: the canceled statements (57014) are also the ones canceled by a user,
+/// and the locks not available (55P03) also the ones of the NOWAIT locks.
+/// The messages are the English ones: with other `lc_messages` the timeouts are `DbError`s.
+const PG_TIMEOUT_MESSAGES: &[(&str, &str)] = &[
+    ("57014", "canceling statement due to statement timeout"),
+    ("55P03", "canceling statement due to lock timeout"),
+];
+
+impl C3p0Error {
+    /// Returns the error reported by a database: a `TimeoutError` if it is the error of
+    /// a statement or lock timeout, otherwise a `DbError`
+    pub fn from_db(db: &'static str, cause: String, code: Option<String>) -> C3p0Error {
+        match code {
+            Some(code) if is_timeout(&code, &cause) => C3p0Error::TimeoutError {
+                db,
+                cause,
+                code: Some(code),
+            },
+            code => C3p0Error::DbError { db, cause, code },
+        }
+    }
+}
+
+fn is_timeout(code: &str, cause: &str) -> bool {
+    TIMEOUT_CODES.contains(&code)
+        || PG_TIMEOUT_MESSAGES
+            .iter()
+            .any(|(pg_code, message)| *pg_code == code && cause.contains(message))
+}
+
 impl From<serde_json::error::Error> for C3p0Error {
     fn from(cause: serde_json::error::Error) -> Self {
         C3p0Error::JsonProcessingError { cause }
@@ -61,4 +112,51 @@ mod test {
     fn error_should_be_send_and_sync() {
         assert_impl_all!(C3p0Error: Send, Sync);
     }
+
+    #[test]
+    fn should_classify_the_timeouts_of_the_db_errors() {
+        for (code, cause) in &[
+            (
+                "57014",
+                "db error: ERROR: canceling statement due to statement timeout",
+            ),
+            (
+                "55P03",
+                "db error: ERROR: canceling statement due to lock timeout",
+            ),
+            (
+                "3024",
+                "Query execution was interrupted, maximum statement execution time exceeded",
+            ),
+            (
+                "1205",
+                "Lock wait timeout exceeded; try restarting transaction",
+            ),
+        ] {
+            match C3p0Error::from_db("db", cause.to_string(), Some(code.to_string())) {
+                C3p0Error::TimeoutError { code: Some(c), .. } => assert_eq!(code, &c),
+                err => panic!("Expected a TimeoutError, found {}", err),
+            }
+        }
+        for (code, cause) in &[
+            ("23505", "duplicate key value violates unique constraint"),
+            (
+                "57014",
+                "db error: ERROR: canceling statement due to user request",
+            ),
+            (
+                "55P03",
+                r#"db error: ERROR: could not obtain lock on row in relation "test""#,
+            ),
+        ] {
+            match C3p0Error::from_db("db", cause.to_string(), Some(code.to_string())) {
+                C3p0Error::DbError { code: Some(c), .. } => assert_eq!(code, &c),
+                err => panic!("Expected a DbError, found {}", err),
+            }
+        }
+        match C3p0Error::from_db("db", "".to_owned(), None) {
+            C3p0Error::DbError { code: None, .. } => {}
+            err => panic!("Expected a DbError, found {}", err),
+        }
+    }
 }
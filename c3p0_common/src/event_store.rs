@@ -0,0 +1,335 @@
+use crate::error::C3p0Error;
+use crate::json::codec::DefaultJsonCodec;
+use crate::json::model::{C3p0Index, IdType, Model, NewModel};
+use crate::json::C3p0Json;
+use crate::lock::{C3p0Lock, LockKey};
+use crate::outbox::now_millis;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::marker::PhantomData;
+
+const STREAM_ID_FIELD: &str = "stream_id";
+
+/// The prefix of the transaction lock that serializes the appends to a stream
+const APPEND_LOCK_PREFIX: &str = "c3p0_event_store_append";
+
+/// An event of a stream
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredEvent<E> {
+    pub stream_id: String,
+    /// The position of the event in its stream, starting from 1
+    pub stream_version: u64,
+    /// When the event was appended, in milliseconds since the Unix epoch
+    pub recorded_at: i64,
+    pub event: E,
+}
+
+/// The head of a stream, that records its current version
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EventStream {
+    pub stream_id: String,
+    /// The number of events of the stream
+    pub version: u64,
+}
+
+/// The version that a stream must have for an append to succeed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpectedVersion {
+    /// Any version, including a stream without events
+    Any,
+    /// The exact version; `Exact(0)` requires a stream without events
+    Exact(u64),
+}
+
+/// An append-only store of event streams.
+/// The events are stored in the table of the events C3p0Json, encoded with its codec,
+/// and the current version of every stream in the table of the streams C3p0Json,
+/// that uses the default codec.
+///
+/// The appends to the same stream are serialized with a transaction lock (see `C3p0Lock`),
+/// held until the end of the transaction of the append, while the appends to different
+/// streams run concurrently. The version check and the unique index on the stream ids
+/// of the streams table reject the conflicting appends.
+///
+/// As the appends to different streams are not serialized, the ids of their events may not
+/// follow the commit order: an event can become visible after an event with a greater id.
+/// The projections following `read_all` should read again the recent positions
+/// and skip the events they already processed.
+#[derive(Clone)]
+pub struct C3p0EventStore<E, CODEC, JSON, STREAMS> {
+    json: JSON,
+    streams_json: STREAMS,
+    phantom: PhantomData<fn() -> (E, CODEC)>,
+}
+
+impl<E, CODEC, JSON, STREAMS> C3p0EventStore<E, CODEC, JSON, STREAMS>
+where
+    E: Clone + Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Send + Sync,
+    JSON: C3p0Json<StoredEvent<E>, CODEC>,
+    JSON::Conn: C3p0Lock,
+    STREAMS: C3p0Json<EventStream, DefaultJsonCodec, Conn = JSON::Conn>,
+{
+    /// Creates an event store on the tables of the given C3p0Jsons
+    pub fn new(json: JSON, streams_json: STREAMS) -> Self {
+        C3p0EventStore {
+            json,
+            streams_json,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn json(&self) -> &JSON {
+        &self.json
+    }
+
+    pub fn streams_json(&self) -> &STREAMS {
+        &self.streams_json
+    }
+
+    /// Creates the tables and their indexes on the stream ids, unique in the streams table
+    pub async fn create_tables_if_not_exists(
+        &self,
+        conn: &mut JSON::Conn,
+    ) -> Result<(), C3p0Error> {
+        self.json.create_table_if_not_exists(conn).await?;
+        self.json
+            .create_index_if_not_exists(conn, &C3p0Index::new(STREAM_ID_FIELD, false))
+            .await?;
+        self.streams_json.create_table_if_not_exists(conn).await?;
+        self.streams_json
+            .create_index_if_not_exists(conn, &C3p0Index::new(STREAM_ID_FIELD, true))
+            .await
+    }
+
+    /// Appends the events to the stream.
+    /// It fails with an `OptimisticLockError` if the stream does not have the expected version.
+    pub async fn append(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+        expected_version: ExpectedVersion,
+        events: Vec<E>,
+    ) -> Result<Vec<Model<StoredEvent<E>>>, C3p0Error> {
+        // The name is hashed, so that it fits the lock names of every database
+        conn.lock(&LockKey::Id(
+            LockKey::from(format!(
+                "{}:{}:{}",
+                APPEND_LOCK_PREFIX,
+                self.json.qualified_table_name(),
+                stream_id
+            ))
+            .id(),
+        ))
+        .await?;
+
+        let stream = self.fetch_stream(conn, stream_id).await?;
+        let version = stream.as_ref().map_or(0, |stream| stream.data.version);
+        if let ExpectedVersion::Exact(expected_version) = expected_version {
+            if version != expected_version {
+                return Err(C3p0Error::OptimisticLockError {
+                    message: format!(
+                        "Cannot append to the stream [{}] with version [{}]: the current version is [{}]",
+                        stream_id, expected_version, version
+                    ),
+                });
+            }
+        }
+
+        let recorded_at = now_millis();
+        let mut stored_events = Vec::with_capacity(events.len());
+        for event in events {
+            stored_events.push(
+                self.json
+                    .save(
+                        conn,
+                        NewModel::new(StoredEvent {
+                            stream_id: stream_id.to_owned(),
+                            stream_version: version + stored_events.len() as u64 + 1,
+                            recorded_at,
+                            event,
+                        }),
+                    )
+                    .await?,
+            );
+        }
+
+        let version = version + stored_events.len() as u64;
+        match stream {
+            Some(mut stream) => {
+                stream.data.version = version;
+                self.streams_json.update(conn, stream).await?;
+            }
+            None => {
+                self.streams_json
+                    .save(
+                        conn,
+                        NewModel::new(EventStream {
+                            stream_id: stream_id.to_owned(),
+                            version,
+                        }),
+                    )
+                    .await?;
+            }
+        }
+
+        Ok(stored_events)
+    }
+
+    /// Returns the number of events of the stream
+    pub async fn stream_version(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+    ) -> Result<u64, C3p0Error> {
+        Ok(self
+            .fetch_stream(conn, stream_id)
+            .await?
+            .map_or(0, |stream| stream.data.version))
+    }
+
+    /// Returns the events of the stream with a version greater than or equal to `from_version`,
+    /// ordered by version
+    pub async fn read_stream(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+        from_version: u64,
+    ) -> Result<Vec<Model<StoredEvent<E>>>, C3p0Error> {
+        let mut events = self
+            .json
+            .fetch_all_by_field(conn, STREAM_ID_FIELD, &Value::String(stream_id.to_owned()))
+            .await?
+            .into_iter()
+            .filter(|event| event.data.stream_version >= from_version)
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| event.data.stream_version);
+        Ok(events)
+    }
+
+    /// Returns at most `limit` events of all the streams appended after the event with id `after_id`,
+    /// ordered by id.
+    /// It allows projections to follow the store: they start from `IdType::MIN`, then pass the id of the
+    /// last event they processed. The concurrent appends to different streams can commit
+    /// out of order, so an event with an id lower than the last processed one may become
+    /// visible later (see `C3p0EventStore`).
+    pub async fn read_all(
+        &self,
+        conn: &mut JSON::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<StoredEvent<E>>>, C3p0Error> {
+        self.json.fetch_all_after_id(conn, after_id, limit).await
+    }
+
+    async fn fetch_stream(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+    ) -> Result<Option<Model<EventStream>>, C3p0Error> {
+        Ok(self
+            .streams_json
+            .fetch_all_by_field(conn, STREAM_ID_FIELD, &Value::String(stream_id.to_owned()))
+            .await?
+            .into_iter()
+            .next())
+    }
+}
+
+/// The state of an aggregate after the events of its stream up to `stream_version`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<S> {
+    pub stream_id: String,
+    pub stream_version: u64,
+    pub state: S,
+}
+
+/// The snapshots of the event streams, one per stream, stored in the table of a C3p0Json.
+/// An aggregate is rebuilt from its snapshot and from the events with a greater version.
+#[derive(Clone)]
+pub struct C3p0SnapshotStore<S, CODEC, JSON> {
+    json: JSON,
+    phantom: PhantomData<fn() -> (S, CODEC)>,
+}
+
+impl<S, CODEC, JSON> C3p0SnapshotStore<S, CODEC, JSON>
+where
+    S: Clone + Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Send + Sync,
+    JSON: C3p0Json<Snapshot<S>, CODEC>,
+    JSON::Conn: C3p0Lock,
+{
+    pub fn new(json: JSON) -> Self {
+        C3p0SnapshotStore {
+            json,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn json(&self) -> &JSON {
+        &self.json
+    }
+
+    /// Creates the table and an index on the stream ids
+    pub async fn create_table_if_not_exists(&self, conn: &mut JSON::Conn) -> Result<(), C3p0Error> {
+        self.json.create_table_if_not_exists(conn).await?;
+        self.json
+            .create_index_if_not_exists(conn, &C3p0Index::new(STREAM_ID_FIELD, false))
+            .await
+    }
+
+    /// Returns the snapshot of the stream, if any
+    pub async fn load(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+    ) -> Result<Option<Model<Snapshot<S>>>, C3p0Error> {
+        Ok(self
+            .json
+            .fetch_all_by_field(conn, STREAM_ID_FIELD, &Value::String(stream_id.to_owned()))
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Replaces the snapshot of the stream, unless the stored one has a greater stream version
+    pub async fn save(
+        &self,
+        conn: &mut JSON::Conn,
+        stream_id: &str,
+        stream_version: u64,
+        state: S,
+    ) -> Result<Model<Snapshot<S>>, C3p0Error> {
+        conn.lock(&LockKey::Id(
+            LockKey::from(format!(
+                "c3p0_snapshot:{}:{}",
+                self.json.qualified_table_name(),
+                stream_id
+            ))
+            .id(),
+        ))
+        .await?;
+
+        let snapshot = Snapshot {
+            stream_id: stream_id.to_owned(),
+            stream_version,
+            state,
+        };
+        match self.load(conn, stream_id).await? {
+            Some(stored) if stored.data.stream_version > stream_version => Ok(stored),
+            Some(stored) => {
+                self.json
+                    .update(
+                        conn,
+                        Model {
+                            id: stored.id,
+                            version: stored.version,
+                            data: snapshot,
+                        },
+                    )
+                    .await
+            }
+            None => self.json.save(conn, NewModel::new(snapshot)).await,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use futures_core::future::BoxFuture;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type Hook = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+#[derive(Default)]
+struct Hooks {
+    on_commit: Vec<Hook>,
+    on_rollback: Vec<Hook>,
+}
+
+/// The callbacks registered in a transaction, run by the pool when the transaction ends.
+/// The clones share the same callbacks.
+#[derive(Clone, Default)]
+pub struct TransactionHooks {
+    hooks: Arc<Mutex<Hooks>>,
+}
+
+/// The number of callbacks registered when a savepoint was created
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HooksMark {
+    on_commit: usize,
+    on_rollback: usize,
+}
+
+impl TransactionHooks {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a callback run after the transaction is committed
+    pub fn on_commit<F, Fut>(&self, hook: F)
+    where
+        F: 'static + Send + FnOnce() -> Fut,
+        Fut: 'static + Send + Future<Output = ()>,
+    {
+        self.lock()
+            .on_commit
+            .push(Box::new(move || Box::pin(hook())));
+    }
+
+    /// Registers a callback run after the transaction is rolled back
+    pub fn on_rollback<F, Fut>(&self, hook: F)
+    where
+        F: 'static + Send + FnOnce() -> Fut,
+        Fut: 'static + Send + Future<Output = ()>,
+    {
+        self.lock()
+            .on_rollback
+            .push(Box::new(move || Box::pin(hook())));
+    }
+
+    /// Runs the `on_commit` callbacks in registration order and discards the `on_rollback` ones
+    pub async fn committed(&self) {
+        let hooks = std::mem::take(&mut *self.lock());
+        for hook in hooks.on_commit {
+            hook().await;
+        }
+    }
+
+    /// Runs the `on_rollback` callbacks in registration order and discards the `on_commit` ones
+    pub async fn rolled_back(&self) {
+        let hooks = std::mem::take(&mut *self.lock());
+        for hook in hooks.on_rollback {
+            hook().await;
+        }
+    }
+
+    /// Returns the mark of the callbacks registered until now
+    pub fn mark(&self) -> HooksMark {
+        let hooks = self.lock();
+        HooksMark {
+            on_commit: hooks.on_commit.len(),
+            on_rollback: hooks.on_rollback.len(),
+        }
+    }
+
+    /// Handles the rollback to a savepoint: the callbacks registered after the mark was taken
+    /// are removed, and the `on_rollback` ones are run
+    pub async fn rolled_back_to(&self, mark: HooksMark) {
+        let on_rollback = {
+            let mut hooks = self.lock();
+            hooks.on_commit.truncate(mark.on_commit);
+            let at = mark.on_rollback.min(hooks.on_rollback.len());
+            hooks.on_rollback.split_off(at)
+        };
+        for hook in on_rollback {
+            hook().await;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Hooks> {
+        // A panic cannot leave the vectors in an inconsistent state
+        self.hooks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The callbacks run when the transaction of a connection ends, implemented by the connections.
+/// On a connection outside of a transaction (see `C3p0Pool::connection`) every statement
+/// is committed, so the `on_commit` callbacks run when the function of the connection returns,
+/// and the `on_rollback` ones never run.
+pub trait C3p0Hooks {
+    /// Returns the callbacks of the transaction
+    fn hooks(&self) -> &TransactionHooks;
+
+    /// Registers a callback run after the transaction is committed.
+    /// It is discarded if the savepoint where it is registered is rolled back.
+    fn on_commit<F, Fut>(&self, hook: F)
+    where
+        F: 'static + Send + FnOnce() -> Fut,
+        Fut: 'static + Send + Future<Output = ()>,
+    {
+        self.hooks().on_commit(hook)
+    }
+
+    /// Registers a callback run after the transaction is rolled back,
+    /// or after the savepoint where it is registered is rolled back
+    fn on_rollback<F, Fut>(&self, hook: F)
+    where
+        F: 'static + Send + FnOnce() -> Fut,
+        Fut: 'static + Send + Future<Output = ()>,
+    {
+        self.hooks().on_rollback(hook)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn record(log: &Arc<Mutex<Vec<&'static str>>>, hooks: &TransactionHooks, name: &'static str) {
+        let commit_log = log.clone();
+        hooks.on_commit(move || async move { commit_log.lock().unwrap().push(name) });
+        let rollback_log = log.clone();
+        hooks.on_rollback(move || async move { rollback_log.lock().unwrap().push(name) });
+    }
+
+    #[tokio::test]
+    async fn should_run_the_hooks_of_the_outcome_in_order() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let hooks = TransactionHooks::new();
+        record(&log, &hooks, "one");
+        record(&log, &hooks, "two");
+
+        hooks.committed().await;
+        assert_eq!(vec!["one", "two"], *log.lock().unwrap());
+
+        hooks.rolled_back().await;
+        assert_eq!(vec!["one", "two"], *log.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_discard_the_hooks_of_a_rolled_back_savepoint() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let hooks = TransactionHooks::new();
+        record(&log, &hooks, "tx");
+
+        let mark = hooks.mark();
+        record(&log, &hooks, "savepoint");
+        hooks.rolled_back_to(mark).await;
+        assert_eq!(vec!["savepoint"], *log.lock().unwrap());
+
+        hooks.committed().await;
+        assert_eq!(vec!["savepoint", "tx"], *log.lock().unwrap());
+    }
+}
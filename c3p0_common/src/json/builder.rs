@@ -1,3 +1,5 @@
+use crate::json::compression::Compression;
+use crate::json::schema::JsonSchema;
 use crate::types::OptString;
 
 #[derive(Clone)]
@@ -8,6 +10,10 @@ pub struct C3p0JsonBuilder<C3P0> {
     pub data_field_name: String,
     pub table_name: String,
     pub schema_name: Option<String>,
+    pub compression: Option<Compression>,
+    pub schema: Option<JsonSchema>,
+    pub schema_check_constraint: bool,
+    pub change_feed: bool,
 }
 
 impl<C3P0> C3p0JsonBuilder<C3P0> {
@@ -20,6 +26,10 @@ impl<C3P0> C3p0JsonBuilder<C3P0> {
             version_field_name: "version".to_owned(),
             data_field_name: "data".to_owned(),
             schema_name: None,
+            compression: None,
+            schema: None,
+            schema_check_constraint: false,
+            change_feed: false,
         }
     }
 
@@ -42,4 +52,47 @@ impl<C3P0> C3p0JsonBuilder<C3P0> {
         self.schema_name = schema_name.into().value;
         self
     }
+
+    /// Compresses the documents bigger than the compression threshold.
+    /// It applies only to tables storing JSON documents.
+    /// The compressed documents cannot be queried, so the tables with compression
+    /// do not support the indexes and the finders by field.
+    pub fn with_compression<O: Into<Option<Compression>>>(mut self, compression: O) -> Self {
+        self.compression = compression.into();
+        self
+    }
+
+    /// Validates every written document against the schema.
+    /// It applies only to tables storing JSON documents.
+    pub fn with_schema<O: Into<Option<JsonSchema>>>(mut self, schema: O) -> Self {
+        self.schema = schema.into();
+        self
+    }
+
+    /// On Postgres, adds to the data column a CHECK constraint equivalent to the schema
+    /// when the table is created. The constraint is not added to compressed tables,
+    /// and it should not be used when the codec changes the shape of the schema fields
+    /// (e.g. an EncryptingCodec).
+    pub fn with_schema_check_constraint(mut self, schema_check_constraint: bool) -> Self {
+        self.schema_check_constraint = schema_check_constraint;
+        self
+    }
+
+    /// Publishes the committed inserts, updates and deletes of the table to the
+    /// `C3p0ChangeFeed` subscribers. On Postgres, the notifications are sent by a trigger
+    /// installed when the table is created; other SQL backends ignore this option.
+    pub fn with_change_feed(mut self, change_feed: bool) -> Self {
+        self.change_feed = change_feed;
+        self
+    }
+
+    /// Returns the CHECK expression to be added to the Postgres data column, if any
+    pub fn pg_schema_check_constraint(&self) -> Option<String> {
+        match (&self.schema, &self.compression) {
+            (Some(schema), None) if self.schema_check_constraint => {
+                schema.to_pg_check_constraint(&self.data_field_name)
+            }
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,613 @@
+use crate::error::C3p0Error;
+use crate::hooks::C3p0Hooks;
+use crate::json::model::{C3p0Index, IdType, Model, NewModel, VersionType};
+use crate::json::C3p0Json;
+use crate::sql::{ForUpdate, OrderBy};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const DEFAULT_CAPACITY: usize = 1000;
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// A C3p0Json that caches the entries fetched by id from the inner C3p0Json.
+///
+/// The entries are kept in an in-process LRU cache for at most the TTL, and they are
+/// invalidated by the writes made through the CachingC3p0Json.
+/// The cache belongs to the table of the inner C3p0Json and it is shared by the clones.
+///
+/// A written entry is not cached until the transaction of the write ends, then it is
+/// invalidated again: before the commit the transaction reads its uncommitted version,
+/// that must not be cached because it could be rolled back, and the other transactions
+/// read the previous version. For the same reason an entry fetched while any entry
+/// was invalidated is not cached.
+///
+/// The writes made elsewhere are not seen until the cached entry expires, but a stale entry
+/// has an older version than the stored one: the updates of a stale entry fail with
+/// an `OptimisticLockError`, that invalidates it, and the fetches for update, that always
+/// read from the database, invalidate the cached entry if its version differs.
+pub struct CachingC3p0Json<Data, CODEC, JSON>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    inner: JSON,
+    cache: Arc<Mutex<LruCache<Data>>>,
+    phantom: PhantomData<fn() -> CODEC>,
+}
+
+impl<Data, CODEC, JSON> Clone for CachingC3p0Json<Data, CODEC, JSON>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    JSON: Clone,
+{
+    fn clone(&self) -> Self {
+        CachingC3p0Json {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Data, CODEC, JSON> CachingC3p0Json<Data, CODEC, JSON>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    JSON: C3p0Json<Data, CODEC>,
+{
+    /// Creates a cache of at most 1000 entries that expire after 60 seconds
+    pub fn new(inner: JSON) -> Self {
+        CachingC3p0Json {
+            inner,
+            cache: Arc::new(Mutex::new(LruCache::new(DEFAULT_CAPACITY, DEFAULT_TTL))),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sets the maximum number of cached entries; zero disables the cache
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        self.lock_cache().capacity = capacity;
+        self
+    }
+
+    /// Sets how long an entry is cached
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        self.lock_cache().ttl = ttl;
+        self
+    }
+
+    pub fn inner(&self) -> &JSON {
+        &self.inner
+    }
+
+    /// Removes the entry with the given id from the cache
+    pub fn invalidate(&self, id: &IdType) {
+        self.lock_cache().invalidate(Some(id));
+    }
+
+    /// Removes all the entries from the cache
+    pub fn invalidate_all(&self) {
+        self.lock_cache().invalidate(None);
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, LruCache<Data>> {
+        // The cache is consistent even if a thread panicked while holding the lock
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn cached(&self, id: &IdType) -> Option<Model<Data>> {
+        self.lock_cache().get(id, Instant::now())
+    }
+
+    /// Returns the generation of the cache, to be passed to `cache` after the fetch
+    fn generation(&self) -> u64 {
+        self.lock_cache().generation
+    }
+
+    /// Caches a model fetched at the given generation, unless an entry was invalidated meanwhile
+    /// or the entry is being written
+    fn cache(&self, model: &Model<Data>, generation: u64) {
+        let mut cache = self.lock_cache();
+        if cache.generation == generation && !cache.is_written(&model.id) {
+            cache.insert(model.clone(), Instant::now());
+        }
+    }
+
+    /// Invalidates the entry with the given id, or all the entries if it is None, before
+    /// it is written; it is not cached until the transaction of `conn` ends, then it is
+    /// invalidated again
+    fn invalidate_written<Conn: C3p0Hooks>(&self, conn: &Conn, id: Option<IdType>)
+    where
+        Data: 'static,
+    {
+        self.lock_cache().begin_write(id);
+        let cache = self.cache.clone();
+        conn.on_commit(move || async move { end_write(&cache, id) });
+        let cache = self.cache.clone();
+        conn.on_rollback(move || async move { end_write(&cache, id) });
+    }
+
+    /// Invalidates the cached entry if its version differs from the one of the fetched model
+    fn check_version(&self, id: &IdType, model: Option<&Model<Data>>) {
+        let mut cache = self.lock_cache();
+        if cache.version(id) != model.map(|model| model.version) {
+            cache.remove(id);
+        }
+    }
+}
+
+#[async_trait]
+impl<Data, CODEC, JSON> C3p0Json<Data, CODEC> for CachingC3p0Json<Data, CODEC, JSON>
+where
+    Data: 'static + Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Send + Sync,
+    JSON: C3p0Json<Data, CODEC>,
+    JSON::Conn: Send + C3p0Hooks,
+{
+    type Conn = JSON::Conn;
+
+    fn codec(&self) -> &CODEC {
+        self.inner.codec()
+    }
+
+    fn qualified_table_name(&self) -> &str {
+        self.inner.qualified_table_name()
+    }
+
+    async fn create_table_if_not_exists(&self, conn: &mut Self::Conn) -> Result<(), C3p0Error> {
+        self.inner.create_table_if_not_exists(conn).await
+    }
+
+    async fn create_index_if_not_exists(
+        &self,
+        conn: &mut Self::Conn,
+        index: &C3p0Index,
+    ) -> Result<(), C3p0Error> {
+        self.inner.create_index_if_not_exists(conn, index).await
+    }
+
+    async fn drop_table_if_exists(
+        &self,
+        conn: &mut Self::Conn,
+        cascade: bool,
+    ) -> Result<(), C3p0Error> {
+        self.invalidate_written(conn, None);
+        self.inner.drop_table_if_exists(conn, cascade).await
+    }
+
+    async fn count_all(&self, conn: &mut Self::Conn) -> Result<u64, C3p0Error> {
+        self.inner.count_all(conn).await
+    }
+
+    async fn exists_by_id<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+    ) -> Result<bool, C3p0Error> {
+        let id = id.into();
+        if self.cached(id).is_some() {
+            return Ok(true);
+        }
+        self.inner.exists_by_id(conn, id).await
+    }
+
+    async fn fetch_all(&self, conn: &mut Self::Conn) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner.fetch_all(conn).await
+    }
+
+    async fn fetch_all_for_update(
+        &self,
+        conn: &mut Self::Conn,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner.fetch_all_for_update(conn, for_update).await
+    }
+
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner.fetch_all_after_id(conn, after_id, limit).await
+    }
+
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error> {
+        self.inner
+            .fetch_all_values_after_id(conn, after_id, limit)
+            .await
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.inner.is_current(value)
+    }
+
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner.fetch_all_by_field(conn, field_name, value).await
+    }
+
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner
+            .fetch_all_by_field_prefix(conn, field_name, prefix)
+            .await
+    }
+
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<Data>>, C3p0Error> {
+        self.inner
+            .fetch_all_by_max_field_for_update(
+                conn,
+                field_name,
+                max_value,
+                order_by_field,
+                limit,
+                for_update,
+            )
+            .await
+    }
+
+    async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+    ) -> Result<Option<Model<Data>>, C3p0Error> {
+        let id = id.into();
+        if let Some(model) = self.cached(id) {
+            return Ok(Some(model));
+        }
+        let generation = self.generation();
+        let model = self.inner.fetch_one_optional_by_id(conn, id).await?;
+        if let Some(model) = &model {
+            self.cache(model, generation);
+        }
+        Ok(model)
+    }
+
+    async fn fetch_one_optional_by_id_for_update<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+        for_update: &ForUpdate,
+    ) -> Result<Option<Model<Data>>, C3p0Error> {
+        let id = id.into();
+        let model = self
+            .inner
+            .fetch_one_optional_by_id_for_update(conn, id, for_update)
+            .await?;
+        self.check_version(id, model.as_ref());
+        Ok(model)
+    }
+
+    async fn fetch_one_by_id<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+    ) -> Result<Model<Data>, C3p0Error> {
+        let id = id.into();
+        if let Some(model) = self.cached(id) {
+            return Ok(model);
+        }
+        let generation = self.generation();
+        let model = self.inner.fetch_one_by_id(conn, id).await?;
+        self.cache(&model, generation);
+        Ok(model)
+    }
+
+    async fn fetch_one_by_id_for_update<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+        for_update: &ForUpdate,
+    ) -> Result<Model<Data>, C3p0Error> {
+        let id = id.into();
+        let model = self
+            .inner
+            .fetch_one_by_id_for_update(conn, id, for_update)
+            .await;
+        self.check_version(id, model.as_ref().ok());
+        model
+    }
+
+    async fn delete(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<Data>,
+    ) -> Result<Model<Data>, C3p0Error> {
+        self.invalidate_written(conn, Some(obj.id));
+        self.inner.delete(conn, obj).await
+    }
+
+    async fn delete_all(&self, conn: &mut Self::Conn) -> Result<u64, C3p0Error> {
+        self.invalidate_written(conn, None);
+        self.inner.delete_all(conn).await
+    }
+
+    async fn delete_by_id<'a, ID: Into<&'a IdType> + Send>(
+        &'a self,
+        conn: &mut Self::Conn,
+        id: ID,
+    ) -> Result<u64, C3p0Error> {
+        let id = id.into();
+        self.invalidate_written(conn, Some(*id));
+        self.inner.delete_by_id(conn, id).await
+    }
+
+    async fn save(
+        &self,
+        conn: &mut Self::Conn,
+        obj: NewModel<Data>,
+    ) -> Result<Model<Data>, C3p0Error> {
+        let model = self.inner.save(conn, obj).await?;
+        // The id is known only after the insert, that the other transactions cannot see yet
+        self.invalidate_written(conn, Some(model.id));
+        Ok(model)
+    }
+
+    async fn save_with_id(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<Data>,
+    ) -> Result<Model<Data>, C3p0Error> {
+        self.invalidate_written(conn, Some(obj.id));
+        self.inner.save_with_id(conn, obj).await
+    }
+
+    async fn update(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<Data>,
+    ) -> Result<Model<Data>, C3p0Error> {
+        self.invalidate_written(conn, Some(obj.id));
+        self.inner.update(conn, obj).await
+    }
+}
+
+/// Ends a write started by `CachingC3p0Json::invalidate_written`
+fn end_write<Data>(cache: &Mutex<LruCache<Data>>, id: Option<IdType>)
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .end_write(id);
+}
+
+struct CacheEntry<Data>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    model: Model<Data>,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+/// A map of models by id that evicts the least recently used entries
+struct LruCache<Data>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<IdType, CacheEntry<Data>>,
+    // The ids of the entries by their last use
+    recency: BTreeMap<u64, IdType>,
+    tick: u64,
+    /// Incremented when the entries are invalidated
+    generation: u64,
+    /// The number of the writes in progress by id; None stands for all the entries
+    writes: HashMap<Option<IdType>, usize>,
+}
+
+impl<Data> LruCache<Data>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        LruCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            generation: 0,
+            writes: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, id: &IdType, now: Instant) -> Option<Model<Data>> {
+        let tick = self.next_tick();
+        match self.entries.get_mut(id) {
+            Some(entry) if entry.expires_at > now => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(tick, *id);
+                entry.last_used = tick;
+                Some(entry.model.clone())
+            }
+            Some(_) => {
+                self.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn version(&self, id: &IdType) -> Option<VersionType> {
+        self.entries.get(id).map(|entry| entry.model.version)
+    }
+
+    fn insert(&mut self, model: Model<Data>, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let id = model.id;
+        self.remove(&id);
+        while self.entries.len() >= self.capacity {
+            match self.recency.keys().next().copied() {
+                Some(least_used) => {
+                    if let Some(least_used_id) = self.recency.remove(&least_used) {
+                        self.entries.remove(&least_used_id);
+                    }
+                }
+                None => break,
+            }
+        }
+        let tick = self.next_tick();
+        self.recency.insert(tick, id);
+        self.entries.insert(
+            id,
+            CacheEntry {
+                model,
+                expires_at: now + self.ttl,
+                last_used: tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: &IdType) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    /// Removes the entry with the given id, or all the entries if it is None,
+    /// so that the entries fetched meanwhile are not cached
+    fn invalidate(&mut self, id: Option<&IdType>) {
+        self.generation += 1;
+        match id {
+            Some(id) => self.remove(id),
+            None => self.clear(),
+        }
+    }
+
+    fn begin_write(&mut self, id: Option<IdType>) {
+        *self.writes.entry(id).or_insert(0) += 1;
+        self.invalidate(id.as_ref());
+    }
+
+    fn end_write(&mut self, id: Option<IdType>) {
+        if let Some(count) = self.writes.get_mut(&id) {
+            *count -= 1;
+            if *count == 0 {
+                self.writes.remove(&id);
+            }
+        }
+        self.invalidate(id.as_ref());
+    }
+
+    fn is_written(&self, id: &IdType) -> bool {
+        self.writes.contains_key(&Some(*id)) || self.writes.contains_key(&None)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn model(id: IdType, version: i32) -> Model<String> {
+        Model {
+            id,
+            version,
+            data: format!("{}", id),
+        }
+    }
+
+    #[test]
+    fn should_evict_the_least_recently_used_entry() {
+        let now = Instant::now();
+        let mut cache = LruCache::new(2, Duration::from_secs(60));
+        cache.insert(model(1, 0), now);
+        cache.insert(model(2, 0), now);
+        assert!(cache.get(&1, now).is_some());
+
+        cache.insert(model(3, 0), now);
+        assert!(cache.get(&1, now).is_some());
+        assert!(cache.get(&2, now).is_none());
+        assert!(cache.get(&3, now).is_some());
+        assert_eq!(2, cache.entries.len());
+        assert_eq!(2, cache.recency.len());
+    }
+
+    #[test]
+    fn should_expire_the_entries() {
+        let now = Instant::now();
+        let mut cache = LruCache::new(2, Duration::from_secs(60));
+        cache.insert(model(1, 0), now);
+        assert!(cache.get(&1, now + Duration::from_secs(59)).is_some());
+        assert!(cache.get(&1, now + Duration::from_secs(60)).is_none());
+        assert!(cache.entries.is_empty());
+        assert!(cache.recency.is_empty());
+    }
+
+    #[test]
+    fn should_replace_an_entry_with_the_same_id() {
+        let now = Instant::now();
+        let mut cache = LruCache::new(2, Duration::from_secs(60));
+        cache.insert(model(1, 0), now);
+        cache.insert(model(1, 1), now);
+        assert_eq!(Some(1), cache.version(&1));
+        assert_eq!(1, cache.recency.len());
+
+        let mut disabled = LruCache::new(0, Duration::from_secs(60));
+        disabled.insert(model(1, 0), now);
+        assert!(disabled.get(&1, now).is_none());
+    }
+
+    #[test]
+    fn should_track_the_writes_in_progress() {
+        let mut cache = LruCache::<String>::new(2, Duration::from_secs(60));
+        cache.begin_write(Some(1));
+        cache.begin_write(Some(1));
+        assert!(cache.is_written(&1));
+        assert!(!cache.is_written(&2));
+
+        cache.end_write(Some(1));
+        assert!(cache.is_written(&1));
+        cache.end_write(Some(1));
+        assert!(!cache.is_written(&1));
+
+        let generation = cache.generation;
+        cache.begin_write(None);
+        assert!(cache.is_written(&2));
+        cache.end_write(None);
+        assert!(!cache.is_written(&2));
+        assert_eq!(generation + 2, cache.generation);
+    }
+}
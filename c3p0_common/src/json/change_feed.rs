@@ -0,0 +1,144 @@
+use crate::error::C3p0Error;
+use crate::json::builder::C3p0JsonBuilder;
+use crate::json::model::{IdType, VersionType};
+use crate::lock::stable_hash;
+use async_trait::async_trait;
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+
+/// The prefix of the Postgres channels on which the changes of the tables are notified
+pub const CHANGE_FEED_CHANNEL_PREFIX: &str = "c3p0_changes_";
+
+/// The maximum length in bytes of a Postgres identifier, like a channel name
+const PG_MAX_IDENTIFIER_LEN: usize = 63;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A committed change of an entry of a table.
+/// The version is the one of the entry after an insert or an update,
+/// and the one of the deleted entry after a delete.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub table_name: String,
+    pub operation: ChangeOperation,
+    pub id: IdType,
+    pub version: VersionType,
+}
+
+impl ChangeEvent {
+    /// Parses the payload of a change notification
+    pub fn from_payload(payload: &str) -> Result<Self, C3p0Error> {
+        serde_json::from_str(payload).map_err(|err| C3p0Error::InternalError {
+            cause: format!("Invalid change notification payload [{}]: {}", payload, err),
+        })
+    }
+}
+
+pub type ChangeStream = Pin<Box<dyn Stream<Item = Result<ChangeEvent, C3p0Error>> + Send>>;
+
+/// Subscription to the changes of the tables built with `C3p0JsonBuilder::with_change_feed`.
+#[async_trait]
+pub trait C3p0ChangeFeed {
+    /// Returns the stream of the changes committed on the table after the subscription.
+    /// The table name is the one passed to the C3p0JsonBuilder,
+    /// prefixed by the schema name and a dot if the table has a schema (see `change_feed_table_name`).
+    /// If the connection that receives the changes is lost, the stream returns an error and ends:
+    /// the changes committed afterwards are not received, so the subscriber must subscribe again
+    /// and read the table to catch up.
+    async fn subscribe(&self, table_name: &str) -> Result<ChangeStream, C3p0Error>;
+}
+
+/// Returns the name that identifies the table in the change events
+pub fn change_feed_table_name<C3P0>(json_builder: &C3p0JsonBuilder<C3P0>) -> String {
+    match &json_builder.schema_name {
+        Some(schema_name) => format!("{}.{}", schema_name, json_builder.table_name),
+        None => json_builder.table_name.clone(),
+    }
+}
+
+/// Returns the name of the Postgres channel on which the changes of the table are notified.
+/// Postgres rejects channel names longer than 63 bytes, so a longer name is truncated
+/// and suffixed by the hash of the table name.
+pub fn change_feed_channel(table_name: &str) -> String {
+    let channel = format!("{}{}", CHANGE_FEED_CHANNEL_PREFIX, table_name);
+    if channel.len() <= PG_MAX_IDENTIFIER_LEN {
+        return channel;
+    }
+    let hash = format!("_{:016x}", stable_hash(table_name));
+    let mut end = PG_MAX_IDENTIFIER_LEN - hash.len();
+    while !channel.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &channel[..end], hash)
+}
+
+/// Returns the error that ends a change stream when its listener connection is lost
+pub fn listener_closed_error(db: &'static str, table_name: &str) -> C3p0Error {
+    C3p0Error::DbError {
+        db,
+        cause: format!(
+            "The connection listening to the changes of the table [{}] was closed",
+            table_name
+        ),
+        code: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_parse_the_payload() -> Result<(), C3p0Error> {
+        let event = ChangeEvent::from_payload(
+            r#"{"table_name": "my_schema.users", "operation": "UPDATE", "id": 12, "version": 3}"#,
+        )?;
+        assert_eq!(
+            ChangeEvent {
+                table_name: "my_schema.users".to_owned(),
+                operation: ChangeOperation::Update,
+                id: 12,
+                version: 3
+            },
+            event
+        );
+        assert!(ChangeEvent::from_payload(r#"{"operation": "TRUNCATE"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_build_the_channel_name() {
+        let builder = C3p0JsonBuilder::<()>::new("users").with_schema_name("my_schema");
+        assert_eq!("my_schema.users", change_feed_table_name(&builder));
+        assert_eq!(
+            "c3p0_changes_my_schema.users",
+            change_feed_channel(&change_feed_table_name(&builder))
+        );
+    }
+
+    #[test]
+    fn should_hash_the_long_channel_names() {
+        let table_name = format!("my_schema.{}", "users_".repeat(10));
+        let channel = change_feed_channel(&table_name);
+        assert_eq!(63, channel.len());
+        assert!(channel.starts_with("c3p0_changes_my_schema.users_users_"));
+        assert_eq!(channel, change_feed_channel(&table_name));
+        assert_ne!(
+            channel,
+            change_feed_channel(&format!("my_schema.{}", "users_".repeat(11)))
+        );
+
+        let table_name = "à".repeat(30);
+        let channel = change_feed_channel(&table_name);
+        assert!(channel.len() <= 63);
+        assert!(channel.starts_with("c3p0_changes_à"));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::C3p0Error;
 use serde_json::Value;
+use std::sync::Arc;
 
 pub trait JsonCodec<Data>: Clone + Send + Sync
 where
@@ -7,6 +8,17 @@ where
 {
     fn from_value(&self, value: Value) -> Result<Data, C3p0Error>;
     fn to_value(&self, data: &Data) -> Result<Value, C3p0Error>;
+
+    /// Returns whether a stored value is already in the format written by the codec,
+    /// so that it does not need to be rewritten.
+    /// By default the value is decoded and encoded again, and compared with itself;
+    /// the codecs whose output is not deterministic must override it.
+    fn is_current(&self, value: &Value) -> bool {
+        match self.from_value(value.clone()) {
+            Ok(data) => self.to_value(&data).is_ok_and(|current| &current == value),
+            Err(_) => false,
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -24,3 +36,413 @@ where
         serde_json::to_value(data).map_err(C3p0Error::from)
     }
 }
+
+impl<Data, CODEC> JsonCodec<Data> for &CODEC
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    CODEC: JsonCodec<Data>,
+{
+    fn from_value(&self, value: Value) -> Result<Data, C3p0Error> {
+        (*self).from_value(value)
+    }
+
+    fn to_value(&self, data: &Data) -> Result<Value, C3p0Error> {
+        (*self).to_value(data)
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        (*self).is_current(value)
+    }
+}
+
+/// The name of the field that contains the version tag of the documents
+/// written by a VersionedCodec
+pub const DEFAULT_VERSION_TAG_FIELD: &str = "@json_tag";
+
+type Upgrader = Arc<dyn Fn(Value) -> Result<Value, C3p0Error> + Send + Sync>;
+
+#[derive(Clone)]
+struct Version {
+    tag: String,
+    upgrade_to_next: Option<Upgrader>,
+}
+
+/// A JsonCodec that keeps track of the historical versions of the data.
+/// Each version is identified by a tag stored in the document (`@json_tag` by default)
+/// and is registered together with the function that upgrades the previous version to it
+/// (e.g. `VersionedCodec::<UserV1>::new("V1").with_version("V2", |user: UserV1| UserV2::from(user))`).
+/// Documents are always written with the tag of the latest version, while documents tagged with
+/// an older version are upgraded through the chain when read.
+/// Documents without a tag are read as the first version, unless `with_untagged_version` is used.
+/// The tag is compatible with the one produced by a serde enum annotated with `#[serde(tag = "@json_tag")]`.
+pub struct VersionedCodec<Data> {
+    tag_field: String,
+    untagged_version: Option<String>,
+    versions: Vec<Version>,
+    phantom_data: std::marker::PhantomData<fn() -> Data>,
+}
+
+impl<Data> Clone for VersionedCodec<Data> {
+    fn clone(&self) -> Self {
+        VersionedCodec {
+            tag_field: self.tag_field.clone(),
+            untagged_version: self.untagged_version.clone(),
+            versions: self.versions.clone(),
+            phantom_data: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<Data> VersionedCodec<Data>
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send + 'static,
+{
+    /// Creates a codec whose first version has the given tag
+    pub fn new<S: Into<String>>(tag: S) -> Self {
+        VersionedCodec {
+            tag_field: DEFAULT_VERSION_TAG_FIELD.to_owned(),
+            untagged_version: None,
+            versions: vec![Version {
+                tag: tag.into(),
+                upgrade_to_next: None,
+            }],
+            phantom_data: std::marker::PhantomData,
+        }
+    }
+
+    /// Registers a new latest version with the function that upgrades the current latest version to it
+    pub fn with_version<Next, S, F>(mut self, tag: S, upgrade: F) -> VersionedCodec<Next>
+    where
+        Next: serde::ser::Serialize + serde::de::DeserializeOwned + Send + 'static,
+        S: Into<String>,
+        F: Fn(Data) -> Next + Send + Sync + 'static,
+    {
+        if let Some(latest) = self.versions.last_mut() {
+            latest.upgrade_to_next = Some(Arc::new(move |value| {
+                let previous = serde_json::from_value::<Data>(value)?;
+                Ok(serde_json::to_value(upgrade(previous))?)
+            }));
+        }
+        self.versions.push(Version {
+            tag: tag.into(),
+            upgrade_to_next: None,
+        });
+        VersionedCodec {
+            tag_field: self.tag_field,
+            untagged_version: self.untagged_version,
+            versions: self.versions,
+            phantom_data: std::marker::PhantomData,
+        }
+    }
+
+    pub fn with_tag_field<S: Into<String>>(mut self, tag_field: S) -> Self {
+        self.tag_field = tag_field.into();
+        self
+    }
+
+    /// Sets the version of the documents that have no tag
+    pub fn with_untagged_version<S: Into<String>>(mut self, tag: S) -> Self {
+        self.untagged_version = Some(tag.into());
+        self
+    }
+
+    /// The tag of the latest version
+    pub fn latest_tag(&self) -> &str {
+        self.versions
+            .last()
+            .map(|version| version.tag.as_str())
+            .unwrap_or_default()
+    }
+
+    fn position(&self, tag: Option<&str>) -> Result<usize, C3p0Error> {
+        let tag = match tag.or(self.untagged_version.as_deref()) {
+            Some(tag) => tag,
+            None => return Ok(0),
+        };
+        self.versions
+            .iter()
+            .position(|version| version.tag == tag)
+            .ok_or_else(|| C3p0Error::VersioningError {
+                cause: format!(
+                    "Unknown version tag [{}] in field [{}]. Known tags: {:?}",
+                    tag,
+                    self.tag_field,
+                    self.versions
+                        .iter()
+                        .map(|version| version.tag.as_str())
+                        .collect::<Vec<_>>()
+                ),
+            })
+    }
+}
+
+impl<Data> JsonCodec<Data> for VersionedCodec<Data>
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send + 'static,
+{
+    fn from_value(&self, mut value: Value) -> Result<Data, C3p0Error> {
+        let tag = match &mut value {
+            Value::Object(map) => match map.remove(&self.tag_field) {
+                Some(Value::String(tag)) => Some(tag),
+                Some(other) => {
+                    return Err(C3p0Error::VersioningError {
+                        cause: format!(
+                            "The version tag in field [{}] is not a string: [{}]",
+                            self.tag_field, other
+                        ),
+                    })
+                }
+                None => None,
+            },
+            _ => None,
+        };
+
+        let position = self.position(tag.as_deref())?;
+        for version in &self.versions[position..] {
+            if let Some(upgrade) = &version.upgrade_to_next {
+                value = upgrade(value)?;
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    fn to_value(&self, data: &Data) -> Result<Value, C3p0Error> {
+        match serde_json::to_value(data)? {
+            Value::Object(mut map) => {
+                map.insert(
+                    self.tag_field.clone(),
+                    Value::String(self.latest_tag().to_owned()),
+                );
+                Ok(Value::Object(map))
+            }
+            other => Err(C3p0Error::VersioningError {
+                cause: format!(
+                    "Only data serialized as a JSON object can be versioned. Found: [{}]",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+/// A codec that stores the data as an opaque array of bytes
+/// (e.g. bytea in Postgres and LONGBLOB in MySql) instead of JSON.
+pub trait BinaryCodec<Data>: Clone + Send + Sync
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    fn decode_bytes(&self, bytes: &[u8]) -> Result<Data, C3p0Error>;
+    fn encode_bytes(&self, data: &Data) -> Result<Vec<u8>, C3p0Error>;
+}
+
+/// A BinaryCodec that serializes the data with MessagePack
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Default)]
+pub struct MsgPackCodec {}
+
+#[cfg(feature = "msgpack")]
+impl<Data> BinaryCodec<Data> for MsgPackCodec
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    fn decode_bytes(&self, bytes: &[u8]) -> Result<Data, C3p0Error> {
+        rmp_serde::from_slice(bytes).map_err(|err| C3p0Error::BinaryProcessingError {
+            cause: format!("{}", err),
+        })
+    }
+
+    fn encode_bytes(&self, data: &Data) -> Result<Vec<u8>, C3p0Error> {
+        rmp_serde::to_vec_named(data).map_err(|err| C3p0Error::BinaryProcessingError {
+            cause: format!("{}", err),
+        })
+    }
+}
+
+/// A BinaryCodec that serializes the data with CBOR
+#[cfg(feature = "cbor")]
+#[derive(Clone, Default)]
+pub struct CborCodec {}
+
+#[cfg(feature = "cbor")]
+impl<Data> BinaryCodec<Data> for CborCodec
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    fn decode_bytes(&self, bytes: &[u8]) -> Result<Data, C3p0Error> {
+        ciborium::de::from_reader(bytes).map_err(|err| C3p0Error::BinaryProcessingError {
+            cause: format!("{}", err),
+        })
+    }
+
+    fn encode_bytes(&self, data: &Data) -> Result<Vec<u8>, C3p0Error> {
+        let mut bytes = vec![];
+        ciborium::ser::into_writer(data, &mut bytes).map_err(|err| {
+            C3p0Error::BinaryProcessingError {
+                cause: format!("{}", err),
+            }
+        })?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct UserV1 {
+        username: String,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct UserV2 {
+        username: String,
+        age: u32,
+    }
+
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct UserV3 {
+        name: String,
+        age: u32,
+    }
+
+    fn versioned_codec() -> VersionedCodec<UserV3> {
+        VersionedCodec::<UserV1>::new("V1")
+            .with_version("V2", |user: UserV1| UserV2 {
+                username: user.username,
+                age: 18,
+            })
+            .with_version("V3", |user: UserV2| UserV3 {
+                name: user.username,
+                age: user.age,
+            })
+    }
+
+    #[test]
+    fn versioned_codec_should_write_the_latest_tag() -> Result<(), C3p0Error> {
+        let codec = versioned_codec();
+        let user = UserV3 {
+            name: "name".to_owned(),
+            age: 30,
+        };
+
+        let value = codec.to_value(&user)?;
+        assert_eq!(json!({"@json_tag": "V3", "name": "name", "age": 30}), value);
+        assert_eq!(user, codec.from_value(value)?);
+        Ok(())
+    }
+
+    #[test]
+    fn versioned_codec_should_upgrade_through_the_chain() -> Result<(), C3p0Error> {
+        let codec = versioned_codec();
+
+        let user: UserV3 = codec.from_value(json!({"@json_tag": "V1", "username": "v1"}))?;
+        assert_eq!(
+            UserV3 {
+                name: "v1".to_owned(),
+                age: 18
+            },
+            user
+        );
+
+        let user: UserV3 =
+            codec.from_value(json!({"@json_tag": "V2", "username": "v2", "age": 40}))?;
+        assert_eq!(
+            UserV3 {
+                name: "v2".to_owned(),
+                age: 40
+            },
+            user
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn versioned_codec_should_read_untagged_values() -> Result<(), C3p0Error> {
+        let user: UserV3 = versioned_codec().from_value(json!({"username": "v1"}))?;
+        assert_eq!("v1", user.name);
+
+        let user: UserV3 = versioned_codec()
+            .with_untagged_version("V2")
+            .from_value(json!({"username": "v2", "age": 40}))?;
+        assert_eq!(40, user.age);
+        Ok(())
+    }
+
+    #[test]
+    fn versioned_codec_should_fail_on_unknown_tags() {
+        let result: Result<UserV3, C3p0Error> =
+            versioned_codec().from_value(json!({"@json_tag": "V4", "name": "v4", "age": 1}));
+        match result {
+            Err(C3p0Error::VersioningError { cause }) => assert!(cause.contains("[V4]")),
+            _ => panic!("Expected a VersioningError"),
+        }
+    }
+
+    #[test]
+    fn versioned_codec_should_use_a_custom_tag_field() -> Result<(), C3p0Error> {
+        let codec = VersionedCodec::<UserV1>::new("V1").with_tag_field("_v");
+        let value = codec.to_value(&UserV1 {
+            username: "name".to_owned(),
+        })?;
+        assert_eq!(json!({"_v": "V1", "username": "name"}), value);
+        Ok(())
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+    struct NumericData {
+        name: String,
+        values: Vec<f64>,
+    }
+
+    #[cfg(any(feature = "msgpack", feature = "cbor"))]
+    fn numeric_data() -> NumericData {
+        NumericData {
+            name: "test".to_owned(),
+            values: (0..1000).map(|i| f64::from(i) / 3.0).collect(),
+        }
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_codec_should_encode_and_decode() -> Result<(), C3p0Error> {
+        let codec = MsgPackCodec {};
+        let data = numeric_data();
+
+        let bytes = codec.encode_bytes(&data)?;
+        let decoded: NumericData = codec.decode_bytes(&bytes)?;
+
+        assert_eq!(data, decoded);
+        assert!(bytes.len() < serde_json::to_vec(&data)?.len());
+        Ok(())
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_codec_should_encode_and_decode() -> Result<(), C3p0Error> {
+        let codec = CborCodec {};
+        let data = numeric_data();
+
+        let bytes = codec.encode_bytes(&data)?;
+        let decoded: NumericData = codec.decode_bytes(&bytes)?;
+
+        assert_eq!(data, decoded);
+        assert!(bytes.len() < serde_json::to_vec(&data)?.len());
+        Ok(())
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn binary_codec_should_fail_on_corrupted_bytes() {
+        let codec = MsgPackCodec {};
+        let result: Result<NumericData, C3p0Error> = codec.decode_bytes(&[0xc1, 0x00]);
+        match result {
+            Err(C3p0Error::BinaryProcessingError { .. }) => {}
+            _ => panic!("Expected a BinaryProcessingError"),
+        }
+    }
+}
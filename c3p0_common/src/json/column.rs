@@ -0,0 +1,143 @@
+use crate::json::codec::{BinaryCodec, JsonCodec};
+use crate::json::compression::{check_fields_queryable, CompressingCodec, Compression};
+use crate::json::schema::{JsonSchema, ValidatingCodec};
+use crate::{C3p0Error, C3p0Index};
+use log::*;
+use serde_json::Value;
+
+/// The column that stores the documents of a table.
+/// It encodes the data with the codec of the table into the values stored in the column,
+/// so every backend has a single implementation for the JSON and the binary documents.
+pub trait DataColumn<Data, Codec>: Clone + Send + Sync
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    /// The type of the values stored in the column
+    type Stored: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync + 'static;
+
+    fn encode(&self, codec: &Codec, data: &Data) -> Result<Self::Stored, C3p0Error>;
+
+    fn decode(&self, codec: &Codec, stored: Self::Stored) -> Result<Data, C3p0Error>;
+
+    /// Returns the stored value as returned by `C3p0Json::fetch_all_values_after_id`
+    fn to_value(&self, stored: Self::Stored) -> Result<Value, C3p0Error>;
+
+    /// Returns whether a value returned by `to_value` is the one that `encode` writes now
+    /// (see `C3p0Json::is_current`)
+    fn is_current(&self, codec: &Codec, value: &Value) -> bool;
+
+    /// Returns whether the database can query the top level fields of the stored documents;
+    /// if it cannot, the entries are selected in memory.
+    /// It fails if the fields cannot be queried at all.
+    fn fields_queryable(&self, table_name: &str, operation: &str) -> Result<bool, C3p0Error>;
+}
+
+/// A column of JSON documents, validated by the schema and compressed, if any
+#[derive(Clone, Default)]
+pub struct JsonColumn {
+    pub compression: Option<Compression>,
+    pub schema: Option<JsonSchema>,
+}
+
+impl JsonColumn {
+    /// The codec used to read and write the stored documents.
+    /// It applies the table schema validation and compression on top of the user codec.
+    pub fn storage_codec<'a, Codec>(
+        &'a self,
+        codec: &'a Codec,
+    ) -> CompressingCodec<'a, ValidatingCodec<'a, Codec>> {
+        CompressingCodec::new(
+            ValidatingCodec::new(codec, self.schema.as_ref()),
+            self.compression.as_ref(),
+        )
+    }
+}
+
+impl<Data, Codec> DataColumn<Data, Codec> for JsonColumn
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    Codec: JsonCodec<Data>,
+{
+    type Stored = Value;
+
+    fn encode(&self, codec: &Codec, data: &Data) -> Result<Value, C3p0Error> {
+        self.storage_codec(codec).to_value(data)
+    }
+
+    fn decode(&self, codec: &Codec, stored: Value) -> Result<Data, C3p0Error> {
+        self.storage_codec(codec).from_value(stored)
+    }
+
+    /// The compressed documents are returned in their envelope
+    fn to_value(&self, stored: Value) -> Result<Value, C3p0Error> {
+        Ok(stored)
+    }
+
+    fn is_current(&self, codec: &Codec, value: &Value) -> bool {
+        self.storage_codec(codec).is_current(value)
+    }
+
+    fn fields_queryable(&self, table_name: &str, operation: &str) -> Result<bool, C3p0Error> {
+        check_fields_queryable(self.compression.as_ref(), table_name, operation)?;
+        Ok(true)
+    }
+}
+
+/// A column of opaque arrays of bytes encoded by a `BinaryCodec`
+/// (e.g. bytea in Postgres and LONGBLOB in MySql).
+/// The fields of its documents cannot be queried by the database.
+#[derive(Clone, Default)]
+pub struct BinaryColumn {}
+
+impl<Data, Codec> DataColumn<Data, Codec> for BinaryColumn
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    Codec: BinaryCodec<Data>,
+{
+    type Stored = Vec<u8>;
+
+    fn encode(&self, codec: &Codec, data: &Data) -> Result<Vec<u8>, C3p0Error> {
+        codec.encode_bytes(data)
+    }
+
+    fn decode(&self, codec: &Codec, stored: Vec<u8>) -> Result<Data, C3p0Error> {
+        codec.decode_bytes(&stored)
+    }
+
+    /// The bytes are returned as a JSON array
+    fn to_value(&self, stored: Vec<u8>) -> Result<Value, C3p0Error> {
+        Ok(Value::from(stored))
+    }
+
+    fn is_current(&self, codec: &Codec, value: &Value) -> bool {
+        serde_json::from_value::<Vec<u8>>(value.clone())
+            .ok()
+            .and_then(|bytes| {
+                let data = codec.decode_bytes(&bytes).ok()?;
+                Some(codec.encode_bytes(&data).ok()? == bytes)
+            })
+            .unwrap_or(false)
+    }
+
+    fn fields_queryable(&self, _table_name: &str, _operation: &str) -> Result<bool, C3p0Error> {
+        Ok(false)
+    }
+}
+
+/// Skips an index on a column whose fields cannot be queried; it fails if the index is unique,
+/// because it could not be enforced
+pub fn skip_index(table_name: &str, index: &C3p0Index) -> Result<(), C3p0Error> {
+    if index.unique {
+        return Err(C3p0Error::InternalError {
+            cause: format!(
+                "Unique indexes are not supported on binary documents. Table [{}], field [{}]",
+                table_name, &index.field_name
+            ),
+        });
+    }
+    warn!(
+        "Indexes are not supported on binary documents. Index on table [{}], field [{}] skipped",
+        table_name, &index.field_name
+    );
+    Ok(())
+}
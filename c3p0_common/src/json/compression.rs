@@ -0,0 +1,312 @@
+use crate::error::C3p0Error;
+use crate::json::codec::JsonCodec;
+use serde_json::{Map, Value};
+
+const ENVELOPE_FIELD: &str = "@c3p0_envelope";
+const COMPRESSION_FIELD: &str = "@c3p0_compression";
+const PAYLOAD_FIELD: &str = "@c3p0_payload";
+
+/// The version of the compressed document envelope
+const ENVELOPE_VERSION: u64 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompressionAlgorithm {
+    Zstd { level: i32 },
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    fn name(&self) -> &str {
+        match self {
+            CompressionAlgorithm::Zstd { .. } => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+        }
+    }
+}
+
+/// The compression applied to the documents of a table.
+/// Documents whose JSON representation is at least `threshold_bytes` long are compressed
+/// and stored in an envelope object that marks them as compressed;
+/// smaller documents are stored as they are.
+///
+/// The envelope is a JSON document too, so the compressed bytes are stored base64 encoded,
+/// about a third larger: the compression saves space only for the documents that compress
+/// to less than three quarters of their size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    pub threshold_bytes: usize,
+}
+
+impl Compression {
+    #[cfg(feature = "zstd")]
+    pub fn zstd(threshold_bytes: usize) -> Self {
+        Compression {
+            algorithm: CompressionAlgorithm::Zstd {
+                level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            },
+            threshold_bytes,
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    pub fn lz4(threshold_bytes: usize) -> Self {
+        Compression {
+            algorithm: CompressionAlgorithm::Lz4,
+            threshold_bytes,
+        }
+    }
+
+    /// Compresses the value if its size reaches the threshold
+    pub fn compress(&self, value: Value) -> Result<Value, C3p0Error> {
+        let bytes = serde_json::to_vec(&value)?;
+        if bytes.len() < self.threshold_bytes {
+            return Ok(value);
+        }
+
+        let compressed = compress_bytes(&self.algorithm, &bytes)?;
+
+        let mut envelope = Map::new();
+        envelope.insert(ENVELOPE_FIELD.to_owned(), Value::from(ENVELOPE_VERSION));
+        envelope.insert(
+            COMPRESSION_FIELD.to_owned(),
+            Value::String(self.algorithm.name().to_owned()),
+        );
+        envelope.insert(
+            PAYLOAD_FIELD.to_owned(),
+            Value::String(base64::encode(&compressed)),
+        );
+        Ok(Value::Object(envelope))
+    }
+}
+
+/// Returns an error if the table has compression, because its documents can be stored compressed:
+/// they cannot be queried or indexed by field
+pub fn check_fields_queryable(
+    compression: Option<&Compression>,
+    table_name: &str,
+    operation: &str,
+) -> Result<(), C3p0Error> {
+    match compression {
+        Some(_) => Err(C3p0Error::CompressionError {
+            cause: format!(
+                "[{}] is not supported on the table [{}] because the documents are compressed",
+                operation, table_name
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Returns whether the value is a compressed document envelope: an object with exactly
+/// the fields of the envelope, and its version, so that it is not confused with a document
+/// that has some of them
+pub fn is_compressed(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => {
+            map.len() == 3
+                && map.get(ENVELOPE_FIELD).and_then(Value::as_u64) == Some(ENVELOPE_VERSION)
+                && map.contains_key(COMPRESSION_FIELD)
+                && map.contains_key(PAYLOAD_FIELD)
+        }
+        _ => false,
+    }
+}
+
+/// Decompresses the value if it is a compressed document envelope;
+/// every other value is returned unchanged.
+/// This allows compressed and uncompressed documents to coexist in the same table.
+pub fn decompress(value: Value) -> Result<Value, C3p0Error> {
+    if !is_compressed(&value) {
+        return Ok(value);
+    }
+
+    let algorithm = match value.get(COMPRESSION_FIELD).and_then(Value::as_str) {
+        Some("zstd") => CompressionAlgorithm::Zstd { level: 0 },
+        Some("lz4") => CompressionAlgorithm::Lz4,
+        other => {
+            return Err(C3p0Error::CompressionError {
+                cause: format!("Unknown compression algorithm [{:?}]", other),
+            })
+        }
+    };
+
+    let payload = value
+        .get(PAYLOAD_FIELD)
+        .and_then(Value::as_str)
+        .ok_or_else(|| C3p0Error::CompressionError {
+            cause: "The compressed payload is not a string".to_owned(),
+        })?;
+    let compressed = base64::decode(payload).map_err(|err| C3p0Error::CompressionError {
+        cause: format!("The compressed payload is not valid base64. Err: {}", err),
+    })?;
+
+    let bytes = decompress_bytes(&algorithm, &compressed)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[allow(unused_variables)]
+fn compress_bytes(algorithm: &CompressionAlgorithm, bytes: &[u8]) -> Result<Vec<u8>, C3p0Error> {
+    match algorithm {
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd { level } => {
+            zstd::encode_all(bytes, *level).map_err(|err| C3p0Error::CompressionError {
+                cause: format!("{}", err),
+            })
+        }
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => Ok(lz4_flex::compress_prepend_size(bytes)),
+        #[allow(unreachable_patterns)]
+        _ => Err(feature_not_enabled(algorithm)),
+    }
+}
+
+#[allow(unused_variables)]
+fn decompress_bytes(algorithm: &CompressionAlgorithm, bytes: &[u8]) -> Result<Vec<u8>, C3p0Error> {
+    match algorithm {
+        #[cfg(feature = "zstd")]
+        CompressionAlgorithm::Zstd { .. } => {
+            zstd::decode_all(bytes).map_err(|err| C3p0Error::CompressionError {
+                cause: format!("{}", err),
+            })
+        }
+        #[cfg(feature = "lz4")]
+        CompressionAlgorithm::Lz4 => {
+            lz4_flex::decompress_size_prepended(bytes).map_err(|err| C3p0Error::CompressionError {
+                cause: format!("{}", err),
+            })
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(feature_not_enabled(algorithm)),
+    }
+}
+
+fn feature_not_enabled(algorithm: &CompressionAlgorithm) -> C3p0Error {
+    C3p0Error::CompressionError {
+        cause: format!(
+            "C3p0 was compiled without the [{}] feature",
+            algorithm.name()
+        ),
+    }
+}
+
+/// A JsonCodec that wraps the codec of a table and applies its compression.
+/// Values are decompressed on read even when the table has no compression configured,
+/// so that the compression can be enabled and disabled without rewriting the stored data.
+#[derive(Clone)]
+pub struct CompressingCodec<'a, CODEC> {
+    codec: CODEC,
+    compression: Option<&'a Compression>,
+}
+
+impl<'a, CODEC> CompressingCodec<'a, CODEC> {
+    pub fn new(codec: CODEC, compression: Option<&'a Compression>) -> Self {
+        CompressingCodec { codec, compression }
+    }
+}
+
+impl<'a, Data, CODEC> JsonCodec<Data> for CompressingCodec<'a, CODEC>
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    CODEC: JsonCodec<Data>,
+{
+    fn from_value(&self, value: Value) -> Result<Data, C3p0Error> {
+        self.codec.from_value(decompress(value)?)
+    }
+
+    fn to_value(&self, data: &Data) -> Result<Value, C3p0Error> {
+        let value = self.codec.to_value(data)?;
+        match self.compression {
+            Some(compression) => compression.compress(value),
+            None => Ok(value),
+        }
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        let decompressed = match decompress(value.clone()) {
+            Ok(decompressed) => decompressed,
+            Err(_) => return false,
+        };
+        let algorithm = match self.compression {
+            Some(compression) => match serde_json::to_vec(&decompressed) {
+                Ok(bytes) if bytes.len() >= compression.threshold_bytes => {
+                    Some(compression.algorithm.name())
+                }
+                Ok(_) => None,
+                Err(_) => return false,
+            },
+            None => None,
+        };
+        let compressed_with = if is_compressed(value) {
+            value.get(COMPRESSION_FIELD).and_then(Value::as_str)
+        } else {
+            None
+        };
+        compressed_with == algorithm && self.codec.is_current(&decompressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn should_not_touch_uncompressed_values() -> Result<(), C3p0Error> {
+        let value = json!({"name": "test", "values": [1, 2, 3]});
+        assert!(!is_compressed(&value));
+        assert_eq!(value, decompress(value.clone())?);
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn should_compress_with_zstd_above_threshold() -> Result<(), C3p0Error> {
+        should_compress_above_threshold(Compression::zstd(100))
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn should_compress_with_lz4_above_threshold() -> Result<(), C3p0Error> {
+        should_compress_above_threshold(Compression::lz4(100))
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    fn should_compress_above_threshold(compression: Compression) -> Result<(), C3p0Error> {
+        let small = json!({"name": "test"});
+        assert_eq!(small, compression.compress(small.clone())?);
+
+        let large = json!({
+            "events": (0..500).map(|i| json!({"type": "audit", "index": i % 3})).collect::<Vec<_>>()
+        });
+        let compressed = compression.compress(large.clone())?;
+        assert!(is_compressed(&compressed));
+        assert!(serde_json::to_vec(&compressed)?.len() < serde_json::to_vec(&large)?.len());
+
+        assert_eq!(large, decompress(compressed)?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_touch_values_with_some_envelope_fields() -> Result<(), C3p0Error> {
+        for value in &[
+            json!({COMPRESSION_FIELD: "zstd", PAYLOAD_FIELD: "not base64"}),
+            json!({ENVELOPE_FIELD: 2, COMPRESSION_FIELD: "zstd", PAYLOAD_FIELD: "not base64"}),
+            json!({ENVELOPE_FIELD: 1, COMPRESSION_FIELD: "zstd", PAYLOAD_FIELD: "", "name": "test"}),
+        ] {
+            assert!(!is_compressed(value));
+            assert_eq!(value, &decompress(value.clone())?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_on_unknown_algorithm() {
+        let value = json!({ENVELOPE_FIELD: 1, COMPRESSION_FIELD: "unknown", PAYLOAD_FIELD: ""});
+        match decompress(value) {
+            Err(C3p0Error::CompressionError { .. }) => {}
+            _ => panic!("Expected a CompressionError"),
+        }
+    }
+}
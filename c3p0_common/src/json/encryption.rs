@@ -0,0 +1,517 @@
+use crate::error::C3p0Error;
+use crate::json::codec::JsonCodec;
+use crate::json::rewrite::{rewrite_all, RewriteProgress};
+use crate::json::C3p0Json;
+use crate::pool::C3p0Pool;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const ENCRYPTION_FIELD: &str = "@c3p0_encryption";
+const KEY_ID_FIELD: &str = "@c3p0_key_id";
+const NONCE_FIELD: &str = "@c3p0_nonce";
+const PAYLOAD_FIELD: &str = "@c3p0_payload";
+
+/// Both the supported algorithms use 96 bit nonces
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncryptionAlgorithm {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+    fn name(&self) -> &str {
+        match self {
+            EncryptionAlgorithm::Aes256Gcm => "aes-256-gcm",
+            EncryptionAlgorithm::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self, C3p0Error> {
+        match name {
+            "aes-256-gcm" => Ok(EncryptionAlgorithm::Aes256Gcm),
+            "chacha20-poly1305" => Ok(EncryptionAlgorithm::ChaCha20Poly1305),
+            other => Err(C3p0Error::EncryptionError {
+                cause: format!("Unknown encryption algorithm [{}]", other),
+            }),
+        }
+    }
+
+    fn encrypt(
+        &self,
+        key: &EncryptionKey,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), C3p0Error> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let result = match self {
+            EncryptionAlgorithm::Aes256Gcm => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                Aes256Gcm::new(&key.0.into())
+                    .encrypt(&nonce, payload)
+                    .map(|ciphertext| (nonce.to_vec(), ciphertext))
+            }
+            EncryptionAlgorithm::ChaCha20Poly1305 => {
+                let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+                ChaCha20Poly1305::new(&key.0.into())
+                    .encrypt(&nonce, payload)
+                    .map(|ciphertext| (nonce.to_vec(), ciphertext))
+            }
+        };
+        result.map_err(|_| C3p0Error::EncryptionError {
+            cause: format!("Failed to encrypt value with [{}]", self.name()),
+        })
+    }
+
+    fn decrypt(
+        &self,
+        key: &EncryptionKey,
+        aad: &[u8],
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, C3p0Error> {
+        if nonce.len() != NONCE_LEN {
+            return Err(C3p0Error::EncryptionError {
+                cause: format!("Wrong nonce length [{}]", nonce.len()),
+            });
+        }
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let result = match self {
+            EncryptionAlgorithm::Aes256Gcm => {
+                Aes256Gcm::new(&key.0.into()).decrypt(nonce.into(), payload)
+            }
+            EncryptionAlgorithm::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(&key.0.into()).decrypt(nonce.into(), payload)
+            }
+        };
+        result.map_err(|_| C3p0Error::EncryptionError {
+            cause: format!(
+                "Failed to decrypt value with [{}]: wrong key or corrupted data",
+                self.name()
+            ),
+        })
+    }
+}
+
+/// A 256 bit key usable by all the supported encryption algorithms
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        EncryptionKey(key)
+    }
+
+    pub fn from_slice(key: &[u8]) -> Result<Self, C3p0Error> {
+        if key.len() != 32 {
+            return Err(C3p0Error::EncryptionError {
+                cause: format!(
+                    "The encryption key must be 32 bytes long but it is [{}]",
+                    key.len()
+                ),
+            });
+        }
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(key);
+        Ok(EncryptionKey(bytes))
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(***)")
+    }
+}
+
+/// Provides the keys used by the EncryptingCodec.
+/// Every encrypted value is tagged with the id of the key used to encrypt it;
+/// new values are always encrypted with the current key, while the old keys
+/// must be kept available until all the values encrypted with them have been rewritten.
+pub trait KeyProvider: Clone + Send + Sync {
+    /// The id of the key to be used to encrypt new values
+    fn current_key_id(&self) -> &str;
+
+    /// Returns the key with the given id
+    fn key(&self, key_id: &str) -> Result<EncryptionKey, C3p0Error>;
+}
+
+/// A KeyProvider backed by a fixed set of keys
+#[derive(Clone, Debug)]
+pub struct StaticKeyProvider {
+    current_key_id: String,
+    keys: Arc<HashMap<String, EncryptionKey>>,
+}
+
+impl StaticKeyProvider {
+    pub fn new<S: Into<String>>(current_key_id: S, current_key: EncryptionKey) -> Self {
+        let current_key_id = current_key_id.into();
+        let mut keys = HashMap::new();
+        keys.insert(current_key_id.clone(), current_key);
+        StaticKeyProvider {
+            current_key_id,
+            keys: Arc::new(keys),
+        }
+    }
+
+    /// Adds a key that can be used only to decrypt existing values
+    pub fn with_key<S: Into<String>>(mut self, key_id: S, key: EncryptionKey) -> Self {
+        Arc::make_mut(&mut self.keys).insert(key_id.into(), key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key_id(&self) -> &str {
+        &self.current_key_id
+    }
+
+    fn key(&self, key_id: &str) -> Result<EncryptionKey, C3p0Error> {
+        self.keys
+            .get(key_id)
+            .cloned()
+            .ok_or_else(|| C3p0Error::EncryptionError {
+                cause: format!("Unknown encryption key id [{}]", key_id),
+            })
+    }
+}
+
+/// A JsonCodec that wraps another codec and encrypts the values of the configured fields.
+/// The fields are identified by JSON pointers (e.g. "/email" or "/address/street");
+/// each of them is replaced by an envelope object that contains the algorithm, the key id,
+/// the nonce and the encrypted value. The path of the field is used as associated data,
+/// so an encrypted value cannot be moved to a different field.
+/// The codec does not know the table and the id of the rows, so the encrypted values are not
+/// bound to them: an envelope copied to the same field of another row, or of another table
+/// encrypted with the same keys, is decrypted successfully.
+/// Fields that are not encrypted are returned unchanged on read; this allows
+/// enabling the encryption on a table that already contains plaintext data.
+#[derive(Clone)]
+pub struct EncryptingCodec<CODEC, KP> {
+    codec: CODEC,
+    key_provider: KP,
+    algorithm: EncryptionAlgorithm,
+    paths: Vec<String>,
+}
+
+impl<CODEC, KP: KeyProvider> EncryptingCodec<CODEC, KP> {
+    pub fn new(codec: CODEC, key_provider: KP) -> Self {
+        EncryptingCodec {
+            codec,
+            key_provider,
+            algorithm: EncryptionAlgorithm::Aes256Gcm,
+            paths: vec![],
+        }
+    }
+
+    pub fn with_algorithm(mut self, algorithm: EncryptionAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_encrypted_path<S: Into<String>>(mut self, path: S) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Encrypts the configured fields of the value.
+    /// Missing and null fields are left untouched.
+    pub fn encrypt_value(&self, mut value: Value) -> Result<Value, C3p0Error> {
+        for path in &self.paths {
+            if let Some(field) = value.pointer_mut(path) {
+                if !field.is_null() && !is_encrypted(field) {
+                    let encrypted = self.encrypt_field(path, field)?;
+                    *field = encrypted;
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Decrypts the configured fields of the value
+    pub fn decrypt_value(&self, mut value: Value) -> Result<Value, C3p0Error> {
+        for path in &self.paths {
+            if let Some(field) = value.pointer_mut(path) {
+                if is_encrypted(field) {
+                    let decrypted = self.decrypt_field(path, field)?;
+                    *field = decrypted;
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    fn encrypt_field(&self, path: &str, field: &Value) -> Result<Value, C3p0Error> {
+        let key_id = self.key_provider.current_key_id();
+        let key = self.key_provider.key(key_id)?;
+        let plaintext = serde_json::to_vec(field)?;
+        let (nonce, ciphertext) = self.algorithm.encrypt(&key, path.as_bytes(), &plaintext)?;
+
+        let mut envelope = Map::new();
+        envelope.insert(
+            ENCRYPTION_FIELD.to_owned(),
+            Value::String(self.algorithm.name().to_owned()),
+        );
+        envelope.insert(KEY_ID_FIELD.to_owned(), Value::String(key_id.to_owned()));
+        envelope.insert(
+            NONCE_FIELD.to_owned(),
+            Value::String(base64::encode(&nonce)),
+        );
+        envelope.insert(
+            PAYLOAD_FIELD.to_owned(),
+            Value::String(base64::encode(&ciphertext)),
+        );
+        Ok(Value::Object(envelope))
+    }
+
+    /// Returns whether the configured fields of the value are encrypted with the current
+    /// algorithm and key; missing and null fields are not encrypted.
+    pub fn is_encrypted_with_current_key(&self, value: &Value) -> bool {
+        self.paths.iter().all(|path| match value.pointer(path) {
+            None | Some(Value::Null) => true,
+            Some(field) => {
+                is_encrypted(field)
+                    && field.get(ENCRYPTION_FIELD).and_then(Value::as_str)
+                        == Some(self.algorithm.name())
+                    && field.get(KEY_ID_FIELD).and_then(Value::as_str)
+                        == Some(self.key_provider.current_key_id())
+            }
+        })
+    }
+
+    fn decrypt_field(&self, path: &str, field: &Value) -> Result<Value, C3p0Error> {
+        let algorithm = EncryptionAlgorithm::from_name(get_str(field, ENCRYPTION_FIELD)?)?;
+        let key = self.key_provider.key(get_str(field, KEY_ID_FIELD)?)?;
+        let nonce = get_base64(field, NONCE_FIELD)?;
+        let ciphertext = get_base64(field, PAYLOAD_FIELD)?;
+        let plaintext = algorithm.decrypt(&key, path.as_bytes(), &nonce, &ciphertext)?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+impl<Data, CODEC, KP> JsonCodec<Data> for EncryptingCodec<CODEC, KP>
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    CODEC: JsonCodec<Data>,
+    KP: KeyProvider,
+{
+    fn from_value(&self, value: Value) -> Result<Data, C3p0Error> {
+        self.codec.from_value(self.decrypt_value(value)?)
+    }
+
+    fn to_value(&self, data: &Data) -> Result<Value, C3p0Error> {
+        self.encrypt_value(self.codec.to_value(data)?)
+    }
+
+    /// The encryption uses a random nonce, so the envelopes are checked instead,
+    /// and the decrypted value is checked by the inner codec
+    fn is_current(&self, value: &Value) -> bool {
+        self.is_encrypted_with_current_key(value)
+            && self
+                .decrypt_value(value.clone())
+                .is_ok_and(|decrypted| self.codec.is_current(&decrypted))
+    }
+}
+
+/// Returns whether the value is an encrypted field envelope
+pub fn is_encrypted(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => {
+            map.contains_key(ENCRYPTION_FIELD)
+                && map.contains_key(KEY_ID_FIELD)
+                && map.contains_key(NONCE_FIELD)
+                && map.contains_key(PAYLOAD_FIELD)
+        }
+        _ => false,
+    }
+}
+
+fn get_str<'a>(value: &'a Value, field: &str) -> Result<&'a str, C3p0Error> {
+    value
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| C3p0Error::EncryptionError {
+            cause: format!("The encrypted field [{}] is not a string", field),
+        })
+}
+
+fn get_base64(value: &Value, field: &str) -> Result<Vec<u8>, C3p0Error> {
+    base64::decode(get_str(value, field)?).map_err(|err| C3p0Error::EncryptionError {
+        cause: format!(
+            "The encrypted field [{}] is not valid base64. Err: {}",
+            field, err
+        ),
+    })
+}
+
+/// Rewrites the entries of a table that are not encrypted with the current key and algorithm,
+/// including the plaintext ones; the other entries are not modified.
+/// This must be executed after a key rotation, before removing the old keys from the KeyProvider.
+/// It is a `rewrite_all` of the table: the entries concurrently modified are counted in the
+/// `conflicts` of the returned progress, and they must be rewritten by running it again.
+pub async fn reencrypt_all<Data, CODEC, KP, C3P0, JSON>(
+    pool: &C3P0,
+    c3p0_json: &JSON,
+    batch_size: u32,
+) -> Result<RewriteProgress, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: JsonCodec<Data>,
+    KP: KeyProvider,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Data, EncryptingCodec<CODEC, KP>, Conn = C3P0::Conn>,
+{
+    rewrite_all(pool, c3p0_json, batch_size).await
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::json::codec::DefaultJsonCodec;
+    use serde_json::json;
+
+    fn codec(
+        key_provider: StaticKeyProvider,
+    ) -> EncryptingCodec<DefaultJsonCodec, StaticKeyProvider> {
+        EncryptingCodec::new(DefaultJsonCodec {}, key_provider)
+            .with_encrypted_path("/email")
+            .with_encrypted_path("/address/street")
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_the_configured_paths() -> Result<(), C3p0Error> {
+        for algorithm in &[
+            EncryptionAlgorithm::Aes256Gcm,
+            EncryptionAlgorithm::ChaCha20Poly1305,
+        ] {
+            let codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])))
+                .with_algorithm(*algorithm);
+            let value = json!({
+                "name": "name",
+                "email": "name@example.com",
+                "address": {"street": "Main Street", "number": 3}
+            });
+
+            let encrypted = codec.encrypt_value(value.clone())?;
+            assert_eq!("name", encrypted["name"]);
+            assert_eq!(3, encrypted["address"]["number"]);
+            assert!(is_encrypted(&encrypted["email"]));
+            assert!(is_encrypted(&encrypted["address"]["street"]));
+            assert_eq!("k1", encrypted["email"][KEY_ID_FIELD]);
+            assert_eq!(algorithm.name(), encrypted["email"][ENCRYPTION_FIELD]);
+
+            assert_eq!(value, codec.decrypt_value(encrypted)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_skip_missing_and_null_paths() -> Result<(), C3p0Error> {
+        let codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let value = json!({"name": "name", "email": null});
+        assert_eq!(value, codec.encrypt_value(value.clone())?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_read_plaintext_values() -> Result<(), C3p0Error> {
+        let codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let value = json!({"email": "name@example.com"});
+        assert_eq!(value, codec.decrypt_value(value.clone())?);
+        Ok(())
+    }
+
+    #[test]
+    fn should_decrypt_values_encrypted_with_old_keys() -> Result<(), C3p0Error> {
+        let old_codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let new_codec = codec(
+            StaticKeyProvider::new("k2", EncryptionKey::new([2; 32]))
+                .with_key("k1", EncryptionKey::new([1; 32])),
+        );
+        let value = json!({"email": "name@example.com"});
+
+        let encrypted = old_codec.encrypt_value(value.clone())?;
+        assert_eq!(value, new_codec.decrypt_value(encrypted)?);
+
+        let encrypted = new_codec.encrypt_value(value)?;
+        assert_eq!("k2", encrypted["email"][KEY_ID_FIELD]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_with_unknown_or_wrong_key() -> Result<(), C3p0Error> {
+        let codec_1 = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let codec_2 = codec(StaticKeyProvider::new("k2", EncryptionKey::new([2; 32])));
+        let wrong_key = codec(StaticKeyProvider::new("k1", EncryptionKey::new([2; 32])));
+
+        let encrypted = codec_1.encrypt_value(json!({"email": "name@example.com"}))?;
+
+        match codec_2.decrypt_value(encrypted.clone()) {
+            Err(C3p0Error::EncryptionError { .. }) => {}
+            _ => panic!("Expected an EncryptionError"),
+        }
+        match wrong_key.decrypt_value(encrypted) {
+            Err(C3p0Error::EncryptionError { .. }) => {}
+            _ => panic!("Expected an EncryptionError"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_allow_moving_encrypted_values_between_fields() -> Result<(), C3p0Error> {
+        let codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let mut encrypted = codec.encrypt_value(
+            json!({"email": "name@example.com", "address": {"street": "Main Street"}}),
+        )?;
+        encrypted["address"]["street"] = encrypted["email"].clone();
+
+        match codec.decrypt_value(encrypted) {
+            Err(C3p0Error::EncryptionError { .. }) => {}
+            _ => panic!("Expected an EncryptionError"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_check_whether_the_values_are_current() -> Result<(), C3p0Error> {
+        let old_codec = codec(StaticKeyProvider::new("k1", EncryptionKey::new([1; 32])));
+        let new_codec = codec(
+            StaticKeyProvider::new("k2", EncryptionKey::new([2; 32]))
+                .with_key("k1", EncryptionKey::new([1; 32])),
+        );
+        let value = json!({"name": "name", "email": "name@example.com"});
+
+        let encrypted = new_codec.encrypt_value(value.clone())?;
+        assert!(JsonCodec::<Value>::is_current(&new_codec, &encrypted));
+        assert!(!JsonCodec::<Value>::is_current(&new_codec, &value));
+        assert!(!JsonCodec::<Value>::is_current(
+            &new_codec,
+            &old_codec.encrypt_value(value.clone())?
+        ));
+        assert!(!JsonCodec::<Value>::is_current(
+            &new_codec
+                .clone()
+                .with_algorithm(EncryptionAlgorithm::ChaCha20Poly1305),
+            &encrypted
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn key_should_have_the_right_length() {
+        assert!(EncryptionKey::from_slice(&[0; 32]).is_ok());
+        assert!(EncryptionKey::from_slice(&[0; 16]).is_err());
+    }
+}
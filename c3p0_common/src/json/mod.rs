@@ -1,22 +1,47 @@
-use crate::{C3p0Error, ForUpdate, IdType, JsonCodec, Model, NewModel};
+use crate::{C3p0Error, C3p0Index, ForUpdate, IdType, Model, NewModel, OrderBy};
 use async_trait::async_trait;
+use serde_json::Value;
 
 pub mod builder;
+pub mod cache;
+pub mod change_feed;
 pub mod codec;
+pub mod column;
+pub mod compression;
+#[cfg(feature = "encryption")]
+pub mod encryption;
 pub mod model;
+pub mod rewrite;
+pub mod schema;
+pub mod transfer;
 
+/// The CRUD operations on a table of versioned documents.
+/// The `Codec` is the one used to encode and decode the document data;
+/// it is usually a `JsonCodec`, but tables storing binary documents use a `BinaryCodec`.
 #[async_trait]
 pub trait C3p0Json<Data, Codec>: Clone + Send + Sync
 where
     Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
-    Codec: JsonCodec<Data>,
 {
     type Conn;
 
     fn codec(&self) -> &Codec;
 
+    /// Returns the name of the table, qualified with its schema if any
+    fn qualified_table_name(&self) -> &str;
+
     async fn create_table_if_not_exists(&self, conn: &mut Self::Conn) -> Result<(), C3p0Error>;
 
+    /// Creates an index on a top level field of the stored documents.
+    /// Backends that cannot index the documents fail if the index is unique, otherwise they skip it;
+    /// the in-memory backend skips every index, so it does not enforce the unique ones.
+    /// It fails on the tables with compression.
+    async fn create_index_if_not_exists(
+        &self,
+        conn: &mut Self::Conn,
+        index: &C3p0Index,
+    ) -> Result<(), C3p0Error>;
+
     async fn drop_table_if_exists(
         &self,
         conn: &mut Self::Conn,
@@ -39,6 +64,65 @@ where
         for_update: &ForUpdate,
     ) -> Result<Vec<Model<Data>>, C3p0Error>;
 
+    /// Returns at most `limit` entries with an id greater than `after_id`, ordered by id.
+    /// It allows iterating over a table in batches.
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Data>>, C3p0Error>;
+
+    /// Returns at most `limit` stored documents with an id greater than `after_id`, ordered by id,
+    /// as they are stored, without decoding them with the codec.
+    /// Compressed documents are returned in their envelope (see `decompress`),
+    /// while binary documents are returned as arrays of bytes.
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error>;
+
+    /// Returns whether a value returned by `fetch_all_values_after_id` is stored in the format
+    /// written by the table now: encoded by the current codec (see `JsonCodec::is_current`)
+    /// and compressed if the table has compression and the document reaches its threshold
+    fn is_current(&self, value: &Value) -> bool;
+
+    /// Returns the entries whose stored document has the top level `field_name` field equal to `value`,
+    /// ordered by id.
+    /// It fails on the tables with compression, like the other finders by field.
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<Data>>, C3p0Error>;
+
+    /// Returns the entries whose stored document has the top level `field_name` field
+    /// set to a string starting with `prefix`, ordered by id
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<Data>>, C3p0Error>;
+
+    /// Returns at most `limit` entries whose stored document has the top level `field_name` field
+    /// set to a number lower than or equal to `max_value`, and locks them with the `for_update` clause.
+    /// The entries are ordered by the numeric `order_by_field`, if any, then by `field_name` and by id.
+    /// It allows polling a table for the due entries; for example, with `ForUpdate::SkipLocked`
+    /// concurrent pollers never receive the same entries.
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<Data>>, C3p0Error>;
+
     async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
         &'a self,
         conn: &mut Self::Conn,
@@ -85,6 +169,14 @@ where
         obj: NewModel<Data>,
     ) -> Result<Model<Data>, C3p0Error>;
 
+    /// Saves the entry with the id and the version of the model; it fails if the id is already used.
+    /// The ids generated afterwards by `save` are greater than the saved one.
+    async fn save_with_id(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<Data>,
+    ) -> Result<Model<Data>, C3p0Error>;
+
     async fn update(
         &self,
         conn: &mut Self::Conn,
@@ -92,6 +184,129 @@ where
     ) -> Result<Model<Data>, C3p0Error>;
 }
 
+/// The escape character of the patterns returned by `like_prefix_pattern`
+pub const LIKE_ESCAPE: char = '!';
+
+/// Returns the SQL LIKE pattern that matches the strings starting with `prefix`;
+/// its wildcards are escaped with `LIKE_ESCAPE`
+pub fn like_prefix_pattern(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+    for c in prefix.chars() {
+        if c == LIKE_ESCAPE || c == '%' || c == '_' {
+            pattern.push(LIKE_ESCAPE);
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Whether the top level `field_name` field of the document is a string starting with `prefix`
+pub fn has_field_prefix(value: &Value, field_name: &str, prefix: &str) -> bool {
+    matches!(value.get(field_name), Some(Value::String(field)) if field.starts_with(prefix))
+}
+
+/// Selects the entries whose document has the top level `field_name` field equal to `value`.
+/// It is used by the backends that cannot query the stored documents.
+pub fn select_by_field<Data>(
+    models: Vec<Model<Data>>,
+    field_name: &str,
+    value: &Value,
+) -> Result<Vec<Model<Data>>, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + Send,
+{
+    let mut selected = vec![];
+    for (model, document) in with_documents(models)? {
+        if document.get(field_name) == Some(value) {
+            selected.push(model);
+        }
+    }
+    Ok(selected)
+}
+
+/// Selects the entries whose document has the top level `field_name` field
+/// set to a string starting with `prefix`.
+/// It is used by the backends that cannot query the stored documents.
+pub fn select_by_field_prefix<Data>(
+    models: Vec<Model<Data>>,
+    field_name: &str,
+    prefix: &str,
+) -> Result<Vec<Model<Data>>, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + Send,
+{
+    let mut selected = vec![];
+    for (model, document) in with_documents(models)? {
+        if has_field_prefix(&document, field_name, prefix) {
+            selected.push(model);
+        }
+    }
+    Ok(selected)
+}
+
+/// Pairs every entry with its document, as required by `select_by_max_field`
+pub fn with_documents<Data>(
+    models: Vec<Model<Data>>,
+) -> Result<Vec<(Model<Data>, Value)>, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + Send,
+{
+    models
+        .into_iter()
+        .map(|model| {
+            let value = serde_json::to_value(&model.data)?;
+            Ok((model, value))
+        })
+        .collect()
+}
+
+/// Selects the entries as specified by `C3p0Json::fetch_all_by_max_field_for_update`.
+/// It is used by the backends that cannot query the stored documents;
+/// every entry is paired with its document.
+pub fn select_by_max_field<Data>(
+    entries: Vec<(Model<Data>, Value)>,
+    field_name: &str,
+    max_value: i64,
+    order_by_field: Option<(&str, &OrderBy)>,
+    limit: u32,
+) -> Vec<Model<Data>>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+{
+    let number = |value: &Value, field_name: &str| value.get(field_name).and_then(Value::as_f64);
+    let compare = |number_1: Option<f64>, number_2: Option<f64>| {
+        number_1
+            .partial_cmp(&number_2)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    };
+
+    let mut selected = entries
+        .into_iter()
+        .filter_map(|(model, value)| {
+            let order =
+                order_by_field.and_then(|(order_by_field, _)| number(&value, order_by_field));
+            number(&value, field_name)
+                .filter(|field| *field <= max_value as f64)
+                .map(|field| (order, field, model))
+        })
+        .collect::<Vec<_>>();
+    selected.sort_by(|(order_1, field_1, model_1), (order_2, field_2, model_2)| {
+        let order = match order_by_field {
+            Some((_, OrderBy::Desc)) => compare(*order_2, *order_1),
+            _ => compare(*order_1, *order_2),
+        };
+        order
+            .then(compare(Some(*field_1), Some(*field_2)))
+            .then(model_1.id.cmp(&model_2.id))
+    });
+    selected
+        .into_iter()
+        .take(limit as usize)
+        .map(|(_, _, model)| model)
+        .collect()
+}
+
 #[derive(Clone)]
 pub struct Queries {
     pub id_field_name: String,
@@ -106,6 +321,7 @@ pub struct Queries {
     pub exists_by_id_sql_query: String,
 
     pub find_all_sql_query: String,
+    pub find_all_after_id_sql_query: String,
     pub find_by_id_sql_query: String,
 
     pub delete_sql_query: String,
@@ -113,6 +329,7 @@ pub struct Queries {
     pub delete_by_id_sql_query: String,
 
     pub save_sql_query: String,
+    pub save_with_id_sql_query: String,
 
     pub update_sql_query: String,
 
@@ -120,4 +337,18 @@ pub struct Queries {
     pub drop_table_sql_query: String,
     pub drop_table_sql_query_cascade: String,
     pub lock_table_sql_query: Option<String>,
+    pub change_feed_sql_query: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_escape_the_like_prefix_pattern() {
+        assert_eq!("%", like_prefix_pattern(""));
+        assert_eq!("flags.%", like_prefix_pattern("flags."));
+        assert_eq!("a!%b!_c!!d%", like_prefix_pattern("a%b_c!d"));
+    }
 }
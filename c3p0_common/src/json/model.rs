@@ -1,3 +1,4 @@
+use crate::json::builder::C3p0JsonBuilder;
 use serde::{Deserialize, Serialize};
 
 pub type IdType = i64;
@@ -72,6 +73,49 @@ where
     }
 }
 
+/// An index on a top level field of the stored documents
+#[derive(Clone, Debug, PartialEq)]
+pub struct C3p0Index {
+    pub field_name: String,
+    pub unique: bool,
+    /// Whether the index is on the numeric values of the field, ordered by id,
+    /// as selected by `C3p0Json::fetch_all_by_max_field_for_update`; a numeric index is never unique
+    pub numeric: bool,
+}
+
+impl C3p0Index {
+    pub fn new<T: Into<String>>(field_name: T, unique: bool) -> Self {
+        C3p0Index {
+            field_name: field_name.into(),
+            unique,
+            numeric: false,
+        }
+    }
+
+    /// Returns an index on the numeric values of the field
+    pub fn numeric<T: Into<String>>(field_name: T) -> Self {
+        C3p0Index {
+            field_name: field_name.into(),
+            unique: false,
+            numeric: true,
+        }
+    }
+}
+
+/// The table definition of a data struct.
+/// It is usually implemented with `#[derive(C3p0Model)]`.
+pub trait C3p0Model:
+    Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync
+{
+    const TABLE_NAME: &'static str;
+
+    fn indexes() -> Vec<C3p0Index>;
+
+    fn json_builder<C3P0>() -> C3p0JsonBuilder<C3P0> {
+        C3p0JsonBuilder::new(Self::TABLE_NAME)
+    }
+}
+
 #[cfg(test)]
 mod test {
 
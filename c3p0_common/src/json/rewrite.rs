@@ -0,0 +1,165 @@
+use crate::error::C3p0Error;
+use crate::json::codec::JsonCodec;
+use crate::json::compression::decompress;
+use crate::json::model::{IdType, Model};
+use crate::json::C3p0Json;
+use crate::pool::C3p0Pool;
+
+/// The progress of a rewrite job.
+/// It is returned at the end of the job and passed to the progress callback after every batch;
+/// a job started from a saved progress resumes after its `last_id`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RewriteProgress {
+    /// The id of the last processed document
+    pub last_id: Option<IdType>,
+    /// The number of processed documents
+    pub processed: u64,
+    /// The number of stale documents, that is, those whose stored value is not current
+    /// (see `C3p0Json::is_current`)
+    pub stale: u64,
+    /// The number of rewritten documents
+    pub rewritten: u64,
+    /// The number of stale documents not rewritten because they were concurrently modified
+    pub conflicts: u64,
+}
+
+/// Rewrites all the documents of a table whose stored value is not in the format written by
+/// the current codec; for example, documents stored with an old version of a VersionedCodec,
+/// or uncompressed documents of a table whose compression was enabled.
+/// The documents are processed in batches of `batch_size`, each one in a separate transaction.
+pub async fn rewrite_all<Data, CODEC, C3P0, JSON>(
+    pool: &C3P0,
+    c3p0_json: &JSON,
+    batch_size: u32,
+) -> Result<RewriteProgress, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: JsonCodec<Data>,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Data, CODEC, Conn = C3P0::Conn>,
+{
+    rewrite_all_with_progress(
+        pool,
+        c3p0_json,
+        batch_size,
+        RewriteProgress::default(),
+        |_| {},
+    )
+    .await
+}
+
+/// Like `rewrite_all` but it starts from the given progress
+/// and calls `on_progress` after every committed batch
+pub async fn rewrite_all_with_progress<Data, CODEC, C3P0, JSON, F>(
+    pool: &C3P0,
+    c3p0_json: &JSON,
+    batch_size: u32,
+    progress: RewriteProgress,
+    on_progress: F,
+) -> Result<RewriteProgress, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: JsonCodec<Data>,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Data, CODEC, Conn = C3P0::Conn>,
+    F: FnMut(&RewriteProgress),
+{
+    process_all(pool, c3p0_json, batch_size, progress, on_progress, true).await
+}
+
+/// Counts the stale documents of a table without modifying them
+pub async fn count_stale<Data, CODEC, C3P0, JSON>(
+    pool: &C3P0,
+    c3p0_json: &JSON,
+    batch_size: u32,
+) -> Result<u64, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: JsonCodec<Data>,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Data, CODEC, Conn = C3P0::Conn>,
+{
+    process_all(
+        pool,
+        c3p0_json,
+        batch_size,
+        RewriteProgress::default(),
+        |_| {},
+        false,
+    )
+    .await
+    .map(|progress| progress.stale)
+}
+
+async fn process_all<Data, CODEC, C3P0, JSON, F>(
+    pool: &C3P0,
+    c3p0_json: &JSON,
+    batch_size: u32,
+    mut progress: RewriteProgress,
+    mut on_progress: F,
+    rewrite: bool,
+) -> Result<RewriteProgress, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: JsonCodec<Data>,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Data, CODEC, Conn = C3P0::Conn>,
+    F: FnMut(&RewriteProgress),
+{
+    loop {
+        let after_id = progress.last_id.unwrap_or(IdType::MIN);
+        let batch = pool
+            .transaction(|mut conn| async move {
+                let values = c3p0_json
+                    .fetch_all_values_after_id(&mut conn, after_id, batch_size)
+                    .await?;
+
+                let mut batch = RewriteProgress {
+                    last_id: values.last().map(|value| value.id),
+                    processed: values.len() as u64,
+                    ..RewriteProgress::default()
+                };
+
+                for value in values {
+                    if c3p0_json.is_current(&value.data) {
+                        continue;
+                    }
+                    let data = c3p0_json.codec().from_value(decompress(value.data)?)?;
+
+                    batch.stale += 1;
+                    if !rewrite {
+                        continue;
+                    }
+
+                    let model = Model {
+                        id: value.id,
+                        version: value.version,
+                        data,
+                    };
+                    match c3p0_json.update(&mut conn, model).await {
+                        Ok(_) => batch.rewritten += 1,
+                        Err(C3p0Error::OptimisticLockError { .. }) => batch.conflicts += 1,
+                        Err(err) => return Err(err),
+                    }
+                }
+
+                Ok(batch)
+            })
+            .await?;
+
+        if batch.last_id.is_none() {
+            return Ok(progress);
+        }
+
+        progress.last_id = batch.last_id;
+        progress.processed += batch.processed;
+        progress.stale += batch.stale;
+        progress.rewritten += batch.rewritten;
+        progress.conflicts += batch.conflicts;
+        on_progress(&progress);
+    }
+}
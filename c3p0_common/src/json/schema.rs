@@ -0,0 +1,227 @@
+use crate::error::C3p0Error;
+use crate::json::codec::JsonCodec;
+use serde_json::Value;
+#[cfg(feature = "json_schema")]
+use std::sync::Arc;
+
+/// A JSON Schema that the documents of a table must satisfy.
+/// It is validated against the `Value` produced by the codec of the table
+/// before it is compressed and written.
+#[derive(Clone)]
+pub struct JsonSchema {
+    schema: Value,
+    #[cfg(feature = "json_schema")]
+    compiled: Arc<jsonschema::JSONSchema>,
+}
+
+impl JsonSchema {
+    #[cfg(feature = "json_schema")]
+    pub fn new(schema: Value) -> Result<Self, C3p0Error> {
+        let compiled =
+            jsonschema::JSONSchema::compile(&schema).map_err(|err| C3p0Error::ValidationError {
+                message: format!("Invalid JSON Schema: {}", err),
+                paths: vec![err.schema_path.to_string()],
+            })?;
+        Ok(JsonSchema {
+            schema,
+            compiled: Arc::new(compiled),
+        })
+    }
+
+    pub fn schema(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Validates the value and returns a ValidationError listing all the violating paths
+    #[cfg(feature = "json_schema")]
+    pub fn validate(&self, value: &Value) -> Result<(), C3p0Error> {
+        if let Err(errors) = self.compiled.validate(value) {
+            let mut messages = vec![];
+            let mut paths = vec![];
+            for error in errors {
+                let path = error.instance_path.to_string();
+                messages.push(format!("[{}] {}", path, error));
+                paths.push(path);
+            }
+            return Err(C3p0Error::ValidationError {
+                message: messages.join(", "),
+                paths,
+            });
+        }
+        Ok(())
+    }
+
+    /// A JsonSchema cannot be built without the `json_schema` feature
+    #[cfg(not(feature = "json_schema"))]
+    pub fn validate(&self, _value: &Value) -> Result<(), C3p0Error> {
+        Ok(())
+    }
+
+    /// Returns a Postgres CHECK expression on the given JSONB column that
+    /// enforces the subset of the schema that can be expressed in SQL:
+    /// the type of the document, the required properties and the types of the properties.
+    /// The expression never rejects a document that satisfies the schema;
+    /// it returns None if nothing in the schema can be translated.
+    pub fn to_pg_check_constraint(&self, data_field_name: &str) -> Option<String> {
+        let mut checks = vec![];
+
+        if let Some(types) = self.schema.get("type").and_then(pg_types) {
+            checks.push(format!("jsonb_typeof({}) IN ({})", data_field_name, types));
+        }
+
+        if let Some(required) = self.schema.get("required").and_then(Value::as_array) {
+            for property in required.iter().filter_map(Value::as_str) {
+                checks.push(format!("{} ? {}", data_field_name, pg_string(property)));
+            }
+        }
+
+        if let Some(properties) = self.schema.get("properties").and_then(Value::as_object) {
+            for (property, property_schema) in properties {
+                if let Some(types) = property_schema.get("type").and_then(pg_types) {
+                    checks.push(format!(
+                        "(NOT {field} ? {name} OR jsonb_typeof({field} -> {name}) IN ({types}))",
+                        field = data_field_name,
+                        name = pg_string(property),
+                        types = types
+                    ));
+                }
+            }
+        }
+
+        if checks.is_empty() {
+            None
+        } else {
+            Some(checks.join(" AND "))
+        }
+    }
+}
+
+/// Maps the JSON Schema types to the values returned by the Postgres jsonb_typeof function
+fn pg_types(schema_type: &Value) -> Option<String> {
+    let types = match schema_type {
+        Value::String(schema_type) => vec![schema_type.as_str()],
+        Value::Array(schema_types) => schema_types.iter().filter_map(Value::as_str).collect(),
+        _ => return None,
+    };
+
+    let mut pg_types = vec![];
+    for schema_type in types {
+        let pg_type = match schema_type {
+            "integer" | "number" => "number",
+            "string" | "boolean" | "object" | "array" | "null" => schema_type,
+            _ => return None,
+        };
+        let pg_type = pg_string(pg_type);
+        if !pg_types.contains(&pg_type) {
+            pg_types.push(pg_type);
+        }
+    }
+
+    if pg_types.is_empty() {
+        None
+    } else {
+        Some(pg_types.join(", "))
+    }
+}
+
+fn pg_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// A JsonCodec that wraps the codec of a table and validates the encoded values
+/// against the table schema, if any.
+#[derive(Clone)]
+pub struct ValidatingCodec<'a, CODEC> {
+    codec: &'a CODEC,
+    schema: Option<&'a JsonSchema>,
+}
+
+impl<'a, CODEC> ValidatingCodec<'a, CODEC> {
+    pub fn new(codec: &'a CODEC, schema: Option<&'a JsonSchema>) -> Self {
+        ValidatingCodec { codec, schema }
+    }
+}
+
+impl<'a, Data, CODEC> JsonCodec<Data> for ValidatingCodec<'a, CODEC>
+where
+    Data: serde::ser::Serialize + serde::de::DeserializeOwned + Send,
+    CODEC: JsonCodec<Data>,
+{
+    fn from_value(&self, value: Value) -> Result<Data, C3p0Error> {
+        self.codec.from_value(value)
+    }
+
+    fn to_value(&self, data: &Data) -> Result<Value, C3p0Error> {
+        let value = self.codec.to_value(data)?;
+        if let Some(schema) = self.schema {
+            schema.validate(&value)?;
+        }
+        Ok(value)
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.codec.is_current(value)
+    }
+}
+
+#[cfg(all(test, feature = "json_schema"))]
+mod test {
+
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> JsonSchema {
+        JsonSchema::new(json!({
+            "type": "object",
+            "required": ["name", "age"],
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer", "minimum": 0},
+                "it's": {"type": ["string", "null"]}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_accept_valid_values() -> Result<(), C3p0Error> {
+        schema().validate(&json!({"name": "name", "age": 10}))
+    }
+
+    #[test]
+    fn should_list_the_violating_paths() {
+        match schema().validate(&json!({"name": 12, "age": -1})) {
+            Err(C3p0Error::ValidationError { paths, .. }) => {
+                assert_eq!(2, paths.len());
+                assert!(paths.contains(&"/name".to_owned()));
+                assert!(paths.contains(&"/age".to_owned()));
+            }
+            _ => panic!("Expected a ValidationError"),
+        }
+    }
+
+    #[test]
+    fn should_fail_on_invalid_schema() {
+        assert!(JsonSchema::new(json!({"type": 12})).is_err());
+    }
+
+    #[test]
+    fn should_build_the_pg_check_constraint() {
+        assert_eq!(
+            Some(
+                "jsonb_typeof(data) IN ('object') AND data ? 'name' AND data ? 'age' \
+                 AND (NOT data ? 'age' OR jsonb_typeof(data -> 'age') IN ('number')) \
+                 AND (NOT data ? 'it''s' OR jsonb_typeof(data -> 'it''s') IN ('string', 'null')) \
+                 AND (NOT data ? 'name' OR jsonb_typeof(data -> 'name') IN ('string'))"
+                    .to_owned()
+            ),
+            schema().to_pg_check_constraint("data")
+        );
+        assert_eq!(
+            None,
+            JsonSchema::new(json!({"minProperties": 1}))
+                .unwrap()
+                .to_pg_check_constraint("data")
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use crate::error::C3p0Error;
+use crate::json::model::{IdType, Model, NewModel};
+use crate::json::C3p0Json;
+use crate::sql::ForUpdate;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The number of entries fetched at once by `export_table`
+const EXPORT_BATCH_SIZE: u32 = 1000;
+
+/// How `import_table` saves the imported entries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    /// The entries are saved with new ids and their own versions
+    RegenerateIds,
+    /// The entries are saved with their own ids and versions;
+    /// those whose id is already used are skipped
+    KeepIdsSkipExisting,
+    /// The entries are saved with their own ids and versions;
+    /// those whose id is already used update the existing ones, whose version is incremented
+    /// as by any other update, so that the concurrent writers of the previous version
+    /// fail with an `OptimisticLockError`
+    KeepIdsOverwriteExisting,
+}
+
+/// The result of an import
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// The number of saved entries, including the overwritten ones
+    pub saved: u64,
+    /// The number of existing entries updated with the imported ones
+    pub overwritten: u64,
+    /// The number of entries not saved because their id was already used
+    pub skipped: u64,
+}
+
+/// Writes all the entries of a table to the writer as JSON Lines, ordered by id.
+/// Every line is a JSON object with the id, the version and the data of an entry;
+/// the data is decoded with the codec of the C3p0Json, so that it can be imported in a table
+/// with a different backend or codec.
+/// The entries are fetched in batches; it returns the number of exported entries.
+/// The writer is asynchronous, so it can be a file or a socket.
+pub async fn export_table<Data, CODEC, JSON, W>(
+    conn: &mut JSON::Conn,
+    c3p0_json: &JSON,
+    mut writer: W,
+) -> Result<u64, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    JSON: C3p0Json<Data, CODEC>,
+    W: AsyncWrite + Unpin,
+{
+    let mut after_id = IdType::MIN;
+    let mut exported = 0;
+    loop {
+        let models = c3p0_json
+            .fetch_all_after_id(conn, after_id, EXPORT_BATCH_SIZE)
+            .await?;
+        for model in &models {
+            let mut line = serde_json::to_vec(model)?;
+            line.push(b'\n');
+            writer
+                .write_all(&line)
+                .await
+                .map_err(|err| C3p0Error::InternalError {
+                    cause: format!("Cannot write the entry with id [{}]: {}", model.id, err),
+                })?;
+            exported += 1;
+        }
+        match models.last() {
+            Some(model) if models.len() as u32 == EXPORT_BATCH_SIZE => after_id = model.id,
+            _ => break,
+        }
+    }
+    writer
+        .flush()
+        .await
+        .map_err(|err| C3p0Error::InternalError {
+            cause: format!("Cannot flush the exported entries: {}", err),
+        })?;
+    Ok(exported)
+}
+
+/// Saves in a table the entries read from the reader as JSON Lines,
+/// in the format written by `export_table`; empty lines are ignored.
+/// The lines are read and saved one at a time; the reader is asynchronous,
+/// so it can be a file or a socket.
+pub async fn import_table<Data, CODEC, JSON, R>(
+    conn: &mut JSON::Conn,
+    c3p0_json: &JSON,
+    reader: R,
+    mode: ImportMode,
+) -> Result<ImportReport, C3p0Error>
+where
+    Data: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    JSON: C3p0Json<Data, CODEC>,
+    R: AsyncBufRead + Unpin,
+{
+    let mut report = ImportReport::default();
+    let mut lines = reader.lines();
+    let mut index = 0;
+    loop {
+        let line = match lines
+            .next_line()
+            .await
+            .map_err(|err| C3p0Error::InternalError {
+                cause: format!("Cannot read the line [{}]: {}", index + 1, err),
+            })? {
+            Some(line) => line,
+            None => break,
+        };
+        index += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let model: Model<Data> =
+            serde_json::from_str(&line).map_err(|err| C3p0Error::InternalError {
+                cause: format!("Cannot parse the line [{}]: {}", index, err),
+            })?;
+
+        match mode {
+            ImportMode::RegenerateIds => {
+                c3p0_json
+                    .save(
+                        conn,
+                        NewModel {
+                            version: model.version,
+                            data: model.data,
+                        },
+                    )
+                    .await?;
+            }
+            ImportMode::KeepIdsSkipExisting => {
+                if c3p0_json.exists_by_id(conn, &model.id).await? {
+                    report.skipped += 1;
+                    continue;
+                }
+                c3p0_json.save_with_id(conn, model).await?;
+            }
+            ImportMode::KeepIdsOverwriteExisting => {
+                match c3p0_json
+                    .fetch_one_optional_by_id_for_update(conn, &model.id, &ForUpdate::Default)
+                    .await?
+                {
+                    Some(existing) => {
+                        c3p0_json
+                            .update(
+                                conn,
+                                Model {
+                                    id: existing.id,
+                                    version: existing.version,
+                                    data: model.data,
+                                },
+                            )
+                            .await?;
+                        report.overwritten += 1;
+                    }
+                    None => {
+                        c3p0_json.save_with_id(conn, model).await?;
+                    }
+                }
+            }
+        }
+        report.saved += 1;
+    }
+    Ok(report)
+}
@@ -0,0 +1,271 @@
+use crate::error::C3p0Error;
+use crate::json::model::{C3p0Index, Model, NewModel, VersionType};
+use crate::json::C3p0Json;
+use crate::lock::{C3p0Lock, LockKey};
+use crate::outbox::now_millis;
+use crate::sql::ForUpdate;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+const KEY_FIELD: &str = "key";
+const EXPIRES_AT_FIELD: &str = "expires_at";
+
+/// The number of expired entries deleted at once by `C3p0Kv::delete_expired`
+const DELETE_EXPIRED_BATCH_SIZE: u32 = 1000;
+
+/// An entry of a C3p0Kv table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KvEntry<V> {
+    pub key: String,
+    pub value: V,
+    /// When the entry expires, in milliseconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl<V> KvEntry<V> {
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(now_millis())
+    }
+
+    fn is_expired_at(&self, now: i64) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at <= now)
+    }
+}
+
+/// A key-value store on a table of versioned documents.
+/// Every entry is a document with its key, its value and its optional expiration;
+/// the version of the entry allows compare-and-set updates.
+///
+/// The expired entries are never returned, and they are deleted by `delete_expired`.
+/// The writes of a key are serialized with a transaction lock (see `C3p0Lock`), and the keys
+/// have a unique index. The writes require a transaction: outside of a transaction they fail,
+/// like the lock.
+#[derive(Clone)]
+pub struct C3p0Kv<V, CODEC, JSON> {
+    json: JSON,
+    phantom: PhantomData<fn() -> (V, CODEC)>,
+}
+
+impl<V, CODEC, JSON> C3p0Kv<V, CODEC, JSON>
+where
+    V: Clone + Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Send + Sync,
+    JSON: C3p0Json<KvEntry<V>, CODEC>,
+    JSON::Conn: C3p0Lock,
+{
+    /// Creates a key-value store in the table of the given C3p0Json
+    pub fn new(json: JSON) -> Self {
+        C3p0Kv {
+            json,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn json(&self) -> &JSON {
+        &self.json
+    }
+
+    /// Creates the table and a unique index on the keys
+    pub async fn create_table_if_not_exists(&self, conn: &mut JSON::Conn) -> Result<(), C3p0Error> {
+        self.json.create_table_if_not_exists(conn).await?;
+        self.json
+            .create_index_if_not_exists(conn, &C3p0Index::new(KEY_FIELD, true))
+            .await
+    }
+
+    /// Returns the entry of the key, if it exists and is not expired
+    pub async fn get(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+    ) -> Result<Option<Model<KvEntry<V>>>, C3p0Error> {
+        Ok(self
+            .fetch_entry(conn, key)
+            .await?
+            .filter(|entry| !entry.data.is_expired()))
+    }
+
+    /// Returns the value of the key, if it exists and is not expired
+    pub async fn get_value(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+    ) -> Result<Option<V>, C3p0Error> {
+        Ok(self.get(conn, key).await?.map(|entry| entry.data.value))
+    }
+
+    /// Sets the value of the key, without expiration
+    pub async fn set(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+        value: V,
+    ) -> Result<Model<KvEntry<V>>, C3p0Error> {
+        self.write(conn, key, value, None, None).await
+    }
+
+    /// Sets the value of the key, that expires after the `ttl`
+    pub async fn set_with_ttl(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+        value: V,
+        ttl: Duration,
+    ) -> Result<Model<KvEntry<V>>, C3p0Error> {
+        self.write(conn, key, value, None, Some(ttl)).await
+    }
+
+    /// Sets the value of the key only if its entry has the expected version;
+    /// an expected version of None means that the key must not exist.
+    /// It fails with an `OptimisticLockError` otherwise.
+    pub async fn compare_and_set(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+        value: V,
+        expected_version: Option<VersionType>,
+        ttl: Option<Duration>,
+    ) -> Result<Model<KvEntry<V>>, C3p0Error> {
+        self.write(conn, key, value, Some(expected_version), ttl)
+            .await
+    }
+
+    /// Deletes the entry of the key; returns whether a not expired entry was deleted
+    pub async fn delete(&self, conn: &mut JSON::Conn, key: &str) -> Result<bool, C3p0Error> {
+        conn.lock(&self.lock_key(key)).await?;
+        match self.fetch_entry(conn, key).await? {
+            Some(entry) => {
+                let expired = entry.data.is_expired();
+                self.json.delete(conn, entry).await?;
+                Ok(!expired)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the not expired entries whose key starts with the prefix, ordered by key
+    pub async fn list_by_prefix(
+        &self,
+        conn: &mut JSON::Conn,
+        prefix: &str,
+    ) -> Result<Vec<Model<KvEntry<V>>>, C3p0Error> {
+        let now = now_millis();
+        let mut entries = self
+            .json
+            .fetch_all_by_field_prefix(conn, KEY_FIELD, prefix)
+            .await?
+            .into_iter()
+            .filter(|entry| !entry.data.is_expired_at(now))
+            .collect::<Vec<_>>();
+        entries.sort_by(|entry_1, entry_2| entry_1.data.key.cmp(&entry_2.data.key));
+        Ok(entries)
+    }
+
+    /// Deletes the expired entries; returns the number of deleted entries
+    pub async fn delete_expired(&self, conn: &mut JSON::Conn) -> Result<u64, C3p0Error> {
+        let now = now_millis();
+        let mut deleted = 0;
+        loop {
+            let entries = self
+                .json
+                .fetch_all_by_max_field_for_update(
+                    conn,
+                    EXPIRES_AT_FIELD,
+                    now,
+                    None,
+                    DELETE_EXPIRED_BATCH_SIZE,
+                    &ForUpdate::No,
+                )
+                .await?;
+            let batch_size = entries.len() as u32;
+            for entry in entries {
+                self.json.delete(conn, entry).await?;
+                deleted += 1;
+            }
+            if batch_size < DELETE_EXPIRED_BATCH_SIZE {
+                return Ok(deleted);
+            }
+        }
+    }
+
+    async fn fetch_entry(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+    ) -> Result<Option<Model<KvEntry<V>>>, C3p0Error> {
+        Ok(self
+            .json
+            .fetch_all_by_field(conn, KEY_FIELD, &Value::String(key.to_owned()))
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Writes the entry of the key.
+    /// If `expected_version` is Some, the existing entry must have that version.
+    async fn write(
+        &self,
+        conn: &mut JSON::Conn,
+        key: &str,
+        value: V,
+        expected_version: Option<Option<VersionType>>,
+        ttl: Option<Duration>,
+    ) -> Result<Model<KvEntry<V>>, C3p0Error> {
+        conn.lock(&self.lock_key(key)).await?;
+
+        let now = now_millis();
+        let data = KvEntry {
+            key: key.to_owned(),
+            value,
+            expires_at: ttl.map(|ttl| now + ttl.as_millis() as i64),
+        };
+        let existing = self.fetch_entry(conn, key).await?;
+
+        if let Some(expected_version) = expected_version {
+            let version = existing
+                .as_ref()
+                .filter(|entry| !entry.data.is_expired_at(now))
+                .map(|entry| entry.version);
+            if version != expected_version {
+                return Err(C3p0Error::OptimisticLockError {
+                    message: format!(
+                        "Cannot set the key [{}] with version [{:?}]: the current version is [{:?}]",
+                        key, expected_version, version
+                    ),
+                });
+            }
+        }
+
+        match existing {
+            Some(existing) => {
+                self.json
+                    .update(
+                        conn,
+                        Model {
+                            id: existing.id,
+                            version: existing.version,
+                            data,
+                        },
+                    )
+                    .await
+            }
+            None => self.json.save(conn, NewModel::new(data)).await,
+        }
+    }
+
+    /// Returns the transaction lock that serializes the writes of a key of the table.
+    /// The name is hashed, so that it fits the lock names of every database.
+    fn lock_key(&self, key: &str) -> LockKey {
+        LockKey::Id(
+            LockKey::from(format!(
+                "c3p0_kv:{}:{}",
+                self.json.qualified_table_name(),
+                key
+            ))
+            .id(),
+        )
+    }
+}
@@ -0,0 +1,165 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+struct Shared<T> {
+    value: Mutex<Option<T>>,
+    abandon: fn(T),
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let value = match self.value.get_mut() {
+            Ok(value) => value.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(value) = value {
+            (self.abandon)(value)
+        }
+    }
+}
+
+/// A value owned by a connection handed to a function, like the database connection of a transaction.
+/// When the `Lent` is dropped the value is given back to its `Lender`,
+/// so that the pool can end the transaction after the function completes.
+/// If the `Lender` is dropped without taking it back, because the function kept the connection
+/// or panicked, the value is passed to the `abandon` function given to `lend`.
+pub struct Lent<T> {
+    value: Option<T>,
+    shared: Arc<Shared<T>>,
+}
+
+/// Takes back the value of a `Lent`
+pub struct Lender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Lends the value; `abandon` receives it if it is not taken back by the returned `Lender`
+pub fn lend<T>(value: T, abandon: fn(T)) -> (Lent<T>, Lender<T>) {
+    let shared = Arc::new(Shared {
+        value: Mutex::new(None),
+        abandon,
+    });
+    (
+        Lent {
+            value: Some(value),
+            shared: shared.clone(),
+        },
+        Lender { shared },
+    )
+}
+
+impl<T> Lent<T> {
+    /// Moves the value to a new `Lent`, with the same abandon function, for a nested function;
+    /// it is restored by `restore`.
+    /// Until then, dereferencing this `Lent` panics.
+    pub fn lend_again(&mut self) -> (Lent<T>, Lender<T>) {
+        lend(self.take_value(), self.shared.abandon)
+    }
+
+    /// Takes back the value lent by `lend_again`; it returns false if the nested function kept it
+    pub fn restore(&mut self, lender: Lender<T>) -> bool {
+        match lender.take_back() {
+            Some(value) => {
+                self.value = Some(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn take_value(&mut self) -> T {
+        self.value.take().expect(Self::MOVED)
+    }
+
+    const MOVED: &'static str =
+        "The connection was used while lent to a nested function, or after the function kept it";
+}
+
+impl<T> Lender<T> {
+    /// Returns the value if the `Lent` was dropped, or None if it is still alive
+    pub fn take_back(self) -> Option<T> {
+        match self.shared.value.lock() {
+            Ok(mut value) => value.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        }
+    }
+}
+
+impl<T> Deref for Lent<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect(Self::MOVED)
+    }
+}
+
+impl<T> DerefMut for Lent<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect(Self::MOVED)
+    }
+}
+
+impl<T> Drop for Lent<T> {
+    fn drop(&mut self) {
+        if let Some(value) = self.value.take() {
+            match self.shared.value.lock() {
+                Ok(mut shared) => *shared = Some(value),
+                Err(poisoned) => *poisoned.into_inner() = Some(value),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static ABANDONED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn abandon(_: Vec<u8>) {
+        ABANDONED.with(|abandoned| abandoned.set(abandoned.get() + 1));
+    }
+
+    fn abandoned() -> usize {
+        ABANDONED.with(|abandoned| abandoned.get())
+    }
+
+    #[test]
+    fn should_give_back_the_value_when_dropped() {
+        let (mut lent, lender) = lend(vec![1], abandon);
+        lent.push(2);
+        drop(lent);
+        assert_eq!(Some(vec![1, 2]), lender.take_back());
+    }
+
+    #[test]
+    fn should_not_give_back_the_value_while_lent() {
+        let (lent, lender) = lend(vec![1], abandon);
+        assert_eq!(None, lender.take_back());
+        assert_eq!(0, abandoned());
+        drop(lent);
+        assert_eq!(1, abandoned());
+    }
+
+    #[test]
+    fn should_lend_the_value_again() {
+        let (mut lent, lender) = lend(vec![1], abandon);
+
+        let (mut nested, nested_lender) = lent.lend_again();
+        nested.push(2);
+        drop(nested);
+        assert!(lent.restore(nested_lender));
+        assert_eq!(vec![1, 2], *lent);
+
+        let (nested, nested_lender) = lent.lend_again();
+        assert!(!lent.restore(nested_lender));
+        drop(nested);
+        assert_eq!(1, abandoned());
+        drop(lent);
+        assert_eq!(None, lender.take_back());
+    }
+}
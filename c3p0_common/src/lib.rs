@@ -1,7 +1,17 @@
 pub mod error;
+pub mod event_store;
+pub mod hooks;
 pub mod json;
+pub mod kv;
+pub mod lend;
+pub mod lock;
+pub mod outbox;
 pub mod pool;
+pub mod queue;
+pub mod replica;
+pub mod retry;
 pub mod sql;
+pub mod trace;
 pub mod types;
 
 #[cfg(feature = "migrate")]
@@ -9,13 +19,52 @@ mod migrate;
 
 mod common {
     pub use crate::error::C3p0Error;
+    #[cfg(feature = "cbor")]
+    pub use crate::json::codec::CborCodec;
+    #[cfg(feature = "msgpack")]
+    pub use crate::json::codec::MsgPackCodec;
+    #[cfg(feature = "encryption")]
+    pub use crate::json::encryption::{
+        reencrypt_all, EncryptingCodec, EncryptionAlgorithm, EncryptionKey, KeyProvider,
+        StaticKeyProvider,
+    };
     pub use crate::json::{
-        builder::C3p0JsonBuilder, codec::DefaultJsonCodec, codec::JsonCodec, model::IdType,
-        model::Model, model::NewModel, model::VersionType, C3p0Json,
+        builder::C3p0JsonBuilder, cache::CachingC3p0Json, change_feed::change_feed_channel,
+        change_feed::change_feed_table_name, change_feed::listener_closed_error,
+        change_feed::C3p0ChangeFeed, change_feed::ChangeEvent, change_feed::ChangeOperation,
+        change_feed::ChangeStream, codec::BinaryCodec, codec::DefaultJsonCodec, codec::JsonCodec,
+        codec::VersionedCodec, column::BinaryColumn, column::DataColumn, column::JsonColumn,
+        compression::CompressingCodec, compression::Compression, compression::CompressionAlgorithm,
+        model::C3p0Index, model::C3p0Model, model::IdType, model::Model, model::NewModel,
+        model::VersionType, rewrite::count_stale, rewrite::rewrite_all,
+        rewrite::rewrite_all_with_progress, rewrite::RewriteProgress, schema::JsonSchema,
+        schema::ValidatingCodec, transfer::export_table, transfer::import_table,
+        transfer::ImportMode, transfer::ImportReport, C3p0Json,
+    };
+
+    pub use crate::event_store::{
+        C3p0EventStore, C3p0SnapshotStore, EventStream, ExpectedVersion, Snapshot, StoredEvent,
     };
+
+    pub use crate::hooks::{C3p0Hooks, TransactionHooks};
+
+    pub use crate::kv::{C3p0Kv, KvEntry};
+
+    pub use crate::lock::{C3p0Lock, C3p0SessionLock, LockKey, LockRelease, SessionLock};
+
+    pub use crate::outbox::{C3p0Outbox, OutboxMessage, OutboxPublisher, RelayReport};
+
+    pub use crate::queue::{C3p0JobQueue, FailedJob, Job, NewJob};
+
+    pub use crate::replica::ReplicatedC3p0Pool;
+
+    pub use crate::retry::{RetryPolicy, RetryableError};
+
     pub use crate::sql::{ForUpdate, OrderBy};
 
-    pub use crate::pool::{C3p0Pool, SqlConnection};
+    pub use crate::pool::{
+        C3p0Pool, C3p0Savepoint, IsolationLevel, PoolStatus, SqlConnection, TxOptions,
+    };
 
     #[cfg(feature = "migrate")]
     pub use crate::migrate::{
@@ -26,3 +75,7 @@ mod common {
 }
 
 pub use crate::common::*;
+
+/// Used by the code generated by the c3p0_macro crate
+#[doc(hidden)]
+pub use serde_json;
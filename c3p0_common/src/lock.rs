@@ -0,0 +1,173 @@
+use crate::error::C3p0Error;
+use async_trait::async_trait;
+use std::fmt;
+use std::time::Duration;
+
+/// The key of an advisory lock
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LockKey {
+    Id(i64),
+    Name(String),
+}
+
+impl LockKey {
+    /// Returns the numeric key of the lock, used by the databases that lock numbers.
+    /// A name is mapped to the 64-bit FNV-1a hash of its bytes, so it is stable across processes.
+    pub fn id(&self) -> i64 {
+        match self {
+            LockKey::Id(id) => *id,
+            LockKey::Name(name) => stable_hash(name) as i64,
+        }
+    }
+
+    /// Returns the name of the lock, used by the databases that lock strings.
+    /// MySQL rejects names longer than 64 characters.
+    pub fn name(&self) -> String {
+        match self {
+            LockKey::Id(id) => format!("c3p0_lock_{}", id),
+            LockKey::Name(name) => name.clone(),
+        }
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of the bytes of the name
+pub(crate) fn stable_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl fmt::Display for LockKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockKey::Id(id) => write!(f, "{}", id),
+            LockKey::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<i64> for LockKey {
+    fn from(id: i64) -> Self {
+        LockKey::Id(id)
+    }
+}
+
+impl From<&str> for LockKey {
+    fn from(name: &str) -> Self {
+        LockKey::Name(name.to_owned())
+    }
+}
+
+impl From<String> for LockKey {
+    fn from(name: String) -> Self {
+        LockKey::Name(name)
+    }
+}
+
+/// Returns the error of a transaction lock requested outside of a transaction
+pub fn no_transaction_error(key: &LockKey) -> C3p0Error {
+    C3p0Error::InternalError {
+        cause: format!(
+            "Cannot acquire the lock [{}]: the transaction locks require a transaction",
+            key
+        ),
+    }
+}
+
+/// Transaction-scoped advisory locks, implemented by the connections.
+/// The locks are released when the transaction commits or rolls back;
+/// on a connection outside of a transaction (see `C3p0Pool::connection`) they fail
+/// with the error returned by `no_transaction_error`.
+#[async_trait]
+pub trait C3p0Lock: Send {
+    /// Waits until the lock is acquired
+    async fn lock(&mut self, key: &LockKey) -> Result<(), C3p0Error>;
+
+    /// Acquires the lock if it is free; returns whether it was acquired
+    async fn try_lock(&mut self, key: &LockKey) -> Result<bool, C3p0Error>;
+
+    /// Waits at most `timeout` for the lock; returns whether it was acquired.
+    /// A failed attempt does not abort the transaction.
+    async fn lock_with_timeout(
+        &mut self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<bool, C3p0Error>;
+}
+
+/// Session-scoped advisory locks, implemented by the pools.
+/// Every lock holds a dedicated connection until it is released;
+/// they allow, for example, electing a leader or running a job on a single replica.
+#[async_trait]
+pub trait C3p0SessionLock {
+    /// Waits until the lock is acquired
+    async fn session_lock(&self, key: &LockKey) -> Result<SessionLock, C3p0Error>;
+
+    /// Acquires the lock if it is free
+    async fn try_session_lock(&self, key: &LockKey) -> Result<Option<SessionLock>, C3p0Error>;
+
+    /// Waits at most `timeout` for the lock
+    async fn session_lock_with_timeout(
+        &self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<Option<SessionLock>, C3p0Error>;
+}
+
+/// Releases a session lock; it is implemented by the backends.
+/// If it is dropped without being released, it must release the lock anyway,
+/// for example by closing the connection that holds it.
+#[async_trait]
+pub trait LockRelease: Send + Sync {
+    async fn release(self: Box<Self>) -> Result<(), C3p0Error>;
+}
+
+/// A session-scoped lock held until it is released or dropped
+pub struct SessionLock {
+    key: LockKey,
+    release: Box<dyn LockRelease>,
+}
+
+impl SessionLock {
+    pub fn new(key: LockKey, release: Box<dyn LockRelease>) -> Self {
+        SessionLock { key, release }
+    }
+
+    pub fn key(&self) -> &LockKey {
+        &self.key
+    }
+
+    /// Releases the lock and gives its connection back to the pool
+    pub async fn release(self) -> Result<(), C3p0Error> {
+        self.release.release().await
+    }
+}
+
+impl fmt::Debug for SessionLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionLock")
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_map_the_names_to_stable_ids() {
+        assert_eq!(0xcbf2_9ce4_8422_2325u64 as i64, LockKey::from("").id());
+        assert_eq!(0xaf63_dc4c_8601_ec8cu64 as i64, LockKey::from("a").id());
+        assert_eq!(LockKey::from("leader").id(), LockKey::from("leader").id());
+        assert_ne!(LockKey::from("leader").id(), LockKey::from("leaders").id());
+        assert_eq!(42, LockKey::from(42).id());
+    }
+
+    #[test]
+    fn should_map_the_ids_to_names() {
+        assert_eq!("c3p0_lock_42", LockKey::from(42).name());
+        assert_eq!("leader", LockKey::from("leader").name());
+    }
+}
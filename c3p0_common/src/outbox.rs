@@ -0,0 +1,313 @@
+use crate::error::C3p0Error;
+use crate::json::codec::DefaultJsonCodec;
+use crate::json::model::{C3p0Index, Model, NewModel};
+use crate::json::C3p0Json;
+use crate::pool::C3p0Pool;
+use crate::sql::ForUpdate;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const NEXT_ATTEMPT_AT_FIELD: &str = "next_attempt_at";
+const SENT_AT_FIELD: &str = "sent_at";
+
+/// A message of the outbox table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub topic: String,
+    pub payload: Value,
+    /// When the message was enqueued, in milliseconds since the Unix epoch
+    pub created_at: i64,
+    /// The number of failed publishing attempts
+    pub attempts: u32,
+    /// When the message is due to be published, in milliseconds since the Unix epoch.
+    /// It is None once the message is sent or has failed `max_attempts` times.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<i64>,
+    /// When the message was published, in milliseconds since the Unix epoch
+    pub sent_at: Option<i64>,
+    /// The error of the last failed publishing attempt
+    pub last_error: Option<String>,
+}
+
+impl OutboxMessage {
+    pub fn is_sent(&self) -> bool {
+        self.sent_at.is_some()
+    }
+
+    /// Whether the message will never be sent because it has failed too many times
+    pub fn is_failed(&self) -> bool {
+        self.sent_at.is_none() && self.next_attempt_at.is_none()
+    }
+}
+
+/// Publishes the outbox messages to the external system (e.g. a message broker)
+#[async_trait]
+pub trait OutboxPublisher: Send + Sync {
+    async fn publish(
+        &self,
+        message: &Model<OutboxMessage>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// The outcome of the relay of the outbox messages
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RelayReport {
+    /// The number of claimed messages
+    pub claimed: u64,
+    /// The number of published messages
+    pub sent: u64,
+    /// The number of messages whose publishing failed and that are scheduled for a retry
+    pub retried: u64,
+    /// The number of messages whose publishing failed for the last time
+    pub failed: u64,
+}
+
+/// A transactional outbox.
+/// The messages are written with `enqueue` in the same transaction that changes the data,
+/// so they are stored if and only if the transaction commits;
+/// then, the relay hands them to an `OutboxPublisher`.
+///
+/// Every batch of messages is claimed with `ForUpdate::SkipLocked`, so many relays can run concurrently,
+/// and the outcome of the publishing is written in the same transaction.
+/// The delivery is at-least-once: a message is published again if its transaction fails after
+/// the publishing. The messages are relayed in enqueue order, but a retried message is
+/// relayed after the messages enqueued before its retry is due.
+///
+/// The sent messages are kept in the table until they are deleted by `purge_sent`,
+/// that should run periodically.
+#[derive(Clone)]
+pub struct C3p0Outbox<C3P0, JSON> {
+    pool: C3P0,
+    json: JSON,
+    batch_size: u32,
+    max_attempts: u32,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+}
+
+impl<C3P0, JSON> C3p0Outbox<C3P0, JSON>
+where
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<OutboxMessage, DefaultJsonCodec, Conn = C3P0::Conn>,
+{
+    /// Creates an outbox stored in the table of the given C3p0Json
+    pub fn new(pool: C3P0, json: JSON) -> Self {
+        C3p0Outbox {
+            pool,
+            json,
+            batch_size: 100,
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(300),
+        }
+    }
+
+    /// Sets the maximum number of messages claimed by every relay transaction
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Sets the number of failed publishing attempts after which a message is no longer retried
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry of a message;
+    /// it doubles at every failed attempt up to `max_retry_delay`
+    pub fn with_retry_delay(mut self, retry_delay: Duration, max_retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self.max_retry_delay = max_retry_delay;
+        self
+    }
+
+    pub fn json(&self) -> &JSON {
+        &self.json
+    }
+
+    /// Creates the table and the numeric indexes on the due and the sent times of the messages,
+    /// that keep the relay and the purge from scanning the whole table
+    pub async fn create_table_if_not_exists(&self, conn: &mut C3P0::Conn) -> Result<(), C3p0Error> {
+        self.json.create_table_if_not_exists(conn).await?;
+        self.json
+            .create_index_if_not_exists(conn, &C3p0Index::numeric(NEXT_ATTEMPT_AT_FIELD))
+            .await?;
+        self.json
+            .create_index_if_not_exists(conn, &C3p0Index::numeric(SENT_AT_FIELD))
+            .await
+    }
+
+    /// Writes a message in the outbox.
+    /// The connection should be the one of the transaction that changes the data the message is about.
+    pub async fn enqueue<T: Serialize + ?Sized>(
+        &self,
+        conn: &mut C3P0::Conn,
+        topic: &str,
+        payload: &T,
+    ) -> Result<Model<OutboxMessage>, C3p0Error> {
+        let now = now_millis();
+        self.json
+            .save(
+                conn,
+                NewModel::new(OutboxMessage {
+                    topic: topic.to_owned(),
+                    payload: serde_json::to_value(payload)?,
+                    created_at: now,
+                    attempts: 0,
+                    next_attempt_at: Some(now),
+                    sent_at: None,
+                    last_error: None,
+                }),
+            )
+            .await
+    }
+
+    /// Claims a batch of due messages, publishes them and marks them as sent
+    /// or schedules their retry, in a single transaction
+    pub async fn relay_batch<P: OutboxPublisher>(
+        &self,
+        publisher: &P,
+    ) -> Result<RelayReport, C3p0Error> {
+        let json = &self.json;
+        self.pool
+            .transaction(|mut conn| async move {
+                let messages = json
+                    .fetch_all_by_max_field_for_update(
+                        &mut conn,
+                        NEXT_ATTEMPT_AT_FIELD,
+                        now_millis(),
+                        None,
+                        self.batch_size,
+                        &ForUpdate::SkipLocked,
+                    )
+                    .await?;
+
+                let mut report = RelayReport {
+                    claimed: messages.len() as u64,
+                    ..RelayReport::default()
+                };
+
+                for mut message in messages {
+                    match publisher.publish(&message).await {
+                        Ok(()) => {
+                            message.data.sent_at = Some(now_millis());
+                            message.data.next_attempt_at = None;
+                            report.sent += 1;
+                        }
+                        Err(err) => {
+                            message.data.attempts += 1;
+                            message.data.last_error = Some(err.to_string());
+                            if message.data.attempts >= self.max_attempts {
+                                message.data.next_attempt_at = None;
+                                report.failed += 1;
+                            } else {
+                                let delay = retry_delay(
+                                    self.retry_delay,
+                                    self.max_retry_delay,
+                                    message.data.attempts,
+                                );
+                                message.data.next_attempt_at =
+                                    Some(now_millis() + delay.as_millis() as i64);
+                                report.retried += 1;
+                            }
+                        }
+                    }
+                    json.update(&mut conn, message).await?;
+                }
+
+                Ok(report)
+            })
+            .await
+    }
+
+    /// Relays batches of messages until no due messages are left
+    pub async fn relay_all<P: OutboxPublisher>(
+        &self,
+        publisher: &P,
+    ) -> Result<RelayReport, C3p0Error> {
+        let mut report = RelayReport::default();
+        loop {
+            let batch = self.relay_batch(publisher).await?;
+            report.claimed += batch.claimed;
+            report.sent += batch.sent;
+            report.retried += batch.retried;
+            report.failed += batch.failed;
+            if batch.claimed < self.batch_size as u64 {
+                return Ok(report);
+            }
+        }
+    }
+
+    /// Deletes the messages sent more than `older_than` ago;
+    /// returns the number of deleted messages
+    pub async fn purge_sent(
+        &self,
+        conn: &mut C3P0::Conn,
+        older_than: Duration,
+    ) -> Result<u64, C3p0Error> {
+        let sent_before = now_millis() - older_than.as_millis() as i64;
+        let mut deleted = 0;
+        loop {
+            let messages = self
+                .json
+                .fetch_all_by_max_field_for_update(
+                    conn,
+                    SENT_AT_FIELD,
+                    sent_before,
+                    None,
+                    self.batch_size,
+                    &ForUpdate::No,
+                )
+                .await?;
+            let batch_size = messages.len() as u32;
+            for message in messages {
+                self.json.delete(conn, message).await?;
+                deleted += 1;
+            }
+            if batch_size < self.batch_size {
+                return Ok(deleted);
+            }
+        }
+    }
+}
+
+/// Returns the delay before the next attempt, doubling the initial delay at every failed attempt
+pub(crate) fn retry_delay(
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    attempts: u32,
+) -> Duration {
+    2u32.checked_pow(attempts.saturating_sub(1))
+        .and_then(|factor| retry_delay.checked_mul(factor))
+        .map(|delay| delay.min(max_retry_delay))
+        .unwrap_or(max_retry_delay)
+}
+
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_double_the_retry_delay() {
+        let delay = |attempts| {
+            retry_delay(Duration::from_secs(1), Duration::from_secs(60), attempts).as_secs()
+        };
+        assert_eq!(1, delay(1));
+        assert_eq!(2, delay(2));
+        assert_eq!(32, delay(6));
+        assert_eq!(60, delay(7));
+        assert_eq!(60, delay(100));
+    }
+}
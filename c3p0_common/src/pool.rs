@@ -1,14 +1,228 @@
 use crate::error::C3p0Error;
+use crate::hooks::C3p0Hooks;
+use crate::retry::{RetryPolicy, RetryableError};
 
 use async_trait::async_trait;
 use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The isolation level of a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IsolationLevel {
+    ReadUncommitted,
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Returns the name of the level in the `SET TRANSACTION` statement
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            IsolationLevel::ReadUncommitted => "READ UNCOMMITTED",
+            IsolationLevel::ReadCommitted => "READ COMMITTED",
+            IsolationLevel::RepeatableRead => "REPEATABLE READ",
+            IsolationLevel::Serializable => "SERIALIZABLE",
+        }
+    }
+}
+
+/// The options of a transaction started by `C3p0Pool::transaction_with_options`.
+/// The default options leave the database defaults in place.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxOptions {
+    /// The isolation level; None keeps the default level of the database
+    pub isolation: Option<IsolationLevel>,
+    /// Whether the transaction cannot write
+    pub read_only: bool,
+    /// Whether a serializable read-only transaction waits until it can run without
+    /// serialization failures; it is supported only by Postgres
+    pub deferrable: bool,
+    /// The maximum duration of every statement, enforced by the database:
+    /// `statement_timeout` on Postgres, `max_execution_time` on MySQL, where it applies
+    /// only to the read-only SELECT statements
+    pub statement_timeout: Option<Duration>,
+    /// The maximum wait for a row lock, enforced by the database:
+    /// `lock_timeout` on Postgres, `innodb_lock_wait_timeout` on MySQL, in whole seconds
+    pub lock_timeout: Option<Duration>,
+    /// The maximum duration of the transaction function, enforced by the client.
+    /// When it expires the function is dropped, the transaction is rolled back
+    /// and its connection is closed instead of being returned to the pool,
+    /// because a statement could still be running.
+    pub timeout: Option<Duration>,
+}
+
+impl TxOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_isolation(mut self, isolation: IsolationLevel) -> Self {
+        self.isolation = Some(isolation);
+        self
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn with_deferrable(mut self, deferrable: bool) -> Self {
+        self.deferrable = deferrable;
+        self
+    }
+
+    pub fn with_statement_timeout(mut self, statement_timeout: Duration) -> Self {
+        self.statement_timeout = Some(statement_timeout);
+        self
+    }
+
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// Runs the function of a transaction within the client-side timeout of the options, if any;
+/// it returns None if the timeout expires
+pub async fn with_client_timeout<Fut: Future>(
+    options: &TxOptions,
+    fut: Fut,
+) -> Option<Fut::Output> {
+    match options.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Returns the error of a transaction function that exceeded the client-side timeout
+pub fn client_timeout_error(db: &'static str, options: &TxOptions) -> C3p0Error {
+    C3p0Error::TimeoutError {
+        db,
+        cause: format!(
+            "The transaction did not complete within the timeout of {:?}",
+            options.timeout.unwrap_or_default()
+        ),
+        code: None,
+    }
+}
+
+/// The status of the connections of a pool, returned by `C3p0Pool::status`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolStatus {
+    /// The number of open connections
+    pub size: usize,
+    /// The number of open connections that are not in use
+    pub idle: usize,
+    /// The number of connections in use
+    pub in_use: usize,
+    /// The number of tasks waiting for a connection
+    pub waiters: usize,
+}
+
+/// Returns the error of a pool that was closed by `C3p0Pool::close`
+pub fn pool_closed_error(db: &'static str, pool: &'static str) -> C3p0Error {
+    C3p0Error::PoolError {
+        db,
+        pool,
+        cause: "The pool is closed".to_owned(),
+    }
+}
+
+/// Counts the tasks waiting for a connection, for the pools that do not count them
+#[derive(Clone, Default)]
+pub struct Waiters(Arc<AtomicUsize>);
+
+impl Waiters {
+    /// Awaits `fut`, a connection request, counting the task as a waiter until it completes
+    pub async fn wait<Fut: Future>(&self, fut: Fut) -> Fut::Output {
+        struct Waiting<'a>(&'a AtomicUsize);
+
+        impl Drop for Waiting<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        self.0.fetch_add(1, Ordering::SeqCst);
+        let _waiting = Waiting(&self.0);
+        fut.await
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
 #[async_trait]
 pub trait C3p0Pool: Clone + Send + Sync {
     type Conn;
 
-    //    async fn connection(&self) -> Result<Self::CONN, C3p0Error>;
+    /// Returns the status of the connections of the pool
+    fn status(&self) -> PoolStatus;
+
+    /// Checks that a connection can be acquired and that the database answers
+    async fn ping(&self) -> Result<(), C3p0Error>;
+
+    /// Closes the pool: the new requests fail, the idle connections are closed and the
+    /// connections in use are closed when they are released.
+    /// It waits until every connection is closed, so the running transactions can complete.
+    async fn close(&self);
+
+    /// Waits until `ping` succeeds, retrying with a backoff, for at most the timeout.
+    /// It returns the error of the last attempt, or a `TimeoutError` if the last attempt
+    /// did not complete within the timeout.
+    async fn wait_until_ready(&self, timeout: Duration) -> Result<(), C3p0Error> {
+        let deadline = Instant::now() + timeout;
+        let policy = RetryPolicy::new();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let err = match tokio::time::timeout(remaining, self.ping()).await {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(err)) => err,
+                Err(_) => {
+                    return Err(C3p0Error::TimeoutError {
+                        db: "c3p0",
+                        cause: format!(
+                            "The pool was not ready within the timeout of {:?}",
+                            timeout
+                        ),
+                        code: None,
+                    })
+                }
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Err(err);
+            }
+            tokio::time::delay_for(policy.backoff(attempt).min(remaining)).await;
+        }
+    }
 
+    /// Runs `f` with a connection outside of a transaction:
+    /// every statement is committed as soon as it is executed, so the statements that cannot run
+    /// in a transaction, like `CREATE INDEX CONCURRENTLY` or `VACUUM`, are allowed.
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E>;
+
+    /// Runs `tx` in a transaction with the default options of the database.
+    /// The transaction is committed if `tx` succeeds, otherwise it is rolled back.
     async fn transaction<
         T: Send,
         E: Send + From<C3p0Error>,
@@ -17,6 +231,64 @@ pub trait C3p0Pool: Clone + Send + Sync {
     >(
         &self,
         tx: F,
+    ) -> Result<T, E> {
+        self.transaction_with_options(&TxOptions::default(), tx)
+            .await
+    }
+
+    /// Runs `tx` in a transaction with the default options of the database, and runs it again
+    /// in a new transaction while it fails with a retryable error, like a serialization failure
+    /// or a deadlock, as allowed by the policy.
+    /// The error of the last attempt is returned.
+    async fn transaction_with_retry<
+        T: Send,
+        E: Send + From<C3p0Error> + RetryableError,
+        F: Send + Sync + Fn(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        policy: &RetryPolicy,
+        tx: F,
+    ) -> Result<T, E> {
+        self.transaction_with_options_and_retry(&TxOptions::default(), policy, tx)
+            .await
+    }
+
+    /// Like `transaction_with_retry`, but the transactions are started with the given options
+    async fn transaction_with_options_and_retry<
+        T: Send,
+        E: Send + From<C3p0Error> + RetryableError,
+        F: Send + Sync + Fn(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        options: &TxOptions,
+        policy: &RetryPolicy,
+        tx: F,
+    ) -> Result<T, E> {
+        let tx = &tx;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match self.transaction_with_options(options, tx).await {
+                Err(err) if err.is_retryable() && policy.can_retry(attempt) => {
+                    tokio::time::delay_for(policy.backoff(attempt)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Like `transaction`, but the transaction is started with the given options
+    async fn transaction_with_options<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        options: &TxOptions,
+        tx: F,
     ) -> Result<T, E>;
 }
 
@@ -24,3 +296,109 @@ pub trait C3p0Pool: Clone + Send + Sync {
 pub trait SqlConnection: Send {
     async fn batch_execute(&mut self, sql: &str) -> Result<(), C3p0Error>;
 }
+
+/// Nested transactions, implemented by the connections.
+/// It allows to compose atomic operations inside a transaction without knowing
+/// whether the caller already started one.
+#[async_trait]
+pub trait C3p0Savepoint: Sized + Send {
+    /// Runs `f` in a savepoint of the current transaction.
+    /// The savepoint is released if `f` succeeds, otherwise the transaction is rolled back
+    /// to the savepoint, so only the changes made by `f` are discarded and the transaction can continue.
+    /// The savepoints can be nested; on a connection outside of a transaction
+    /// (see `C3p0Pool::connection`) it fails with the error returned by `no_transaction_savepoint_error`.
+    async fn savepoint<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &mut self,
+        f: F,
+    ) -> Result<T, E>;
+}
+
+/// Returns the error of a savepoint requested outside of a transaction
+pub fn no_transaction_savepoint_error() -> C3p0Error {
+    C3p0Error::InternalError {
+        cause: "Cannot create a savepoint: the savepoints require a transaction".to_owned(),
+    }
+}
+
+/// Returns the error of a transaction function that kept its connection, for example returning it
+/// or moving it to another task; the transaction is rolled back
+pub fn connection_not_released_error() -> C3p0Error {
+    C3p0Error::InternalError {
+        cause: "The connection was not released when the function returned, the transaction is rolled back"
+            .to_owned(),
+    }
+}
+
+/// Returns a savepoint name unique in the process, so that nested savepoints do not replace each other
+pub fn savepoint_name() -> String {
+    static NEXT_SAVEPOINT: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "c3p0_savepoint_{}",
+        NEXT_SAVEPOINT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// A connection of a SQL backend that can move its transaction to the connection of a savepoint.
+/// It is used by `sql_savepoint`.
+pub trait SqlSavepointConnection: SqlConnection + C3p0Hooks + Sized {
+    type Lender: Send;
+
+    /// Moves the transaction to a new connection sharing the hooks; `self` cannot be used
+    /// until the transaction is restored. It fails outside of a transaction.
+    fn lend_transaction(&mut self) -> Result<(Self, Self::Lender), C3p0Error>;
+
+    /// Takes back the transaction moved by `lend_transaction` after the savepoint connection
+    /// is dropped; it returns false if the savepoint function kept the connection.
+    fn restore_transaction(&mut self, lender: Self::Lender) -> bool;
+}
+
+/// Runs `f` in a savepoint created with the SQL statements, with a connection
+/// to the transaction of `conn`.
+/// It is used by the SQL backends to implement `C3p0Savepoint`.
+pub async fn sql_savepoint<C, T, E, F, Fut>(conn: &mut C, f: F) -> Result<T, E>
+where
+    C: SqlSavepointConnection,
+    E: From<C3p0Error>,
+    F: FnOnce(C) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let name = savepoint_name();
+    let (mut savepoint_conn, lender) = conn.lend_transaction()?;
+    let mark = conn.hooks().mark();
+
+    let result = match savepoint_conn
+        .batch_execute(&format!("SAVEPOINT {}", name))
+        .await
+    {
+        Ok(()) => Ok((f)(savepoint_conn).await),
+        Err(err) => {
+            drop(savepoint_conn);
+            Err(err)
+        }
+    };
+    if !conn.restore_transaction(lender) {
+        return Err(connection_not_released_error().into());
+    }
+
+    match result? {
+        Ok(result) => {
+            conn.batch_execute(&format!("RELEASE SAVEPOINT {}", name))
+                .await?;
+            Ok(result)
+        }
+        Err(err) => {
+            conn.batch_execute(&format!(
+                "ROLLBACK TO SAVEPOINT {name}; RELEASE SAVEPOINT {name}",
+                name = name
+            ))
+            .await?;
+            conn.hooks().rolled_back_to(mark).await;
+            Err(err)
+        }
+    }
+}
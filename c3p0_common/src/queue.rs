@@ -0,0 +1,277 @@
+use crate::error::C3p0Error;
+use crate::json::model::{Model, NewModel};
+use crate::json::C3p0Json;
+use crate::outbox::{now_millis, retry_delay};
+use crate::pool::C3p0Pool;
+use crate::sql::{ForUpdate, OrderBy};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+const DUE_AT_FIELD: &str = "due_at";
+const PRIORITY_FIELD: &str = "priority";
+
+/// A job of a C3p0JobQueue
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job<T> {
+    pub payload: T,
+    /// The jobs with a higher priority are claimed first
+    pub priority: i32,
+    /// When the job can be claimed, in milliseconds since the Unix epoch.
+    /// While the job is claimed, it is the end of its visibility timeout.
+    pub due_at: i64,
+    /// When the job was enqueued, in milliseconds since the Unix epoch
+    pub created_at: i64,
+    /// The number of times the job was claimed
+    pub attempts: u32,
+    /// The error of the last failed attempt
+    pub last_error: Option<String>,
+}
+
+/// A job to be enqueued
+#[derive(Clone, Debug)]
+pub struct NewJob<T> {
+    pub payload: T,
+    pub priority: i32,
+    /// When the job can be claimed, in milliseconds since the Unix epoch; None means immediately
+    pub run_at: Option<i64>,
+}
+
+impl<T> NewJob<T> {
+    pub fn new(payload: T) -> Self {
+        NewJob {
+            payload,
+            priority: 0,
+            run_at: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_run_at(mut self, run_at: i64) -> Self {
+        self.run_at = Some(run_at);
+        self
+    }
+}
+
+impl<T> From<T> for NewJob<T> {
+    fn from(payload: T) -> Self {
+        NewJob::new(payload)
+    }
+}
+
+/// The outcome of a failed job
+#[derive(Clone, Debug, PartialEq)]
+pub enum FailedJob<T>
+where
+    T: Clone + Serialize + serde::de::DeserializeOwned + Send,
+{
+    /// The job is scheduled for a retry
+    Retried(Model<Job<T>>),
+    /// The job failed `max_attempts` times and was moved to the dead-letter table
+    DeadLettered(Model<Job<T>>),
+}
+
+/// A durable job queue.
+/// The jobs are claimed in a short transaction with `ForUpdate::SkipLocked`, by descending priority
+/// and then by due time, so many workers can poll the queue concurrently.
+/// A claimed job stays invisible for the visibility timeout: if the worker neither completes
+/// nor fails it in time, for example because the worker crashed, the job is claimed again.
+///
+/// The jobs are stored in the table of the queue C3p0Json, while the jobs that failed
+/// `max_attempts` times are moved to the table of the dead-letter C3p0Json.
+#[derive(Clone)]
+pub struct C3p0JobQueue<T, CODEC, C3P0, JSON> {
+    pool: C3P0,
+    json: JSON,
+    dead_letter_json: JSON,
+    visibility_timeout: Duration,
+    max_attempts: u32,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    phantom: PhantomData<fn() -> (T, CODEC)>,
+}
+
+impl<T, CODEC, C3P0, JSON> C3p0JobQueue<T, CODEC, C3P0, JSON>
+where
+    T: Clone + Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Send + Sync,
+    C3P0: C3p0Pool,
+    C3P0::Conn: Send,
+    JSON: C3p0Json<Job<T>, CODEC, Conn = C3P0::Conn>,
+{
+    /// Creates a queue stored in the table of `json`,
+    /// that moves the dead jobs to the table of `dead_letter_json`
+    pub fn new(pool: C3P0, json: JSON, dead_letter_json: JSON) -> Self {
+        C3p0JobQueue {
+            pool,
+            json,
+            dead_letter_json,
+            visibility_timeout: Duration::from_secs(300),
+            max_attempts: 10,
+            retry_delay: Duration::from_secs(1),
+            max_retry_delay: Duration::from_secs(300),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sets for how long a claimed job is not returned by the other claims
+    pub fn with_visibility_timeout(mut self, visibility_timeout: Duration) -> Self {
+        self.visibility_timeout = visibility_timeout;
+        self
+    }
+
+    /// Sets the number of attempts after which a job is moved to the dead-letter table
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the delay before the first retry of a job;
+    /// it doubles at every failed attempt up to `max_retry_delay`
+    pub fn with_retry_delay(mut self, retry_delay: Duration, max_retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self.max_retry_delay = max_retry_delay;
+        self
+    }
+
+    pub fn json(&self) -> &JSON {
+        &self.json
+    }
+
+    pub fn dead_letter_json(&self) -> &JSON {
+        &self.dead_letter_json
+    }
+
+    pub async fn create_tables_if_not_exists(
+        &self,
+        conn: &mut C3P0::Conn,
+    ) -> Result<(), C3p0Error> {
+        self.json.create_table_if_not_exists(conn).await?;
+        self.dead_letter_json.create_table_if_not_exists(conn).await
+    }
+
+    /// Writes a job in the queue.
+    /// If the connection is the one of a transaction, the job is enqueued only if it commits.
+    pub async fn enqueue(
+        &self,
+        conn: &mut C3P0::Conn,
+        job: NewJob<T>,
+    ) -> Result<Model<Job<T>>, C3p0Error> {
+        let now = now_millis();
+        self.json
+            .save(
+                conn,
+                NewModel::new(Job {
+                    payload: job.payload,
+                    priority: job.priority,
+                    due_at: job.run_at.unwrap_or(now),
+                    created_at: now,
+                    attempts: 0,
+                    last_error: None,
+                }),
+            )
+            .await
+    }
+
+    /// Claims at most `limit` due jobs and hides them for the visibility timeout.
+    /// The jobs whose last attempt timed out are moved to the dead-letter table instead.
+    pub async fn claim(&self, limit: u32) -> Result<Vec<Model<Job<T>>>, C3p0Error> {
+        self.pool
+            .transaction(|mut conn| async move {
+                let now = now_millis();
+                let jobs = self
+                    .json
+                    .fetch_all_by_max_field_for_update(
+                        &mut conn,
+                        DUE_AT_FIELD,
+                        now,
+                        Some((PRIORITY_FIELD, &OrderBy::Desc)),
+                        limit,
+                        &ForUpdate::SkipLocked,
+                    )
+                    .await?;
+
+                let mut claimed = Vec::with_capacity(jobs.len());
+                for mut job in jobs {
+                    if job.data.attempts >= self.max_attempts {
+                        job.data.last_error = Some("Visibility timeout expired".to_owned());
+                        self.move_to_dead_letter(&mut conn, job).await?;
+                    } else {
+                        job.data.attempts += 1;
+                        job.data.due_at = now + self.visibility_timeout.as_millis() as i64;
+                        claimed.push(self.json.update(&mut conn, job).await?);
+                    }
+                }
+                Ok(claimed)
+            })
+            .await
+    }
+
+    /// Hides a claimed job for another visibility timeout.
+    /// Workers running long jobs should call it periodically.
+    pub async fn extend_visibility(
+        &self,
+        conn: &mut C3P0::Conn,
+        mut job: Model<Job<T>>,
+    ) -> Result<Model<Job<T>>, C3p0Error> {
+        job.data.due_at = now_millis() + self.visibility_timeout.as_millis() as i64;
+        self.json.update(conn, job).await
+    }
+
+    /// Removes a completed job from the queue.
+    /// It fails with an `OptimisticLockError` if the job was claimed again after its visibility timeout.
+    pub async fn complete(
+        &self,
+        conn: &mut C3P0::Conn,
+        job: Model<Job<T>>,
+    ) -> Result<Model<Job<T>>, C3p0Error> {
+        self.json.delete(conn, job).await
+    }
+
+    /// Schedules the retry of a failed job, or moves it to the dead-letter table
+    /// if it failed `max_attempts` times
+    pub async fn fail(
+        &self,
+        conn: &mut C3P0::Conn,
+        mut job: Model<Job<T>>,
+        error: &str,
+    ) -> Result<FailedJob<T>, C3p0Error> {
+        job.data.last_error = Some(error.to_owned());
+        if job.data.attempts >= self.max_attempts {
+            Ok(FailedJob::DeadLettered(
+                self.move_to_dead_letter(conn, job).await?,
+            ))
+        } else {
+            let delay = retry_delay(self.retry_delay, self.max_retry_delay, job.data.attempts);
+            job.data.due_at = now_millis() + delay.as_millis() as i64;
+            Ok(FailedJob::Retried(self.json.update(conn, job).await?))
+        }
+    }
+
+    /// Moves a job to the dead-letter table without further retries;
+    /// for example, when its error is not transient
+    pub async fn dead_letter(
+        &self,
+        conn: &mut C3P0::Conn,
+        mut job: Model<Job<T>>,
+        error: &str,
+    ) -> Result<Model<Job<T>>, C3p0Error> {
+        job.data.last_error = Some(error.to_owned());
+        self.move_to_dead_letter(conn, job).await
+    }
+
+    async fn move_to_dead_letter(
+        &self,
+        conn: &mut C3P0::Conn,
+        job: Model<Job<T>>,
+    ) -> Result<Model<Job<T>>, C3p0Error> {
+        let job = self.json.delete(conn, job).await?;
+        self.dead_letter_json
+            .save(conn, NewModel::new(job.data))
+            .await
+    }
+}
@@ -0,0 +1,385 @@
+use crate::error::C3p0Error;
+use crate::pool::{C3p0Pool, PoolStatus, TxOptions};
+
+use async_trait::async_trait;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const DEFAULT_UNHEALTHY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A pool that runs the transactions on a primary database and the read-only transactions
+/// on its replicas.
+///
+/// `transaction` and `connection` always use the primary, while `read_transaction`
+/// runs a read-only transaction on the next healthy replica, in round-robin order.
+/// A replica that fails to start a transaction is unhealthy, and it is skipped until
+/// the unhealthy timeout expires; if no replica is healthy, the primary is used.
+///
+/// The replicas could lag behind the primary: with a "read your writes" window,
+/// the read-only transactions started within the window after a write on the primary
+/// use the primary.
+///
+/// The connections are the ones of the inner pools, so every `C3p0Json` works with them.
+#[derive(Clone)]
+pub struct ReplicatedC3p0Pool<P: C3p0Pool> {
+    primary: P,
+    replicas: Arc<Vec<Replica<P>>>,
+    next_replica: Arc<AtomicUsize>,
+    read_your_writes: Option<Duration>,
+    last_write: Arc<Mutex<Option<Instant>>>,
+    unhealthy_timeout: Duration,
+}
+
+struct Replica<P> {
+    pool: P,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl<P> Replica<P> {
+    fn is_healthy(&self) -> bool {
+        match *lock(&self.unhealthy_until) {
+            Some(unhealthy_until) => Instant::now() >= unhealthy_until,
+            None => true,
+        }
+    }
+
+    fn set_unhealthy(&self, timeout: Duration) {
+        *lock(&self.unhealthy_until) = Some(Instant::now() + timeout);
+    }
+}
+
+impl<P: C3p0Pool> ReplicatedC3p0Pool<P> {
+    pub fn new(primary: P, replicas: Vec<P>) -> Self {
+        ReplicatedC3p0Pool {
+            primary,
+            replicas: Arc::new(
+                replicas
+                    .into_iter()
+                    .map(|pool| Replica {
+                        pool,
+                        unhealthy_until: Mutex::new(None),
+                    })
+                    .collect(),
+            ),
+            next_replica: Default::default(),
+            read_your_writes: None,
+            last_write: Default::default(),
+            unhealthy_timeout: DEFAULT_UNHEALTHY_TIMEOUT,
+        }
+    }
+
+    /// Sets how long after a write on the primary the read-only transactions use the primary.
+    /// The writes are the committed transactions that are not read-only,
+    /// and the functions run by `connection`.
+    pub fn with_read_your_writes(mut self, window: Duration) -> Self {
+        self.read_your_writes = Some(window);
+        self
+    }
+
+    /// Sets how long a replica that failed to start a transaction is skipped; the default is 5 seconds
+    pub fn with_unhealthy_timeout(mut self, unhealthy_timeout: Duration) -> Self {
+        self.unhealthy_timeout = unhealthy_timeout;
+        self
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Runs `tx` in a read-only transaction on a replica with the default options of the database.
+    /// The transaction is committed if `tx` succeeds, otherwise it is rolled back.
+    pub async fn read_transaction<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(P::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        tx: F,
+    ) -> Result<T, E> {
+        self.read_transaction_with_options(&TxOptions::default(), tx)
+            .await
+    }
+
+    /// Like `read_transaction`, but the transaction is started with the given options;
+    /// it is always read-only.
+    pub async fn read_transaction_with_options<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(P::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        options: &TxOptions,
+        tx: F,
+    ) -> Result<T, E> {
+        let options = options.clone().with_read_only(true);
+        if self.is_in_read_your_writes_window() {
+            return self.primary.transaction_with_options(&options, tx).await;
+        }
+
+        // `tx` stays in the slot if a replica fails before starting the transaction,
+        // so that it can run on the next one
+        let slot = Mutex::new(Some(tx));
+        let count = self.replicas.len();
+        let first = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        for index in 0..count {
+            let replica = &self.replicas[(first + index) % count];
+            if !replica.is_healthy() {
+                continue;
+            }
+            let result = replica
+                .pool
+                .transaction_with_options(&options, |conn| take_tx(&slot)(conn))
+                .await;
+            if lock(&slot).is_none() {
+                return result;
+            }
+            log::warn!(
+                "C3p0 - The replica cannot start a transaction, it is skipped for {:?}",
+                self.unhealthy_timeout
+            );
+            replica.set_unhealthy(self.unhealthy_timeout);
+        }
+
+        self.primary
+            .transaction_with_options(&options, take_tx(&slot))
+            .await
+    }
+
+    fn is_in_read_your_writes_window(&self) -> bool {
+        match (self.read_your_writes, *lock(&self.last_write)) {
+            (Some(window), Some(last_write)) => last_write.elapsed() < window,
+            _ => false,
+        }
+    }
+
+    fn record_write(&self) {
+        if self.read_your_writes.is_some() {
+            *lock(&self.last_write) = Some(Instant::now());
+        }
+    }
+}
+
+#[async_trait]
+impl<P: C3p0Pool> C3p0Pool for ReplicatedC3p0Pool<P> {
+    type Conn = P::Conn;
+
+    /// Returns the sum of the status of the primary and of the replicas
+    fn status(&self) -> PoolStatus {
+        self.replicas
+            .iter()
+            .map(|replica| replica.pool.status())
+            .fold(self.primary.status(), |total, status| PoolStatus {
+                size: total.size + status.size,
+                idle: total.idle + status.idle,
+                in_use: total.in_use + status.in_use,
+                waiters: total.waiters + status.waiters,
+            })
+    }
+
+    /// Pings the primary; the unavailable replicas do not prevent the reads,
+    /// that fall back to the primary
+    async fn ping(&self) -> Result<(), C3p0Error> {
+        self.primary.ping().await
+    }
+
+    async fn close(&self) {
+        for replica in self.replicas.iter() {
+            replica.pool.close().await;
+        }
+        self.primary.close().await;
+    }
+
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let result = self.primary.connection(f).await;
+        // The statements are committed even if `f` fails
+        self.record_write();
+        result
+    }
+
+    async fn transaction_with_options<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        options: &TxOptions,
+        tx: F,
+    ) -> Result<T, E> {
+        let result = self.primary.transaction_with_options(options, tx).await;
+        if result.is_ok() && !options.read_only {
+            self.record_write();
+        }
+        result
+    }
+}
+
+fn take_tx<F>(slot: &Mutex<Option<F>>) -> F {
+    lock(slot)
+        .take()
+        .expect("The transaction function should be called once")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    /// A pool whose connection is its name
+    #[derive(Clone)]
+    struct NamedPool {
+        name: &'static str,
+        available: Arc<AtomicBool>,
+    }
+
+    impl NamedPool {
+        fn new(name: &'static str) -> Self {
+            NamedPool {
+                name,
+                available: Arc::new(AtomicBool::new(true)),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl C3p0Pool for NamedPool {
+        type Conn = &'static str;
+
+        fn status(&self) -> PoolStatus {
+            PoolStatus {
+                size: 1,
+                idle: 1,
+                ..Default::default()
+            }
+        }
+
+        async fn ping(&self) -> Result<(), C3p0Error> {
+            Ok(())
+        }
+
+        async fn close(&self) {}
+
+        async fn connection<
+            T: Send,
+            E: Send + From<C3p0Error>,
+            F: Send + FnOnce(Self::Conn) -> Fut,
+            Fut: Send + Future<Output = Result<T, E>>,
+        >(
+            &self,
+            f: F,
+        ) -> Result<T, E> {
+            self.transaction(f).await
+        }
+
+        async fn transaction_with_options<
+            T: Send,
+            E: Send + From<C3p0Error>,
+            F: Send + FnOnce(Self::Conn) -> Fut,
+            Fut: Send + Future<Output = Result<T, E>>,
+        >(
+            &self,
+            _options: &TxOptions,
+            tx: F,
+        ) -> Result<T, E> {
+            if self.available.load(Ordering::SeqCst) {
+                (tx)(self.name).await
+            } else {
+                Err(C3p0Error::InternalError {
+                    cause: "unavailable".to_owned(),
+                }
+                .into())
+            }
+        }
+    }
+
+    async fn read(pool: &ReplicatedC3p0Pool<NamedPool>) -> &'static str {
+        pool.read_transaction(|conn| async move { Ok::<_, C3p0Error>(conn) })
+            .await
+            .unwrap()
+    }
+
+    async fn write(pool: &ReplicatedC3p0Pool<NamedPool>) -> &'static str {
+        pool.transaction(|conn| async move { Ok::<_, C3p0Error>(conn) })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_balance_the_reads_across_the_replicas() {
+        let pool = ReplicatedC3p0Pool::new(
+            NamedPool::new("primary"),
+            vec![NamedPool::new("first"), NamedPool::new("second")],
+        );
+
+        assert_eq!("first", read(&pool).await);
+        assert_eq!("second", read(&pool).await);
+        assert_eq!("first", read(&pool).await);
+        assert_eq!("primary", write(&pool).await);
+        assert_eq!(3, pool.status().size);
+    }
+
+    #[tokio::test]
+    async fn should_skip_the_unhealthy_replicas() {
+        let unavailable = NamedPool::new("unavailable");
+        unavailable.available.store(false, Ordering::SeqCst);
+        let pool = ReplicatedC3p0Pool::new(
+            NamedPool::new("primary"),
+            vec![unavailable.clone(), NamedPool::new("available")],
+        )
+        .with_unhealthy_timeout(Duration::from_millis(50));
+
+        assert_eq!("available", read(&pool).await);
+        assert_eq!("available", read(&pool).await);
+
+        unavailable.available.store(true, Ordering::SeqCst);
+        assert_eq!("available", read(&pool).await);
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+        let reads = [read(&pool).await, read(&pool).await];
+        assert!(reads.contains(&"unavailable"));
+    }
+
+    #[tokio::test]
+    async fn should_fall_back_to_the_primary() {
+        let unavailable = NamedPool::new("unavailable");
+        unavailable.available.store(false, Ordering::SeqCst);
+        let pool = ReplicatedC3p0Pool::new(NamedPool::new("primary"), vec![unavailable]);
+
+        assert_eq!("primary", read(&pool).await);
+
+        let pool = ReplicatedC3p0Pool::new(NamedPool::new("primary"), vec![]);
+        assert_eq!("primary", read(&pool).await);
+    }
+
+    #[tokio::test]
+    async fn should_read_from_the_primary_after_a_write() {
+        let pool =
+            ReplicatedC3p0Pool::new(NamedPool::new("primary"), vec![NamedPool::new("replica")])
+                .with_read_your_writes(Duration::from_millis(50));
+
+        assert_eq!("replica", read(&pool).await);
+        write(&pool).await;
+        assert_eq!("primary", read(&pool).await);
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+        assert_eq!("replica", read(&pool).await);
+    }
+}
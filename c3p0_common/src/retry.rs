@@ -0,0 +1,163 @@
+use crate::error::C3p0Error;
+use rand::Rng;
+use std::time::Duration;
+
+/// The codes of the errors caused by concurrent transactions, that succeed if retried:
+/// the Postgres SQLSTATEs of serialization failures (40001) and deadlocks (40P01),
+/// and the MySQL error numbers of deadlocks (1213) and lock wait timeouts (1205)
+const RETRYABLE_CODES: &[&str] = &["40001", "40P01", "1213", "1205"];
+
+/// The errors that tell whether the transaction that returned them can be retried
+pub trait RetryableError {
+    fn is_retryable(&self) -> bool;
+}
+
+impl RetryableError for C3p0Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            C3p0Error::DbError {
+                code: Some(code), ..
+            }
+            | C3p0Error::TimeoutError {
+                code: Some(code), ..
+            } => RETRYABLE_CODES.contains(&code.as_str()),
+            _ => false,
+        }
+    }
+}
+
+/// How `C3p0Pool::transaction_with_retry` retries the transactions that fail with a retryable error.
+/// The delay before a retry starts from `initial_backoff` and is multiplied by `multiplier`
+/// after every attempt, up to `max_backoff`; with jitter, a random delay between half and
+/// the whole computed one is used, so that the conflicting transactions do not retry together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of executions of the transaction, including the first one
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Returns whether the transaction can be executed again after `attempt` failed executions
+    pub fn can_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+
+    /// Returns the delay before the next execution after `attempt` failed executions
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()));
+        if self.jitter {
+            backoff.mul_f64(rand::thread_rng().gen_range(0.5, 1.0))
+        } else {
+            backoff
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn db_error(code: Option<&str>) -> C3p0Error {
+        C3p0Error::DbError {
+            db: "test",
+            cause: "".to_owned(),
+            code: code.map(|code| code.to_owned()),
+        }
+    }
+
+    #[test]
+    fn should_classify_the_retryable_errors() {
+        assert!(db_error(Some("40001")).is_retryable());
+        assert!(db_error(Some("40P01")).is_retryable());
+        assert!(db_error(Some("1213")).is_retryable());
+        assert!(db_error(Some("1205")).is_retryable());
+        assert!(C3p0Error::from_db("test", "".to_owned(), Some("1205".to_owned())).is_retryable());
+        assert!(!db_error(Some("23505")).is_retryable());
+        assert!(!db_error(None).is_retryable());
+        assert!(!C3p0Error::ResultNotFoundError.is_retryable());
+    }
+
+    #[test]
+    fn should_increase_the_backoff_up_to_the_max() {
+        let policy = RetryPolicy::new()
+            .with_initial_backoff(Duration::from_millis(10))
+            .with_max_backoff(Duration::from_millis(50))
+            .with_multiplier(2.0)
+            .with_jitter(false);
+
+        assert_eq!(Duration::from_millis(10), policy.backoff(1));
+        assert_eq!(Duration::from_millis(20), policy.backoff(2));
+        assert_eq!(Duration::from_millis(40), policy.backoff(3));
+        assert_eq!(Duration::from_millis(50), policy.backoff(4));
+        assert_eq!(Duration::from_millis(50), policy.backoff(100));
+    }
+
+    #[test]
+    fn should_add_jitter_to_the_backoff() {
+        let policy = RetryPolicy::new()
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_jitter(true);
+
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff >= Duration::from_millis(50));
+            assert!(backoff <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn should_limit_the_attempts() {
+        let policy = RetryPolicy::new().with_max_attempts(3);
+        assert!(policy.can_retry(1));
+        assert!(policy.can_retry(2));
+        assert!(!policy.can_retry(3));
+    }
+}
@@ -0,0 +1,233 @@
+//! The tracing spans of the transactions, of the operations on the tables and of the
+//! SQL statements. They are created only with the `tracing` feature; without it the
+//! futures run as they are.
+//!
+//! The spans record the database, the operation or the SQL statement, the table,
+//! the number of rows, the duration in milliseconds and the error.
+//! The SQL statements are recorded as templates, the values of their parameters are not.
+
+use crate::error::C3p0Error;
+use crate::json::model::Model;
+use crate::pool::TxOptions;
+use std::future::Future;
+
+/// The number of rows of a result, recorded in the span
+pub trait TracedRows {
+    fn rows(&self) -> Option<u64>;
+}
+
+impl TracedRows for () {
+    fn rows(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl TracedRows for bool {
+    fn rows(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The number of affected rows
+impl TracedRows for u64 {
+    fn rows(&self) -> Option<u64> {
+        Some(*self)
+    }
+}
+
+impl<T> TracedRows for Vec<T> {
+    fn rows(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+}
+
+impl<T> TracedRows for Option<T> {
+    fn rows(&self) -> Option<u64> {
+        Some(if self.is_some() { 1 } else { 0 })
+    }
+}
+
+impl<Data: Clone + serde::ser::Serialize + Send> TracedRows for Model<Data> {
+    fn rows(&self) -> Option<u64> {
+        Some(1)
+    }
+}
+
+/// A span that is not entered yet; `run` runs a future in it
+pub struct TraceSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl TraceSpan {
+    /// Returns the span of a SQL statement
+    pub fn sql(db: &'static str, sql: &str) -> Self {
+        let operation = sql
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_uppercase();
+        TraceSpan {
+            span: tracing::info_span!(
+                "c3p0.sql",
+                db.system = db,
+                db.operation = operation.as_str(),
+                db.statement = sql,
+                db.table = tracing::field::Empty,
+                rows = tracing::field::Empty,
+                elapsed_ms = tracing::field::Empty,
+                error = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Returns the span of an operation on a table, like `save` or `fetch_all`
+    pub fn operation(db: &'static str, operation: &'static str, table: &str) -> Self {
+        TraceSpan {
+            span: tracing::info_span!(
+                "c3p0.operation",
+                db.system = db,
+                c3p0.operation = operation,
+                db.table = table,
+                rows = tracing::field::Empty,
+                elapsed_ms = tracing::field::Empty,
+                error = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Returns the span of a transaction
+    pub fn transaction(db: &'static str, options: &TxOptions) -> Self {
+        TraceSpan {
+            span: tracing::info_span!(
+                "c3p0.transaction",
+                db.system = db,
+                isolation = tracing::field::debug(&options.isolation),
+                read_only = options.read_only,
+                committed = tracing::field::Empty,
+                elapsed_ms = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Records the table of a SQL statement
+    pub fn with_table(self, table: &str) -> Self {
+        self.span.record("db.table", table);
+        self
+    }
+
+    /// Runs `fut` in the span, recording its duration, its rows and its error
+    pub async fn run<T: TracedRows, Fut: Future<Output = Result<T, C3p0Error>>>(
+        self,
+        fut: Fut,
+    ) -> Result<T, C3p0Error> {
+        use tracing::Instrument;
+
+        let start = std::time::Instant::now();
+        let result = fut.instrument(self.span.clone()).await;
+        self.span
+            .record("elapsed_ms", start.elapsed().as_millis() as u64);
+        match &result {
+            Ok(value) => {
+                if let Some(rows) = value.rows() {
+                    self.span.record("rows", rows);
+                }
+            }
+            Err(err) => {
+                self.span.record("error", tracing::field::display(err));
+            }
+        }
+        result
+    }
+
+    /// Runs the transaction `fut` in the span, recording its duration and whether it was committed
+    pub async fn run_transaction<T, E, Fut: Future<Output = Result<T, E>>>(
+        self,
+        fut: Fut,
+    ) -> Result<T, E> {
+        use tracing::Instrument;
+
+        let start = std::time::Instant::now();
+        let result = fut.instrument(self.span.clone()).await;
+        self.span
+            .record("elapsed_ms", start.elapsed().as_millis() as u64);
+        self.span.record("committed", result.is_ok());
+        result
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl TraceSpan {
+    /// Returns the span of a SQL statement
+    pub fn sql(_db: &'static str, _sql: &str) -> Self {
+        TraceSpan {}
+    }
+
+    /// Returns the span of an operation on a table, like `save` or `fetch_all`
+    pub fn operation(_db: &'static str, _operation: &'static str, _table: &str) -> Self {
+        TraceSpan {}
+    }
+
+    /// Returns the span of a transaction
+    pub fn transaction(_db: &'static str, _options: &TxOptions) -> Self {
+        TraceSpan {}
+    }
+
+    /// Records the table of a SQL statement
+    pub fn with_table(self, _table: &str) -> Self {
+        self
+    }
+
+    /// Runs `fut` in the span, recording its duration, its rows and its error
+    pub async fn run<T: TracedRows, Fut: Future<Output = Result<T, C3p0Error>>>(
+        self,
+        fut: Fut,
+    ) -> Result<T, C3p0Error> {
+        fut.await
+    }
+
+    /// Runs the transaction `fut` in the span, recording its duration and whether it was committed
+    pub async fn run_transaction<T, E, Fut: Future<Output = Result<T, E>>>(
+        self,
+        fut: Fut,
+    ) -> Result<T, E> {
+        fut.await
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::pool::IsolationLevel;
+
+    #[test]
+    fn should_count_the_rows() {
+        assert_eq!(None, ().rows());
+        assert_eq!(Some(3), 3u64.rows());
+        assert_eq!(Some(2), vec!["a", "b"].rows());
+        assert_eq!(Some(0), None::<u32>.rows());
+        assert_eq!(Some(1), Some(1).rows());
+    }
+
+    #[tokio::test]
+    async fn should_return_the_result_of_the_future() {
+        let result = TraceSpan::sql("test", "SELECT 1")
+            .with_table("TEST_TABLE")
+            .run(async { Ok(vec![1, 2]) })
+            .await;
+        assert_eq!(vec![1, 2], result.unwrap());
+
+        let result: Result<u64, C3p0Error> = TraceSpan::operation("test", "save", "TEST_TABLE")
+            .run(async { Err(C3p0Error::ResultNotFoundError) })
+            .await;
+        assert!(result.is_err());
+
+        let options = TxOptions::default().with_isolation(IsolationLevel::Serializable);
+        let result = TraceSpan::transaction("test", &options)
+            .run_transaction(async { Ok::<_, ()>("committed") })
+            .await;
+        assert_eq!(Ok("committed"), result);
+    }
+}
@@ -1,46 +1,142 @@
 use crate::pool::{InMemoryC3p0Pool, InMemoryConnection};
 use async_trait::async_trait;
+use c3p0_common::json::{
+    has_field_prefix, select_by_field, select_by_field_prefix, select_by_max_field, with_documents,
+};
+use c3p0_common::trace::TraceSpan;
 use c3p0_common::{
-    C3p0Error, C3p0Json, C3p0JsonBuilder, DefaultJsonCodec, ForUpdate, IdType, Model, NewModel,
+    change_feed_table_name, BinaryCodec, BinaryColumn, C3p0Error, C3p0Index, C3p0Json,
+    C3p0JsonBuilder, ChangeOperation, DataColumn, DefaultJsonCodec, ForUpdate, IdType, JsonCodec,
+    JsonColumn, Model, NewModel, OrderBy,
 };
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Excluded, Unbounded};
 
 pub trait InMemoryC3p0JsonBuilder {
     fn build<DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync>(
         self,
-    ) -> InMemoryC3p0Json<DATA>;
+    ) -> InMemoryC3p0Json<DATA, DefaultJsonCodec>;
+    fn build_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: JsonCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> InMemoryC3p0Json<DATA, CODEC>;
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> InMemoryC3p0Binary<DATA, CODEC>;
 }
 
 impl InMemoryC3p0JsonBuilder for C3p0JsonBuilder<InMemoryC3p0Pool> {
     fn build<DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync>(
         self,
-    ) -> InMemoryC3p0Json<DATA> {
-        let qualified_table_name = match &self.schema_name {
-            Some(schema_name) => format!(r#"{}."{}""#, schema_name, self.table_name),
-            None => self.table_name.clone(),
-        };
+    ) -> InMemoryC3p0Json<DATA, DefaultJsonCodec> {
+        self.build_with_codec(DefaultJsonCodec {})
+    }
+
+    fn build_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: JsonCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> InMemoryC3p0Json<DATA, CODEC> {
         InMemoryC3p0Json {
-            qualified_table_name,
+            qualified_table_name: qualified_table_name(&self),
+            change_feed: change_feed(&self),
             phantom_data: std::marker::PhantomData,
-            codec: Default::default(),
+            codec,
+            column: JsonColumn {
+                compression: self.compression,
+                schema: self.schema,
+            },
         }
     }
+
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> InMemoryC3p0Binary<DATA, CODEC> {
+        InMemoryC3p0Json {
+            qualified_table_name: qualified_table_name(&self),
+            change_feed: change_feed(&self),
+            phantom_data: std::marker::PhantomData,
+            codec,
+            column: BinaryColumn {},
+        }
+    }
+}
+
+/// Returns the table name used in the change events, if the change feed is enabled
+pub(crate) fn change_feed<C3P0>(builder: &C3p0JsonBuilder<C3P0>) -> Option<String> {
+    if builder.change_feed {
+        Some(change_feed_table_name(builder))
+    } else {
+        None
+    }
 }
 
+/// Returns the id of the next entry saved in the table
+pub(crate) fn next_id(table: &BTreeMap<IdType, Model<Value>>) -> IdType {
+    table.keys().next_back().map_or(0, |id| id + 1)
+}
+
+/// Inserts the entry with its own id; it fails if the id is already used
+pub(crate) fn insert_with_id(
+    table: &mut BTreeMap<IdType, Model<Value>>,
+    qualified_table_name: &str,
+    model: Model<Value>,
+) -> Result<(), C3p0Error> {
+    if table.contains_key(&model.id) {
+        return Err(C3p0Error::InternalError {
+            cause: format!(
+                "Cannot save data in table [{}] with id [{}]: the id is already used",
+                qualified_table_name, model.id
+            ),
+        });
+    }
+    table.insert(model.id, model);
+    Ok(())
+}
+
+fn qualified_table_name<C3P0>(builder: &C3p0JsonBuilder<C3P0>) -> String {
+    match &builder.schema_name {
+        Some(schema_name) => format!(r#"{}."{}""#, schema_name, builder.table_name),
+        None => builder.table_name.clone(),
+    }
+}
+
+/// A C3p0Json implementation that keeps the documents encoded by the data column `COLUMN`.
+/// The values of the column are stored in the in-memory db as JSON: the binary documents
+/// are stored as arrays of bytes.
 #[derive(Clone)]
-pub struct InMemoryC3p0Json<DATA>
+pub struct InMemoryC3p0Json<DATA, CODEC = DefaultJsonCodec, COLUMN = JsonColumn>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
 {
     qualified_table_name: String,
+    change_feed: Option<String>,
     phantom_data: std::marker::PhantomData<DATA>,
-    codec: DefaultJsonCodec,
+    codec: CODEC,
+    column: COLUMN,
 }
 
-impl<DATA> InMemoryC3p0Json<DATA>
+/// A C3p0Json implementation that keeps the data encoded by a BinaryCodec
+pub type InMemoryC3p0Binary<DATA, CODEC> = InMemoryC3p0Json<DATA, CODEC, BinaryColumn>;
+
+impl<DATA, CODEC, COLUMN> InMemoryC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
 {
     fn get_table<'a>(
         &self,
@@ -63,7 +159,7 @@ where
         Ok(Model {
             id: model.id,
             version: model.version,
-            data: serde_json::to_value(&model.data)?,
+            data: serde_json::to_value(self.column.encode(&self.codec, &model.data)?)?,
         })
     }
 
@@ -71,27 +167,55 @@ where
         Ok(Model {
             id: model.id,
             version: model.version,
-            data: serde_json::from_value(model.data.clone())?,
+            data: self
+                .column
+                .decode(&self.codec, serde_json::from_value(model.data.clone())?)?,
         })
     }
 }
 
 #[async_trait]
-impl<DATA> C3p0Json<DATA, DefaultJsonCodec> for InMemoryC3p0Json<DATA>
+impl<DATA, CODEC, COLUMN> C3p0Json<DATA, CODEC> for InMemoryC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Clone + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
 {
     type Conn = InMemoryConnection;
 
-    fn codec(&self) -> &DefaultJsonCodec {
+    fn codec(&self) -> &CODEC {
         &self.codec
     }
 
+    fn qualified_table_name(&self) -> &str {
+        &self.qualified_table_name
+    }
+
     async fn create_table_if_not_exists(
         &self,
         conn: &mut InMemoryConnection,
     ) -> Result<(), C3p0Error> {
-        self.get_or_create_table(&self.qualified_table_name, conn);
+        TraceSpan::operation(
+            "in_memory",
+            "create_table_if_not_exists",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            self.get_or_create_table(&self.qualified_table_name, conn);
+            Ok(())
+        })
+        .await
+    }
+
+    /// The in-memory tables have no indexes, so it does nothing: in particular,
+    /// the unique indexes are not enforced
+    async fn create_index_if_not_exists(
+        &self,
+        _conn: &mut InMemoryConnection,
+        _index: &C3p0Index,
+    ) -> Result<(), C3p0Error> {
+        self.column
+            .fields_queryable(&self.qualified_table_name, "create_index_if_not_exists")?;
         Ok(())
     }
 
@@ -100,16 +224,28 @@ where
         conn: &mut InMemoryConnection,
         _cascade: bool,
     ) -> Result<(), C3p0Error> {
-        conn.remove(&self.qualified_table_name);
-        Ok(())
+        TraceSpan::operation(
+            "in_memory",
+            "drop_table_if_exists",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            conn.remove(&self.qualified_table_name);
+            Ok(())
+        })
+        .await
     }
 
     async fn count_all(&self, conn: &mut InMemoryConnection) -> Result<u64, C3p0Error> {
-        if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
-            Ok(table.len() as u64)
-        } else {
-            Ok(0)
-        }
+        TraceSpan::operation("in_memory", "count_all", &self.qualified_table_name)
+            .run(async move {
+                if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                    Ok(table.len() as u64)
+                } else {
+                    Ok(0)
+                }
+            })
+            .await
     }
 
     async fn exists_by_id<'a, ID: Into<&'a IdType> + Send>(
@@ -117,25 +253,33 @@ where
         conn: &mut InMemoryConnection,
         id: ID,
     ) -> Result<bool, C3p0Error> {
-        if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
-            Ok(table.contains_key(id.into()))
-        } else {
-            Ok(false)
-        }
+        TraceSpan::operation("in_memory", "exists_by_id", &self.qualified_table_name)
+            .run(async move {
+                if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                    Ok(table.contains_key(id.into()))
+                } else {
+                    Ok(false)
+                }
+            })
+            .await
     }
 
     async fn fetch_all(
         &self,
         conn: &mut InMemoryConnection,
     ) -> Result<Vec<Model<DATA>>, C3p0Error> {
-        if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
-            table
-                .values()
-                .map(|value| self.to_data_model(value))
-                .collect::<Result<Vec<_>, _>>()
-        } else {
-            Ok(vec![])
-        }
+        TraceSpan::operation("in_memory", "fetch_all", &self.qualified_table_name)
+            .run(async move {
+                if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                    table
+                        .values()
+                        .map(|value| self.to_data_model(value))
+                        .collect::<Result<Vec<_>, _>>()
+                } else {
+                    Ok(vec![])
+                }
+            })
+            .await
     }
 
     async fn fetch_all_for_update(
@@ -146,17 +290,190 @@ where
         self.fetch_all(conn).await
     }
 
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut InMemoryConnection,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_all_after_id",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                table
+                    .range((Excluded(after_id), Unbounded))
+                    .take(limit as usize)
+                    .map(|(_, value)| self.to_data_model(value))
+                    .collect::<Result<Vec<_>, _>>()
+            } else {
+                Ok(vec![])
+            }
+        })
+        .await
+    }
+
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut InMemoryConnection,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error> {
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_all_values_after_id",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                table
+                    .range((Excluded(after_id), Unbounded))
+                    .take(limit as usize)
+                    .map(|(_, value)| {
+                        Ok(Model {
+                            id: value.id,
+                            version: value.version,
+                            data: self
+                                .column
+                                .to_value(serde_json::from_value(value.data.clone())?)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            } else {
+                Ok(vec![])
+            }
+        })
+        .await
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.column.is_current(&self.codec, value)
+    }
+
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut InMemoryConnection,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_all_by_field",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if !self
+                .column
+                .fields_queryable(&self.qualified_table_name, "fetch_all_by_field")?
+            {
+                return select_by_field(self.fetch_all(conn).await?, field_name, value);
+            }
+            let mut models = vec![];
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                for model in table.values() {
+                    if model.data.get(field_name) == Some(value) {
+                        models.push(self.to_data_model(model)?);
+                    }
+                }
+            }
+            Ok(models)
+        })
+        .await
+    }
+
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut InMemoryConnection,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_all_by_field_prefix",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if !self
+                .column
+                .fields_queryable(&self.qualified_table_name, "fetch_all_by_field_prefix")?
+            {
+                return select_by_field_prefix(self.fetch_all(conn).await?, field_name, prefix);
+            }
+            let mut models = vec![];
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                for model in table.values() {
+                    if has_field_prefix(&model.data, field_name, prefix) {
+                        models.push(self.to_data_model(model)?);
+                    }
+                }
+            }
+            Ok(models)
+        })
+        .await
+    }
+
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut InMemoryConnection,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        _for_update: &ForUpdate,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_all_by_max_field_for_update",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if !self.column.fields_queryable(
+                &self.qualified_table_name,
+                "fetch_all_by_max_field_for_update",
+            )? {
+                return Ok(select_by_max_field(
+                    with_documents(self.fetch_all(conn).await?)?,
+                    field_name,
+                    max_value,
+                    order_by_field,
+                    limit,
+                ));
+            }
+            let mut entries = vec![];
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                for model in table.values() {
+                    entries.push((model.clone(), model.data.clone()));
+                }
+            }
+            select_by_max_field(entries, field_name, max_value, order_by_field, limit)
+                .iter()
+                .map(|model| self.to_data_model(model))
+                .collect()
+        })
+        .await
+    }
+
     async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
         &'a self,
         conn: &mut InMemoryConnection,
         id: ID,
     ) -> Result<Option<Model<DATA>>, C3p0Error> {
-        if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
-            if let Some(value) = table.get(id.into()) {
-                return Ok(Some(self.to_data_model(value)?));
+        TraceSpan::operation(
+            "in_memory",
+            "fetch_one_optional_by_id",
+            &self.qualified_table_name,
+        )
+        .run(async move {
+            if let Some(table) = self.get_table(&self.qualified_table_name, conn) {
+                if let Some(value) = table.get(id.into()) {
+                    return Ok(Some(self.to_data_model(value)?));
+                }
             }
-        }
-        Ok(None)
+            Ok(None)
+        })
+        .await
     }
 
     async fn fetch_one_optional_by_id_for_update<'a, ID: Into<&'a IdType> + Send>(
@@ -194,32 +511,52 @@ where
         conn: &mut InMemoryConnection,
         obj: Model<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let table = self.get_or_create_table(&self.qualified_table_name, conn);
-
-        let mut good_version = false;
-
-        if let Some(value) = table.get(&obj.id) {
-            good_version = value.version == obj.version;
-        };
-
-        if good_version {
-            table.remove(&obj.id);
-            return Ok(obj);
-        }
-
-        Err(C3p0Error::OptimisticLockError {
-            message: format!(
-                "Cannot delete data in table [{}] with id [{}], version [{}]: data was changed!",
-                &self.qualified_table_name, &obj.id, &obj.version
-            ),
-        })
+        TraceSpan::operation("in_memory", "delete", &self.qualified_table_name)
+            .run(async move {
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+
+                let mut good_version = false;
+
+                if let Some(value) = table.get(&obj.id) {
+                    good_version = value.version == obj.version;
+                };
+
+                if good_version {
+                    table.remove(&obj.id);
+                    conn.push_change(
+                        &self.change_feed,
+                        ChangeOperation::Delete,
+                        obj.id,
+                        obj.version,
+                    );
+                    return Ok(obj);
+                }
+
+                Err(C3p0Error::OptimisticLockError {
+                    message: format!(
+                        "Cannot delete data in table [{}] with id [{}], version [{}]: data was changed!",
+                        &self.qualified_table_name, &obj.id, &obj.version
+                    ),
+                })
+            })
+            .await
     }
 
     async fn delete_all(&self, conn: &mut InMemoryConnection) -> Result<u64, C3p0Error> {
-        let table = self.get_or_create_table(&self.qualified_table_name, conn);
-        let len = table.len();
-        table.clear();
-        Ok(len as u64)
+        TraceSpan::operation("in_memory", "delete_all", &self.qualified_table_name)
+            .run(async move {
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+                let deleted = table
+                    .values()
+                    .map(|model| (model.id, model.version))
+                    .collect::<Vec<_>>();
+                table.clear();
+                for (id, version) in &deleted {
+                    conn.push_change(&self.change_feed, ChangeOperation::Delete, *id, *version);
+                }
+                Ok(deleted.len() as u64)
+            })
+            .await
     }
 
     async fn delete_by_id<'a, ID: Into<&'a IdType> + Send>(
@@ -227,11 +564,23 @@ where
         conn: &mut InMemoryConnection,
         id: ID,
     ) -> Result<u64, C3p0Error> {
-        let table = self.get_or_create_table(&self.qualified_table_name, conn);
-        match table.remove(id.into()) {
-            Some(_) => Ok(1),
-            None => Ok(0),
-        }
+        TraceSpan::operation("in_memory", "delete_by_id", &self.qualified_table_name)
+            .run(async move {
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+                match table.remove(id.into()) {
+                    Some(model) => {
+                        conn.push_change(
+                            &self.change_feed,
+                            ChangeOperation::Delete,
+                            model.id,
+                            model.version,
+                        );
+                        Ok(1)
+                    }
+                    None => Ok(0),
+                }
+            })
+            .await
     }
 
     async fn save(
@@ -239,46 +588,87 @@ where
         conn: &mut InMemoryConnection,
         obj: NewModel<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let table = self.get_or_create_table(&self.qualified_table_name, conn);
-        let id = table.len() as IdType;
-        let model = Model {
-            id,
-            version: obj.version,
-            data: obj.data,
-        };
-        table.insert(id, self.to_value_model(&model)?);
-        Ok(model)
+        TraceSpan::operation("in_memory", "save", &self.qualified_table_name)
+            .run(async move {
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+                let id = next_id(table);
+                let model = Model {
+                    id,
+                    version: obj.version,
+                    data: obj.data,
+                };
+                table.insert(id, self.to_value_model(&model)?);
+                conn.push_change(
+                    &self.change_feed,
+                    ChangeOperation::Insert,
+                    model.id,
+                    model.version,
+                );
+                Ok(model)
+            })
+            .await
     }
 
-    async fn update(
+    async fn save_with_id(
         &self,
         conn: &mut InMemoryConnection,
         obj: Model<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let table = self.get_or_create_table(&self.qualified_table_name, conn);
-
-        let mut good_version = false;
-
-        if let Some(value) = table.get(&obj.id) {
-            good_version = value.version == obj.version;
-        };
-
-        if good_version {
-            let updated_model = Model {
-                id: obj.id,
-                version: obj.version + 1,
-                data: obj.data,
-            };
-            table.insert(updated_model.id, self.to_value_model(&updated_model)?);
-            return Ok(updated_model);
-        }
+        TraceSpan::operation("in_memory", "save_with_id", &self.qualified_table_name)
+            .run(async move {
+                let value_model = self.to_value_model(&obj)?;
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+                insert_with_id(table, &self.qualified_table_name, value_model)?;
+                conn.push_change(
+                    &self.change_feed,
+                    ChangeOperation::Insert,
+                    obj.id,
+                    obj.version,
+                );
+                Ok(obj)
+            })
+            .await
+    }
 
-        Err(C3p0Error::OptimisticLockError {
-            message: format!(
-                "Cannot update data in table [{}] with id [{}], version [{}]: data was changed!",
-                &self.qualified_table_name, &obj.id, &obj.version
-            ),
-        })
+    async fn update(
+        &self,
+        conn: &mut InMemoryConnection,
+        obj: Model<DATA>,
+    ) -> Result<Model<DATA>, C3p0Error> {
+        TraceSpan::operation("in_memory", "update", &self.qualified_table_name)
+            .run(async move {
+                let table = self.get_or_create_table(&self.qualified_table_name, conn);
+
+                let mut good_version = false;
+
+                if let Some(value) = table.get(&obj.id) {
+                    good_version = value.version == obj.version;
+                };
+
+                if good_version {
+                    let updated_model = Model {
+                        id: obj.id,
+                        version: obj.version + 1,
+                        data: obj.data,
+                    };
+                    table.insert(updated_model.id, self.to_value_model(&updated_model)?);
+                    conn.push_change(
+                        &self.change_feed,
+                        ChangeOperation::Update,
+                        updated_model.id,
+                        updated_model.version,
+                    );
+                    return Ok(updated_model);
+                }
+
+                Err(C3p0Error::OptimisticLockError {
+                    message: format!(
+                        "Cannot update data in table [{}] with id [{}], version [{}]: data was changed!",
+                        &self.qualified_table_name, &obj.id, &obj.version
+                    ),
+                })
+            })
+            .await
     }
 }
 
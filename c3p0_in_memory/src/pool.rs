@@ -1,71 +1,456 @@
 use async_trait::async_trait;
+use c3p0_common::lend::{lend, Lent};
+use c3p0_common::pool::Waiters;
+use c3p0_common::trace::TraceSpan;
 use c3p0_common::*;
 use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::stream::StreamExt;
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard};
+use tokio::time;
 
 type Db = HashMap<String, BTreeMap<IdType, Model<serde_json::Value>>>;
 
-#[derive(Clone, Default)]
+/// The async mutexes that emulate the advisory locks, by key
+type Locks = parking_lot::Mutex<HashMap<LockKey, Arc<Mutex<()>>>>;
+
+/// The number of change events kept for the subscribers that are lagging behind
+const CHANGE_FEED_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
 pub struct InMemoryC3p0Pool {
     db: Arc<Mutex<Db>>,
+    changes: broadcast::Sender<ChangeEvent>,
+    locks: Arc<Locks>,
+    closed: Arc<AtomicBool>,
+    waiters: Waiters,
+}
+
+impl Default for InMemoryC3p0Pool {
+    fn default() -> Self {
+        InMemoryC3p0Pool {
+            db: Default::default(),
+            changes: broadcast::channel(CHANGE_FEED_CAPACITY).0,
+            locks: Default::default(),
+            closed: Default::default(),
+            waiters: Default::default(),
+        }
+    }
 }
 
 impl InMemoryC3p0Pool {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Locks the data for a connection; the data is the only connection of the pool
+    async fn lock_db(&self) -> Result<OwnedMutexGuard<Db>, C3p0Error> {
+        self.check_open()?;
+        let guard = self.waiters.wait(self.db.clone().lock_owned()).await;
+        // The pool could be closed while waiting
+        self.check_open()?;
+        Ok(guard)
+    }
+
+    fn check_open(&self) -> Result<(), C3p0Error> {
+        if self.closed.load(Ordering::SeqCst) {
+            Err(c3p0_common::pool::pool_closed_error(
+                "in_memory",
+                "in_memory",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn publish(&self, changes: &parking_lot::Mutex<Vec<ChangeEvent>>) {
+        for change in changes.lock().drain(..) {
+            // an error only means that there are no subscribers
+            let _ = self.changes.send(change);
+        }
+    }
 }
 
 #[async_trait]
 impl C3p0Pool for InMemoryC3p0Pool {
     type Conn = InMemoryConnection;
 
-    async fn transaction<
+    /// The pool has a single connection, that is in use while a transaction
+    /// or a connection function runs
+    fn status(&self) -> PoolStatus {
+        let idle = if self.db.try_lock().is_ok() { 1 } else { 0 };
+        PoolStatus {
+            size: 1,
+            idle,
+            in_use: 1 - idle,
+            waiters: self.waiters.count(),
+        }
+    }
+
+    async fn ping(&self) -> Result<(), C3p0Error> {
+        self.check_open()
+    }
+
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // Waits for the running transaction
+        drop(self.db.lock().await);
+    }
+
+    /// The transactions are serialized, so every isolation level is satisfied;
+    /// a read-only transaction fails at commit if it modified the data.
+    async fn transaction_with_options<
         T: Send,
         E: Send + From<C3p0Error>,
         F: Send + FnOnce(Self::Conn) -> Fut,
         Fut: Send + Future<Output = Result<T, E>>,
     >(
         &self,
+        options: &TxOptions,
         tx: F,
     ) -> Result<T, E> {
-        let mut guard = self.db.lock().await;
-        // .map_err(|err| C3p0Error::InternalError {
-        //         cause: format!("{}", err),
-        //     })?;
-        let db = guard.deref();
-        let mut db_clone = db.clone();
-
-        // ToDo: To avoid this unsafe we need GAT
+        TraceSpan::transaction("in_memory", options)
+            .run_transaction(async move {
+                let mut guard = self.lock_db().await?;
+                let (db, lender) = lend(guard.clone(), drop);
+                let hooks = TransactionHooks::new();
+
+                let conn = InMemoryConnection {
+                    db: ConnectionDb::Tx(db),
+                    changes: Default::default(),
+                    locks: self.locks.clone(),
+                    held_locks: Default::default(),
+                    options: options.clone(),
+                    hooks: hooks.clone(),
+                };
+                let changes = conn.changes.clone();
+
+                let result = match c3p0_common::pool::with_client_timeout(options, (tx)(conn)).await
+                {
+                    Some(Ok(result)) => match lender.take_back() {
+                        Some(db) if options.read_only && db != *guard => {
+                            Err(C3p0Error::InternalError {
+                                cause: "Cannot commit the changes of a read-only transaction"
+                                    .to_owned(),
+                            }
+                            .into())
+                        }
+                        Some(db) => {
+                            *guard = db;
+                            self.publish(&changes);
+                            Ok(result)
+                        }
+                        None => Err(c3p0_common::pool::connection_not_released_error().into()),
+                    },
+                    Some(Err(err)) => Err(err),
+                    None => {
+                        Err(c3p0_common::pool::client_timeout_error("in_memory", options).into())
+                    }
+                };
+                drop(guard);
+
+                match result {
+                    Ok(_) => hooks.committed().await,
+                    Err(_) => hooks.rolled_back().await,
+                }
+                result
+            })
+            .await
+    }
+
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let guard = self.lock_db().await?;
+        let hooks = TransactionHooks::new();
+
+        // The changes are applied directly to the db, so they are kept even if `f` fails
         let conn = InMemoryConnection {
-            db: (unsafe { ::std::mem::transmute(&mut db_clone) }),
+            db: ConnectionDb::Conn(guard),
+            changes: Default::default(),
+            locks: self.locks.clone(),
+            held_locks: Default::default(),
+            options: Default::default(),
+            hooks: hooks.clone(),
         };
+        let changes = conn.changes.clone();
+
+        let result = (f)(conn).await;
+
+        self.publish(&changes);
 
-        let result = (tx)(conn).await?;
-        *guard = db_clone;
+        // Every change is already applied
+        hooks.committed().await;
+        result
+    }
+}
 
-        Ok(result)
+#[async_trait]
+impl C3p0ChangeFeed for InMemoryC3p0Pool {
+    async fn subscribe(&self, table_name: &str) -> Result<ChangeStream, C3p0Error> {
+        let table_name = table_name.to_owned();
+        Ok(Box::pin(self.changes.subscribe().filter_map(
+            move |change| match change {
+                Ok(change) if change.table_name == table_name => Some(Ok(change)),
+                Ok(_) => None,
+                Err(err) => Some(Err(C3p0Error::InternalError {
+                    cause: format!("Change feed of table [{}] failed: {}", table_name, err),
+                })),
+            },
+        )))
     }
 }
 
+/// The data of a connection
+enum ConnectionDb {
+    /// A copy of the data, that replaces it when the transaction is committed
+    Tx(Lent<Db>),
+    /// The data, locked until the connection is dropped
+    Conn(OwnedMutexGuard<Db>),
+}
+
 pub struct InMemoryConnection {
-    db: &'static mut Db,
+    db: ConnectionDb,
+    changes: Arc<parking_lot::Mutex<Vec<ChangeEvent>>>,
+    locks: Arc<Locks>,
+    /// The locks held until the end of the transaction, shared with the savepoints
+    held_locks: Arc<parking_lot::Mutex<HashMap<LockKey, OwnedMutexGuard<()>>>>,
+    options: TxOptions,
+    hooks: TransactionHooks,
+}
+
+impl C3p0Hooks for InMemoryConnection {
+    fn hooks(&self) -> &TransactionHooks {
+        &self.hooks
+    }
+}
+
+impl InMemoryConnection {
+    /// Returns the options of the transaction; they are the default ones outside of a transaction
+    pub fn options(&self) -> &TxOptions {
+        &self.options
+    }
+
+    fn require_transaction(&self, key: &LockKey) -> Result<(), C3p0Error> {
+        match self.db {
+            ConnectionDb::Tx(_) => Ok(()),
+            ConnectionDb::Conn(_) => Err(c3p0_common::lock::no_transaction_error(key)),
+        }
+    }
+
+    /// Queues a change of a table with a change feed;
+    /// the queued changes are published when the transaction is committed,
+    /// or when the function of a connection outside of a transaction returns.
+    pub(crate) fn push_change(
+        &self,
+        table_name: &Option<String>,
+        operation: ChangeOperation,
+        id: IdType,
+        version: VersionType,
+    ) {
+        if let Some(table_name) = table_name {
+            self.changes.lock().push(ChangeEvent {
+                table_name: table_name.clone(),
+                operation,
+                id,
+                version,
+            });
+        }
+    }
+}
+
+/// The savepoint works on a copy of the data, that replaces the data if `f` succeeds;
+/// the locks acquired in the savepoint are held until the end of the transaction.
+#[async_trait]
+impl C3p0Savepoint for InMemoryConnection {
+    async fn savepoint<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &mut self,
+        f: F,
+    ) -> Result<T, E> {
+        let snapshot = match &self.db {
+            ConnectionDb::Tx(db) => Db::clone(db),
+            ConnectionDb::Conn(_) => {
+                return Err(c3p0_common::pool::no_transaction_savepoint_error().into())
+            }
+        };
+        let (db, lender) = lend(snapshot, drop);
+        let queued_changes = self.changes.lock().len();
+        let mark = self.hooks.mark();
+
+        let savepoint_conn = InMemoryConnection {
+            db: ConnectionDb::Tx(db),
+            changes: self.changes.clone(),
+            locks: self.locks.clone(),
+            held_locks: self.held_locks.clone(),
+            options: self.options.clone(),
+            hooks: self.hooks.clone(),
+        };
+
+        let result = match (f)(savepoint_conn).await {
+            Ok(result) => match (lender.take_back(), &mut self.db) {
+                (Some(db), ConnectionDb::Tx(self_db)) => {
+                    **self_db = db;
+                    Ok(result)
+                }
+                _ => Err(c3p0_common::pool::connection_not_released_error().into()),
+            },
+            Err(err) => Err(err),
+        };
+        if result.is_err() {
+            self.changes.lock().truncate(queued_changes);
+            self.hooks.rolled_back_to(mark).await;
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl C3p0Lock for InMemoryConnection {
+    /// It waits at most the lock timeout of the transaction, if any
+    async fn lock(&mut self, key: &LockKey) -> Result<(), C3p0Error> {
+        self.require_transaction(key)?;
+        if !self.held_locks.lock().contains_key(key) {
+            let lock = lock_mutex(&self.locks, key).lock_owned();
+            let guard = match self.options.lock_timeout {
+                Some(timeout) => {
+                    time::timeout(timeout, lock)
+                        .await
+                        .map_err(|_| C3p0Error::TimeoutError {
+                            db: "in_memory",
+                            cause: format!(
+                                "Cannot acquire the lock [{}] within the lock timeout of {:?}",
+                                key, timeout
+                            ),
+                            code: None,
+                        })?
+                }
+                None => lock.await,
+            };
+            self.held_locks.lock().insert(key.clone(), guard);
+        }
+        Ok(())
+    }
+
+    async fn try_lock(&mut self, key: &LockKey) -> Result<bool, C3p0Error> {
+        self.require_transaction(key)?;
+        if !self.held_locks.lock().contains_key(key) {
+            match lock_mutex(&self.locks, key).try_lock_owned() {
+                Ok(guard) => {
+                    self.held_locks.lock().insert(key.clone(), guard);
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    async fn lock_with_timeout(
+        &mut self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<bool, C3p0Error> {
+        self.require_transaction(key)?;
+        if !self.held_locks.lock().contains_key(key) {
+            match time::timeout(timeout, lock_mutex(&self.locks, key).lock_owned()).await {
+                Ok(guard) => {
+                    self.held_locks.lock().insert(key.clone(), guard);
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The session locks do not hold the connection of the pool, but the transactions do:
+/// a transaction that waits for a lock held by a session lock blocks every other transaction,
+/// including the ones opened by the holder of the session lock, that would never release it.
+/// Unlike Postgres and MySql, where the transactions run on different connections,
+/// this interleaving deadlocks, unless the waiting transaction has a lock timeout
+/// (see `TxOptions::with_lock_timeout`).
+#[async_trait]
+impl C3p0SessionLock for InMemoryC3p0Pool {
+    async fn session_lock(&self, key: &LockKey) -> Result<SessionLock, C3p0Error> {
+        let guard = lock_mutex(&self.locks, key).lock_owned().await;
+        Ok(InMemoryLockRelease::lock(key, guard))
+    }
+
+    async fn try_session_lock(&self, key: &LockKey) -> Result<Option<SessionLock>, C3p0Error> {
+        Ok(lock_mutex(&self.locks, key)
+            .try_lock_owned()
+            .ok()
+            .map(|guard| InMemoryLockRelease::lock(key, guard)))
+    }
+
+    async fn session_lock_with_timeout(
+        &self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<Option<SessionLock>, C3p0Error> {
+        Ok(
+            time::timeout(timeout, lock_mutex(&self.locks, key).lock_owned())
+                .await
+                .ok()
+                .map(|guard| InMemoryLockRelease::lock(key, guard)),
+        )
+    }
+}
+
+/// Returns the mutex of the lock with the given key
+fn lock_mutex(locks: &Locks, key: &LockKey) -> Arc<Mutex<()>> {
+    locks.lock().entry(key.clone()).or_default().clone()
+}
+
+/// Holds the guard of a session lock; the lock is released when the guard is dropped
+struct InMemoryLockRelease {
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl InMemoryLockRelease {
+    fn lock(key: &LockKey, guard: OwnedMutexGuard<()>) -> SessionLock {
+        SessionLock::new(key.clone(), Box::new(InMemoryLockRelease { _guard: guard }))
+    }
+}
+
+#[async_trait]
+impl LockRelease for InMemoryLockRelease {
+    async fn release(self: Box<Self>) -> Result<(), C3p0Error> {
+        Ok(())
+    }
 }
 
 impl Deref for InMemoryConnection {
     type Target = Db;
 
     fn deref(&self) -> &Self::Target {
-        &self.db
+        match &self.db {
+            ConnectionDb::Tx(db) => db,
+            ConnectionDb::Conn(db) => db,
+        }
     }
 }
 
 impl DerefMut for InMemoryConnection {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.db
+        match &mut self.db {
+            ConnectionDb::Tx(db) => db,
+            ConnectionDb::Conn(db) => db,
+        }
     }
 }
 
@@ -199,4 +584,68 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn should_refuse_the_transactions_after_close() -> Result<(), C3p0Error> {
+        let pool = InMemoryC3p0Pool::new();
+        pool.wait_until_ready(Duration::from_secs(1)).await?;
+
+        let status = pool
+            .transaction(|_conn| {
+                let pool = pool.clone();
+                async move { Ok::<_, C3p0Error>(pool.status()) }
+            })
+            .await?;
+        assert_eq!(1, status.in_use);
+        assert_eq!(0, pool.status().in_use);
+
+        pool.close().await;
+        assert!(pool.ping().await.is_err());
+        let result = pool
+            .transaction(|_conn| async { Ok::<_, C3p0Error>(()) })
+            .await;
+        match result {
+            Err(C3p0Error::PoolError { .. }) => {}
+            _ => panic!("Expected a PoolError"),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_block_the_transactions_while_a_transaction_waits_for_a_session_lock(
+    ) -> Result<(), C3p0Error> {
+        let pool = InMemoryC3p0Pool::new();
+        let key = LockKey::from("session_lock");
+        let session_lock = pool.session_lock(&key).await?;
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            let key = key.clone();
+            async move {
+                let options = TxOptions::new().with_lock_timeout(Duration::from_millis(300));
+                pool.transaction_with_options(
+                    &options,
+                    |mut conn| async move { conn.lock(&key).await },
+                )
+                .await
+            }
+        });
+        time::delay_for(Duration::from_millis(50)).await;
+
+        // The waiting transaction holds the only connection
+        let transaction = pool.transaction(|_conn| async { Ok::<_, C3p0Error>(()) });
+        assert!(time::timeout(Duration::from_millis(50), transaction)
+            .await
+            .is_err());
+
+        // until its lock timeout expires
+        match waiting.await.unwrap() {
+            Err(C3p0Error::TimeoutError { .. }) => {}
+            result => panic!("Expected a TimeoutError, found {:?}", result),
+        }
+        pool.transaction(|_conn| async { Ok::<_, C3p0Error>(()) })
+            .await?;
+
+        session_lock.release().await
+    }
 }
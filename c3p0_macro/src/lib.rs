@@ -0,0 +1,368 @@
+//! Derive macros for c3p0.
+//!
+//! `#[derive(C3p0Model)]` implements `c3p0::C3p0Model` for a struct with named fields
+//! and generates a typed repository, named after the struct (e.g. `UserRepository`),
+//! that wraps any `C3p0Json` implementation of the struct.
+//!
+//! The supported attributes are:
+//! - `#[c3p0(table = "name")]` on the struct, to set the table name;
+//!   by default, it is the struct name in snake_case.
+//! - `#[c3p0(unique)]` on a field, to declare a unique index and generate a `find_by_<field>` finder
+//!   that returns at most one entry.
+//! - `#[c3p0(index)]` on a field, to declare an index and generate a `find_all_by_<field>` finder.
+//!
+//! The JSON name of the fields takes into account the serde `rename` and `rename_all` attributes.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::meta::ParseNestedMeta;
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Fields, LitStr};
+
+#[proc_macro_derive(C3p0Model, attributes(c3p0))]
+pub fn derive_c3p0_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+struct IndexedField {
+    ident: syn::Ident,
+    ty: syn::Type,
+    json_name: String,
+    unique: bool,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, syn::Error> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "C3p0Model cannot be derived for generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "C3p0Model can be derived only for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "C3p0Model can be derived only for structs",
+            ))
+        }
+    };
+
+    let mut table_name = None;
+    for attr in c3p0_attrs(&input.attrs) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                table_name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported c3p0 attribute, expected `table`"))
+            }
+        })?;
+    }
+    let table_name = table_name.unwrap_or_else(|| to_snake_case(&input.ident.to_string()));
+    let rename_all = serde_rename(&input.attrs, "rename_all")?;
+
+    let mut indexed_fields = vec![];
+    for field in fields {
+        let mut index = None;
+        for attr in c3p0_attrs(&field.attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("unique") {
+                    index = Some(true);
+                    Ok(())
+                } else if meta.path.is_ident("index") {
+                    index = Some(false);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported c3p0 attribute, expected `index` or `unique`"))
+                }
+            })?;
+        }
+
+        if let (Some(unique), Some(ident)) = (index, &field.ident) {
+            let field_name = ident.unraw().to_string();
+            let json_name = match serde_rename(&field.attrs, "rename")? {
+                Some(json_name) => json_name,
+                None => apply_rename_all(&field_name, rename_all.as_deref(), ident)?,
+            };
+            indexed_fields.push(IndexedField {
+                ident: ident.clone(),
+                ty: field.ty.clone(),
+                json_name,
+                unique,
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let repository = format_ident!("{}Repository", name);
+    let repository_doc = format!("A typed repository of [`{}`] documents", name);
+
+    let indexes = indexed_fields.iter().map(|field| {
+        let json_name = &field.json_name;
+        let unique = field.unique;
+        quote! { ::c3p0::C3p0Index::new(#json_name, #unique) }
+    });
+
+    let finders = indexed_fields.iter().map(|field| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        let json_name = &field.json_name;
+        let field_name = ident.unraw();
+        if field.unique {
+            let finder = format_ident!("find_by_{}", field_name);
+            let doc = format!("Returns the entry with the given `{}`, if any", json_name);
+            quote! {
+                #[doc = #doc]
+                pub async fn #finder(
+                    &self,
+                    conn: &mut JSON::Conn,
+                    #ident: &#ty,
+                ) -> ::std::result::Result<::std::option::Option<::c3p0::Model<#name>>, ::c3p0::C3p0Error> {
+                    let value = ::c3p0::serde_json::to_value(#ident)?;
+                    ::std::result::Result::Ok(
+                        self.json
+                            .fetch_all_by_field(conn, #json_name, &value)
+                            .await?
+                            .into_iter()
+                            .next(),
+                    )
+                }
+            }
+        } else {
+            let finder = format_ident!("find_all_by_{}", field_name);
+            let doc = format!("Returns all the entries with the given `{}`", json_name);
+            quote! {
+                #[doc = #doc]
+                pub async fn #finder(
+                    &self,
+                    conn: &mut JSON::Conn,
+                    #ident: &#ty,
+                ) -> ::std::result::Result<::std::vec::Vec<::c3p0::Model<#name>>, ::c3p0::C3p0Error> {
+                    let value = ::c3p0::serde_json::to_value(#ident)?;
+                    self.json.fetch_all_by_field(conn, #json_name, &value).await
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl ::c3p0::C3p0Model for #name {
+            const TABLE_NAME: &'static str = #table_name;
+
+            fn indexes() -> ::std::vec::Vec<::c3p0::C3p0Index> {
+                ::std::vec![#(#indexes),*]
+            }
+        }
+
+        #[doc = #repository_doc]
+        #vis struct #repository<JSON, CODEC = ::c3p0::DefaultJsonCodec> {
+            json: JSON,
+            phantom_codec: ::std::marker::PhantomData<fn() -> CODEC>,
+        }
+
+        impl<JSON: ::std::clone::Clone, CODEC> ::std::clone::Clone for #repository<JSON, CODEC> {
+            fn clone(&self) -> Self {
+                #repository::new(self.json.clone())
+            }
+        }
+
+        impl<JSON, CODEC> ::std::ops::Deref for #repository<JSON, CODEC> {
+            type Target = JSON;
+
+            fn deref(&self) -> &JSON {
+                &self.json
+            }
+        }
+
+        impl<JSON, CODEC> #repository<JSON, CODEC> {
+            pub fn new(json: JSON) -> Self {
+                #repository {
+                    json,
+                    phantom_codec: ::std::marker::PhantomData,
+                }
+            }
+
+            pub fn json(&self) -> &JSON {
+                &self.json
+            }
+        }
+
+        impl<JSON, CODEC> #repository<JSON, CODEC>
+        where
+            JSON: ::c3p0::C3p0Json<#name, CODEC>,
+        {
+            /// Creates the table and its indexes
+            pub async fn create_table_if_not_exists(
+                &self,
+                conn: &mut JSON::Conn,
+            ) -> ::std::result::Result<(), ::c3p0::C3p0Error> {
+                self.json.create_table_if_not_exists(conn).await?;
+                for index in <#name as ::c3p0::C3p0Model>::indexes() {
+                    self.json.create_index_if_not_exists(conn, &index).await?;
+                }
+                ::std::result::Result::Ok(())
+            }
+
+            #(#finders)*
+        }
+    })
+}
+
+fn c3p0_attrs(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("c3p0"))
+}
+
+/// Returns the value of a serde attribute like `rename` or `rename_all`.
+/// When the serialize and deserialize names are different, the serialize one is used.
+fn serde_rename(attrs: &[Attribute], name: &str) -> Result<Option<String>, syn::Error> {
+    let mut result = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident(name) {
+                if meta.input.peek(syn::Token![=]) {
+                    result = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else {
+                    meta.parse_nested_meta(|inner| {
+                        if inner.path.is_ident("serialize") {
+                            result = Some(inner.value()?.parse::<LitStr>()?.value());
+                            Ok(())
+                        } else {
+                            skip_meta(&inner)
+                        }
+                    })?;
+                }
+                Ok(())
+            } else {
+                skip_meta(&meta)
+            }
+        })?;
+    }
+    Ok(result)
+}
+
+/// Consumes a serde attribute not relevant for c3p0
+fn skip_meta(meta: &ParseNestedMeta) -> Result<(), syn::Error> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|inner| skip_meta(&inner))?;
+    }
+    Ok(())
+}
+
+fn apply_rename_all(
+    field_name: &str,
+    rename_all: Option<&str>,
+    ident: &syn::Ident,
+) -> Result<String, syn::Error> {
+    let words = field_name.split('_').filter(|word| !word.is_empty());
+    Ok(match rename_all {
+        None | Some("snake_case") => field_name.to_owned(),
+        Some("lowercase") => field_name.to_lowercase(),
+        Some("UPPERCASE") | Some("SCREAMING_SNAKE_CASE") => field_name.to_uppercase(),
+        Some("kebab-case") => field_name.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => field_name.replace('_', "-").to_uppercase(),
+        Some("PascalCase") => words.map(capitalize).collect(),
+        Some("camelCase") => words
+            .enumerate()
+            .map(|(index, word)| {
+                if index == 0 {
+                    word.to_owned()
+                } else {
+                    capitalize(word)
+                }
+            })
+            .collect(),
+        Some(other) => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                format!("unsupported serde rename_all value [{}]", other),
+            ))
+        }
+    })
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                result.push('_');
+            }
+            result.extend(c.to_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_convert_to_snake_case() {
+        assert_eq!("user", to_snake_case("User"));
+        assert_eq!("user_account", to_snake_case("UserAccount"));
+    }
+
+    #[test]
+    fn should_apply_rename_all() {
+        let ident = format_ident!("first_name");
+        let rename = |rename_all| apply_rename_all("first_name", Some(rename_all), &ident).unwrap();
+        assert_eq!("firstName", rename("camelCase"));
+        assert_eq!("FirstName", rename("PascalCase"));
+        assert_eq!("first-name", rename("kebab-case"));
+        assert_eq!("FIRST_NAME", rename("SCREAMING_SNAKE_CASE"));
+        assert!(apply_rename_all("first_name", Some("unknown"), &ident).is_err());
+    }
+
+    #[test]
+    fn should_read_the_serde_rename_attributes() {
+        let input: DeriveInput = syn::parse_quote! {
+            #[serde(rename_all = "camelCase", deny_unknown_fields)]
+            struct User {
+                #[serde(default, rename(serialize = "mail", deserialize = "email"))]
+                email: String,
+            }
+        };
+        assert_eq!(
+            Some("camelCase".to_owned()),
+            serde_rename(&input.attrs, "rename_all").unwrap()
+        );
+        if let Data::Struct(data) = &input.data {
+            let field = data.fields.iter().next().unwrap();
+            assert_eq!(
+                Some("mail".to_owned()),
+                serde_rename(&field.attrs, "rename").unwrap()
+            );
+        }
+    }
+}
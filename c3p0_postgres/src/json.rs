@@ -1,8 +1,16 @@
-use crate::tokio_postgres::{row::Row, types::ToSql};
+use crate::tokio_postgres::{
+    row::Row,
+    types::{FromSqlOwned, ToSql},
+};
 use crate::*;
 use async_trait::async_trait;
-use c3p0_common::json::Queries;
+use c3p0_common::json::column::skip_index;
+use c3p0_common::json::{
+    like_prefix_pattern, select_by_field, select_by_field_prefix, select_by_max_field,
+    with_documents, Queries,
+};
 use c3p0_common::*;
+use serde_json::Value;
 
 pub trait PgC3p0JsonBuilder {
     fn build<DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync>(
@@ -15,6 +23,13 @@ pub trait PgC3p0JsonBuilder {
         self,
         codec: CODEC,
     ) -> PgC3p0Json<DATA, CODEC>;
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> PgC3p0Binary<DATA, CODEC>;
 }
 
 impl PgC3p0JsonBuilder for C3p0JsonBuilder<PgC3p0Pool> {
@@ -34,25 +49,53 @@ impl PgC3p0JsonBuilder for C3p0JsonBuilder<PgC3p0Pool> {
         PgC3p0Json {
             phantom_data: std::marker::PhantomData,
             codec,
+            column: JsonColumn {
+                compression: self.compression.clone(),
+                schema: self.schema.clone(),
+            },
             queries: build_pg_queries(self),
         }
     }
+
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> PgC3p0Binary<DATA, CODEC> {
+        PgC3p0Json {
+            phantom_data: std::marker::PhantomData,
+            codec,
+            column: BinaryColumn {},
+            queries: build_pg_binary_queries(self),
+        }
+    }
 }
 
+/// A C3p0Json implementation that stores the documents in the data column `COLUMN`:
+/// a JSONB column by default, or a bytea column for the binary documents
 #[derive(Clone)]
-pub struct PgC3p0Json<DATA, CODEC: JsonCodec<DATA>>
+pub struct PgC3p0Json<DATA, CODEC, COLUMN = JsonColumn>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
 {
     phantom_data: std::marker::PhantomData<DATA>,
 
     codec: CODEC,
+    column: COLUMN,
     queries: Queries,
 }
 
-impl<DATA, CODEC: JsonCodec<DATA>> PgC3p0Json<DATA, CODEC>
+/// A C3p0Json implementation that stores the data in a bytea column
+/// encoded by a BinaryCodec.
+pub type PgC3p0Binary<DATA, CODEC> = PgC3p0Json<DATA, CODEC, BinaryColumn>;
+
+impl<DATA, CODEC, COLUMN> PgC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    COLUMN::Stored: ToSql + FromSqlOwned,
 {
     pub fn queries(&self) -> &Queries {
         &self.queries
@@ -60,7 +103,12 @@ where
 
     #[inline]
     pub fn to_model(&self, row: &Row) -> Result<Model<DATA>, Box<dyn std::error::Error>> {
-        to_model(&self.codec, row, 0, 1, 2)
+        let model = to_stored_model::<COLUMN::Stored>(row)?;
+        Ok(Model {
+            id: model.id,
+            version: model.version,
+            data: self.column.decode(&self.codec, model.data)?,
+        })
     }
 
     /// Allows the execution of a custom sql query and returns the first entry in the result set.
@@ -105,9 +153,12 @@ where
 }
 
 #[async_trait]
-impl<DATA, CODEC: JsonCodec<DATA>> C3p0Json<DATA, CODEC> for PgC3p0Json<DATA, CODEC>
+impl<DATA, CODEC, COLUMN> C3p0Json<DATA, CODEC> for PgC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Clone + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    COLUMN::Stored: ToSql + FromSqlOwned,
 {
     type Conn = PgConnection;
 
@@ -115,9 +166,32 @@ where
         &self.codec
     }
 
+    fn qualified_table_name(&self) -> &str {
+        &self.queries.qualified_table_name
+    }
+
     async fn create_table_if_not_exists(&self, conn: &mut PgConnection) -> Result<(), C3p0Error> {
         conn.execute(&self.queries.create_table_sql_query, &[])
             .await?;
+        if let Some(change_feed_sql_query) = &self.queries.change_feed_sql_query {
+            conn.batch_execute(change_feed_sql_query).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_index_if_not_exists(
+        &self,
+        conn: &mut PgConnection,
+        index: &C3p0Index,
+    ) -> Result<(), C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "create_index_if_not_exists",
+        )? {
+            return skip_index(&self.queries.qualified_table_name, index);
+        }
+        conn.execute(&build_pg_create_index_sql_query(&self.queries, index), &[])
+            .await?;
         Ok(())
     }
 
@@ -170,6 +244,139 @@ where
         conn.fetch_all(&sql, &[], |row| self.to_model(row)).await
     }
 
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut PgConnection,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        conn.fetch_all(
+            &self.queries.find_all_after_id_sql_query,
+            &[&after_id, &(limit as i64)],
+            |row| self.to_model(row),
+        )
+        .await
+    }
+
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut PgConnection,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error> {
+        conn.fetch_all(
+            &self.queries.find_all_after_id_sql_query,
+            &[&after_id, &(limit as i64)],
+            |row| {
+                let model = to_stored_model::<COLUMN::Stored>(row)?;
+                Ok(Model {
+                    id: model.id,
+                    version: model.version,
+                    data: self.column.to_value(model.data)?,
+                })
+            },
+        )
+        .await
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.column.is_current(&self.codec, value)
+    }
+
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut PgConnection,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self
+            .column
+            .fields_queryable(&self.queries.qualified_table_name, "fetch_all_by_field")?
+        {
+            return select_by_field(self.fetch_all(conn).await?, field_name, value);
+        }
+        conn.fetch_all(
+            &build_pg_find_all_by_field_sql_query(&self.queries, field_name),
+            &[value],
+            |row| self.to_model(row),
+        )
+        .await
+    }
+
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut PgConnection,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_field_prefix",
+        )? {
+            return select_by_field_prefix(self.fetch_all(conn).await?, field_name, prefix);
+        }
+        conn.fetch_all(
+            &build_pg_find_all_by_field_prefix_sql_query(&self.queries, field_name),
+            &[&like_prefix_pattern(prefix)],
+            |row| self.to_model(row),
+        )
+        .await
+    }
+
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut PgConnection,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_max_field_for_update",
+        )? {
+            return conn
+                .fetch_all(
+                    &build_pg_find_all_by_max_field_sql_query(
+                        &self.queries,
+                        field_name,
+                        order_by_field,
+                        for_update,
+                    ),
+                    &[&max_value, &(limit as i64)],
+                    |row| self.to_model(row),
+                )
+                .await;
+        }
+
+        // The documents cannot be queried, so the entries are selected in memory
+        // without locking them; then only the selected entries are locked, in their order,
+        // and they are selected again because they could have changed in the meantime
+        let selected = select_by_max_field(
+            with_documents(self.fetch_all(conn).await?)?,
+            field_name,
+            max_value,
+            order_by_field,
+            u32::MAX,
+        );
+        if selected.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids = selected.iter().map(|model| model.id).collect::<Vec<_>>();
+        let sql = build_pg_find_by_ids_sql_query(&self.queries, for_update);
+        let locked = conn
+            .fetch_all(&sql, &[&ids, &(limit as i64)], |row| self.to_model(row))
+            .await?;
+        Ok(select_by_max_field(
+            with_documents(locked)?,
+            field_name,
+            max_value,
+            order_by_field,
+            limit,
+        ))
+    }
+
     async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
         &'a self,
         conn: &mut PgConnection,
@@ -253,9 +460,9 @@ where
         conn: &mut PgConnection,
         obj: NewModel<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let json_data = self.codec().to_value(&obj.data)?;
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
         let id = conn
-            .fetch_one_value(&self.queries.save_sql_query, &[&obj.version, &json_data])
+            .fetch_one_value(&self.queries.save_sql_query, &[&obj.version, &stored_data])
             .await?;
         Ok(Model {
             id,
@@ -264,12 +471,26 @@ where
         })
     }
 
+    async fn save_with_id(
+        &self,
+        conn: &mut PgConnection,
+        obj: Model<DATA>,
+    ) -> Result<Model<DATA>, C3p0Error> {
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
+        conn.execute(
+            &self.queries.save_with_id_sql_query,
+            &[&obj.id, &obj.version, &stored_data],
+        )
+        .await?;
+        Ok(obj)
+    }
+
     async fn update(
         &self,
         conn: &mut PgConnection,
         obj: Model<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let json_data = self.codec().to_value(&obj.data)?;
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
 
         let updated_model = Model {
             id: obj.id,
@@ -282,7 +503,7 @@ where
                 &self.queries.update_sql_query,
                 &[
                     &updated_model.version,
-                    &json_data,
+                    &stored_data,
                     &updated_model.id,
                     &obj.version,
                 ],
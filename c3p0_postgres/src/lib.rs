@@ -2,6 +2,7 @@ mod common;
 
 mod error;
 mod json;
+mod lock;
 mod pool;
 
 pub use common::*;
@@ -0,0 +1,229 @@
+use crate::deadpool::postgres::Client;
+use crate::pool::close_client;
+use crate::*;
+
+use async_trait::async_trait;
+use c3p0_common::*;
+use std::time::Duration;
+
+/// The SQLSTATE of a lock that was not acquired within the `lock_timeout`
+const LOCK_NOT_AVAILABLE: &str = "55P03";
+
+#[async_trait]
+impl C3p0Lock for PgConnection {
+    async fn lock(&mut self, key: &LockKey) -> Result<(), C3p0Error> {
+        require_transaction(self, key)?;
+        self.execute("SELECT pg_advisory_xact_lock($1)", &[&key.id()])
+            .await
+            .map(|_| ())
+    }
+
+    async fn try_lock(&mut self, key: &LockKey) -> Result<bool, C3p0Error> {
+        require_transaction(self, key)?;
+        self.fetch_one_value("SELECT pg_try_advisory_xact_lock($1)", &[&key.id()])
+            .await
+    }
+
+    async fn lock_with_timeout(
+        &mut self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<bool, C3p0Error> {
+        require_transaction(self, key)?;
+        // The failed lock aborts the transaction, so it is attempted in a savepoint
+        let lock_timeout: String = self
+            .fetch_one_value("SELECT current_setting('lock_timeout')", &[])
+            .await?;
+        self.batch_execute("SAVEPOINT c3p0_lock_with_timeout")
+            .await?;
+        self.execute(
+            "SELECT set_config('lock_timeout', $1, true)",
+            &[&to_pg_timeout(timeout)],
+        )
+        .await?;
+        match self
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&key.id()])
+            .await
+        {
+            Ok(_) => {
+                self.batch_execute("RELEASE SAVEPOINT c3p0_lock_with_timeout")
+                    .await?;
+                self.execute(
+                    "SELECT set_config('lock_timeout', $1, true)",
+                    &[&lock_timeout],
+                )
+                .await?;
+                Ok(true)
+            }
+            Err(err) if is_lock_not_available(&err) => {
+                self.batch_execute(
+                    "ROLLBACK TO SAVEPOINT c3p0_lock_with_timeout; RELEASE SAVEPOINT c3p0_lock_with_timeout",
+                )
+                .await?;
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl C3p0SessionLock for PgC3p0Pool {
+    async fn session_lock(&self, key: &LockKey) -> Result<SessionLock, C3p0Error> {
+        let acquisition = PgLockAcquisition::new(self.get_client().await?);
+        acquisition
+            .client()
+            .execute("SELECT pg_advisory_lock($1)", &[&key.id()])
+            .await
+            .map_err(into_c3p0_error)?;
+        Ok(PgLockRelease::lock(key, acquisition.into_client()))
+    }
+
+    async fn try_session_lock(&self, key: &LockKey) -> Result<Option<SessionLock>, C3p0Error> {
+        let acquisition = PgLockAcquisition::new(self.get_client().await?);
+        let locked: bool = acquisition
+            .client()
+            .query_one("SELECT pg_try_advisory_lock($1)", &[&key.id()])
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(into_c3p0_error)?;
+        let client = acquisition.into_client();
+        Ok(if locked {
+            Some(PgLockRelease::lock(key, client))
+        } else {
+            None
+        })
+    }
+
+    async fn session_lock_with_timeout(
+        &self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<Option<SessionLock>, C3p0Error> {
+        let acquisition = PgLockAcquisition::new(self.get_client().await?);
+        let client = acquisition.client();
+        client
+            .batch_execute(&format!("SET lock_timeout = '{}'", to_pg_timeout(timeout)))
+            .await
+            .map_err(into_c3p0_error)?;
+        let result = client
+            .execute("SELECT pg_advisory_lock($1)", &[&key.id()])
+            .await
+            .map_err(into_c3p0_error);
+        client
+            .batch_execute("RESET lock_timeout")
+            .await
+            .map_err(into_c3p0_error)?;
+        let client = acquisition.into_client();
+        match result {
+            Ok(_) => Ok(Some(PgLockRelease::lock(key, client))),
+            Err(err) if is_lock_not_available(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Holds the pooled connection of a session lock being acquired.
+/// If it is dropped before `into_client`, because the acquisition failed or its future
+/// was dropped, the connection is closed: Postgres could still grant it the lock,
+/// or it could keep the lock timeout.
+struct PgLockAcquisition {
+    client: Option<Client>,
+}
+
+impl PgLockAcquisition {
+    fn new(client: Client) -> Self {
+        PgLockAcquisition {
+            client: Some(client),
+        }
+    }
+
+    fn client(&self) -> &Client {
+        self.client
+            .as_ref()
+            .expect("The client is taken only by into_client")
+    }
+
+    fn into_client(mut self) -> Client {
+        self.client
+            .take()
+            .expect("The client is taken only by into_client")
+    }
+}
+
+impl Drop for PgLockAcquisition {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            close_client(client);
+        }
+    }
+}
+
+/// Holds the pooled connection of a session lock.
+/// If it is dropped without being released, the connection is closed by `close_client`,
+/// so that Postgres releases the lock.
+struct PgLockRelease {
+    client: Option<Client>,
+    id: i64,
+}
+
+impl PgLockRelease {
+    fn lock(key: &LockKey, client: Client) -> SessionLock {
+        SessionLock::new(
+            key.clone(),
+            Box::new(PgLockRelease {
+                client: Some(client),
+                id: key.id(),
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl LockRelease for PgLockRelease {
+    async fn release(mut self: Box<Self>) -> Result<(), C3p0Error> {
+        if let Some(client) = self.client.take() {
+            if let Err(err) = client
+                .execute("SELECT pg_advisory_unlock($1)", &[&self.id])
+                .await
+            {
+                close_client(client);
+                return Err(into_c3p0_error(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PgLockRelease {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            close_client(client);
+        }
+    }
+}
+
+/// The transaction locks of a connection outside of a transaction would be released
+/// at the end of the statement, so they are refused
+fn require_transaction(conn: &PgConnection, key: &LockKey) -> Result<(), C3p0Error> {
+    if conn.in_transaction() {
+        Ok(())
+    } else {
+        Err(c3p0_common::lock::no_transaction_error(key))
+    }
+}
+
+/// Returns the value of a Postgres timeout setting
+pub(crate) fn to_pg_timeout(timeout: Duration) -> String {
+    // A timeout of zero disables the timeout
+    format!("{}ms", timeout.as_millis().max(1))
+}
+
+fn is_lock_not_available(err: &C3p0Error) -> bool {
+    match err {
+        C3p0Error::TimeoutError {
+            code: Some(code), ..
+        } => code == LOCK_NOT_AVAILABLE,
+        _ => false,
+    }
+}
@@ -1,12 +1,24 @@
+use crate::deadpool::managed::Object;
+use crate::deadpool::postgres::Client;
 use crate::deadpool::postgres::Pool;
+use crate::lock::to_pg_timeout;
 use crate::tokio_postgres::row::Row;
+use crate::tokio_postgres::tls::{MakeTlsConnect, TlsConnect};
 use crate::tokio_postgres::types::{FromSqlOwned, ToSql};
-use crate::tokio_postgres::Transaction;
+use crate::tokio_postgres::{AsyncMessage, Config, NoTls, Socket};
 use crate::*;
 
 use async_trait::async_trait;
+use c3p0_common::lend::{lend, Lender, Lent};
+use c3p0_common::pool::SqlSavepointConnection;
+use c3p0_common::trace::TraceSpan;
 use c3p0_common::*;
-use futures::Future;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{stream, Future, FutureExt, Stream, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub enum PgC3p0ConnectionManager {
     DeadPool,
@@ -14,17 +26,97 @@ pub enum PgC3p0ConnectionManager {
 
 impl PgC3p0ConnectionManager {}
 
+/// The number of change events buffered for a subscriber; when the buffer is full,
+/// the listener connection is not read until the subscriber consumes the events
+const CHANGE_FEED_BUFFER_SIZE: usize = 1024;
+
+/// Opens a dedicated connection with the configuration, returning its client and its messages
+type ListenerConnect = Arc<
+    dyn Fn(
+            &Config,
+        ) -> BoxFuture<
+            'static,
+            Result<
+                (
+                    tokio_postgres::Client,
+                    BoxStream<'static, Result<AsyncMessage, tokio_postgres::Error>>,
+                ),
+                tokio_postgres::Error,
+            >,
+        > + Send
+        + Sync,
+>;
+
 #[derive(Clone)]
 pub struct PgC3p0Pool {
     pool: Pool,
+    listener_config: Option<Config>,
+    listener_connect: ListenerConnect,
+    closed: Arc<AtomicBool>,
 }
 
 impl PgC3p0Pool {
     pub fn new(pool: Pool) -> Self {
-        PgC3p0Pool { pool }
+        PgC3p0Pool {
+            pool,
+            listener_config: None,
+            listener_connect: listener_connect(NoTls),
+            closed: Default::default(),
+        }
+    }
+
+    /// Sets the configuration of the dedicated connections opened by `subscribe`.
+    /// The notifications cannot be received through the pooled connections,
+    /// so subscribing to a change feed fails if this configuration is not set.
+    /// The connections are opened without TLS, unless `with_listener_tls` sets a connector.
+    pub fn with_listener_config<O: Into<Option<Config>>>(mut self, listener_config: O) -> Self {
+        self.listener_config = listener_config.into();
+        self
+    }
+
+    /// Sets the TLS connector of the dedicated connections opened by `subscribe`,
+    /// usually the same one of the pool
+    pub fn with_listener_tls<T>(mut self, tls: T) -> Self
+    where
+        T: MakeTlsConnect<Socket> + Clone + Send + Sync + 'static,
+        T::Stream: Send,
+        T::TlsConnect: Send,
+        <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+    {
+        self.listener_connect = listener_connect(tls);
+        self
+    }
+
+    /// Returns a connection of the pool outside of a transaction
+    pub(crate) async fn get_client(&self) -> Result<Client, C3p0Error> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(c3p0_common::pool::pool_closed_error("postgres", "deadpool"));
+        }
+        self.pool.get().await.map_err(deadpool_into_c3p0_error)
     }
 }
 
+/// Returns a `ListenerConnect` that opens the connections with the TLS connector
+fn listener_connect<T>(tls: T) -> ListenerConnect
+where
+    T: MakeTlsConnect<Socket> + Clone + Send + Sync + 'static,
+    T::Stream: Send,
+    T::TlsConnect: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    Arc::new(move |config| {
+        let config = config.clone();
+        let tls = tls.clone();
+        async move {
+            let (client, mut connection) = config.connect(tls).await?;
+            let messages: BoxStream<'static, _> =
+                Box::pin(stream::poll_fn(move |cx| connection.poll_message(cx)));
+            Ok((client, messages))
+        }
+        .boxed()
+    })
+}
+
 impl Into<PgC3p0Pool> for Pool {
     fn into(self) -> PgC3p0Pool {
         PgC3p0Pool::new(self)
@@ -35,52 +127,360 @@ impl Into<PgC3p0Pool> for Pool {
 impl C3p0Pool for PgC3p0Pool {
     type Conn = PgConnection;
 
-    async fn transaction<
+    fn status(&self) -> PoolStatus {
+        let status = self.pool.status();
+        // The available connections become negative when there are waiters
+        let idle = status.available.max(0) as usize;
+        PoolStatus {
+            size: status.size,
+            idle,
+            in_use: status.size.saturating_sub(idle),
+            waiters: (-status.available).max(0) as usize,
+        }
+    }
+
+    async fn ping(&self) -> Result<(), C3p0Error> {
+        self.get_client()
+            .await?
+            .batch_execute("SELECT 1")
+            .await
+            .map_err(into_c3p0_error)
+    }
+
+    /// Deadpool cannot close a pool, so the idle connections are taken out of the pool
+    /// and closed until the connections in use are released
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        loop {
+            let status = self.pool.status();
+            if status.size == 0 {
+                return;
+            }
+            if status.available > 0 {
+                if let Ok(client) = self.pool.try_get().await {
+                    close_client(client);
+                    continue;
+                }
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn transaction_with_options<
         T: Send,
         E: Send + From<C3p0Error>,
         F: Send + FnOnce(Self::Conn) -> Fut,
         Fut: Send + Future<Output = Result<T, E>>,
     >(
         &self,
+        options: &TxOptions,
         tx: F,
     ) -> Result<T, E> {
-        let mut conn = self.pool.get().await.map_err(deadpool_into_c3p0_error)?;
+        TraceSpan::transaction("postgres", options)
+            .run_transaction(async move {
+                let client = self.get_client().await?;
+                let (client, lender) = lend(client, close_client);
+                let hooks = TransactionHooks::new();
 
-        let native_transaction = conn.transaction().await.map_err(into_c3p0_error)?;
+                let mut transaction = PgConnection {
+                    client,
+                    in_transaction: true,
+                    hooks: hooks.clone(),
+                };
 
-        // ToDo: To avoid this unsafe we need GAT
-        let transaction = PgConnection::Tx(unsafe { ::std::mem::transmute(&native_transaction) });
+                let result = match transaction.batch_execute(&begin_sql(options)).await {
+                    // If the client timeout expires the connection is dropped with `tx`
+                    // and closed by `close_client`, because a statement could still be running
+                    Ok(()) => {
+                        match c3p0_common::pool::with_client_timeout(options, (tx)(transaction))
+                            .await
+                        {
+                            Some(result) => end_transaction(lender, result).await,
+                            None => {
+                                drop(lender);
+                                Err(c3p0_common::pool::client_timeout_error("postgres", options)
+                                    .into())
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        // The transaction is aborted if one of the SET LOCAL statements failed
+                        drop(transaction);
+                        end_transaction(lender, Err(err.into())).await
+                    }
+                };
 
-        let result = { (tx)(transaction).await? };
+                match result {
+                    Ok(_) => hooks.committed().await,
+                    Err(_) => hooks.rolled_back().await,
+                }
+                result
+            })
+            .await
+    }
 
-        native_transaction.commit().await.map_err(into_c3p0_error)?;
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let client = self.get_client().await?;
+        // Outside of a transaction the client can be returned to the pool when it is dropped,
+        // even if `f` kept it
+        let (client, _) = lend(client, drop);
+
+        let hooks = TransactionHooks::new();
 
-        Ok(result)
+        let conn = PgConnection {
+            client,
+            in_transaction: false,
+            hooks: hooks.clone(),
+        };
+
+        let result = (f)(conn).await;
+
+        // Every statement is already committed
+        hooks.committed().await;
+        result
     }
 }
 
-pub enum PgConnection {
-    Tx(&'static Transaction<'static>),
+/// Commits the transaction if `result` is Ok, otherwise rolls it back
+async fn end_transaction<T, E: From<C3p0Error>>(
+    lender: Lender<Client>,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    let client = match lender.take_back() {
+        Some(client) => client,
+        // The connection is closed by `close_client` when it is dropped
+        None => return Err(c3p0_common::pool::connection_not_released_error().into()),
+    };
+    let sql = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    match client.batch_execute(sql).await {
+        Ok(()) => result,
+        Err(err) => {
+            // The transaction could still be open, so the connection is closed
+            close_client(client);
+            // The error of `tx` is more relevant than a failed rollback
+            result.and_then(|_| Err(into_c3p0_error(err).into()))
+        }
+    }
+}
+
+/// Closes the connection instead of returning it to the pool
+pub(crate) fn close_client(client: Client) {
+    Object::take(client);
+}
+
+#[async_trait]
+impl C3p0ChangeFeed for PgC3p0Pool {
+    async fn subscribe(&self, table_name: &str) -> Result<ChangeStream, C3p0Error> {
+        let config = self
+            .listener_config
+            .as_ref()
+            .ok_or_else(|| C3p0Error::InternalError {
+                cause: "PgC3p0Pool has no listener config, it cannot subscribe to a change feed"
+                    .to_owned(),
+            })?;
+
+        let (client, messages) = (self.listener_connect)(config)
+            .await
+            .map_err(into_c3p0_error)?;
+        let (sender, receiver) = futures::channel::mpsc::channel(CHANGE_FEED_BUFFER_SIZE);
+        // The connection is closed when the returned stream is dropped
+        tokio::spawn(
+            change_events(messages, table_name.to_owned())
+                .map(Ok)
+                .forward(sender)
+                .map(|_| ()),
+        );
+
+        client
+            .batch_execute(&format!(
+                r#"LISTEN "{}""#,
+                change_feed_channel(table_name).replace('"', r#""""#)
+            ))
+            .await
+            .map_err(into_c3p0_error)?;
+
+        Ok(Box::pin(receiver.map(move |event| {
+            let _client = &client;
+            event
+        })))
+    }
+}
+
+/// Returns the change events of the notifications received by a listener connection.
+/// The stream ends with an error when the connection fails or is closed.
+fn change_events(
+    messages: BoxStream<'static, Result<AsyncMessage, tokio_postgres::Error>>,
+    table_name: String,
+) -> impl Stream<Item = Result<ChangeEvent, C3p0Error>> {
+    stream::unfold(Some(messages), move |messages| {
+        let table_name = table_name.clone();
+        async move {
+            let mut messages = messages?;
+            loop {
+                match messages.next().await {
+                    Some(Ok(AsyncMessage::Notification(notification))) => {
+                        return Some((
+                            ChangeEvent::from_payload(notification.payload()),
+                            Some(messages),
+                        ))
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return Some((Err(into_c3p0_error(err)), None)),
+                    None => {
+                        return Some((Err(listener_closed_error("postgres", &table_name)), None))
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Returns the `SET LOCAL` statements of the timeouts of the options, if any
+fn set_local_timeouts_sql(options: &TxOptions) -> Option<String> {
+    let mut statements = vec![];
+    if let Some(timeout) = options.statement_timeout {
+        statements.push(format!(
+            "SET LOCAL statement_timeout = '{}'",
+            to_pg_timeout(timeout)
+        ));
+    }
+    if let Some(timeout) = options.lock_timeout {
+        statements.push(format!(
+            "SET LOCAL lock_timeout = '{}'",
+            to_pg_timeout(timeout)
+        ));
+    }
+    if statements.is_empty() {
+        None
+    } else {
+        Some(statements.join("; "))
+    }
+}
+
+/// Returns the statements that start a transaction with the options.
+/// The `Transaction` of tokio-postgres borrows its client, while the transactions of the pool
+/// own it (see `lend`), so `Client::build_transaction` cannot be used:
+/// the `START TRANSACTION` statement is the same one of its `TransactionBuilder`.
+fn begin_sql(options: &TxOptions) -> String {
+    let mut modes = vec![];
+    if let Some(isolation) = options.isolation {
+        modes.push(format!("ISOLATION LEVEL {}", isolation.as_sql()));
+    }
+    if options.read_only {
+        modes.push("READ ONLY".to_owned());
+    }
+    if options.deferrable {
+        modes.push("DEFERRABLE".to_owned());
+    }
+    let mut sql = "START TRANSACTION".to_owned();
+    if !modes.is_empty() {
+        sql.push(' ');
+        sql.push_str(&modes.join(", "));
+    }
+    if let Some(timeouts) = set_local_timeouts_sql(options) {
+        sql.push_str("; ");
+        sql.push_str(&timeouts);
+    }
+    sql
+}
+
+pub struct PgConnection {
+    client: Lent<Client>,
+    /// Whether the connection is in a transaction started by the pool
+    in_transaction: bool,
+    hooks: TransactionHooks,
+}
+
+impl C3p0Hooks for PgConnection {
+    fn hooks(&self) -> &TransactionHooks {
+        &self.hooks
+    }
 }
 
 #[async_trait]
 impl SqlConnection for PgConnection {
     async fn batch_execute(&mut self, sql: &str) -> Result<(), C3p0Error> {
-        match self {
-            PgConnection::Tx(tx) => tx.batch_execute(sql).await.map_err(into_c3p0_error),
+        TraceSpan::sql("postgres", sql)
+            .run(async {
+                self.client()
+                    .batch_execute(sql)
+                    .await
+                    .map_err(into_c3p0_error)
+            })
+            .await
+    }
+}
+
+impl SqlSavepointConnection for PgConnection {
+    type Lender = Lender<Client>;
+
+    fn lend_transaction(&mut self) -> Result<(Self, Self::Lender), C3p0Error> {
+        if !self.in_transaction {
+            return Err(c3p0_common::pool::no_transaction_savepoint_error());
         }
+        let (client, lender) = self.client.lend_again();
+        Ok((
+            PgConnection {
+                client,
+                in_transaction: true,
+                hooks: self.hooks.clone(),
+            },
+            lender,
+        ))
+    }
+
+    fn restore_transaction(&mut self, lender: Self::Lender) -> bool {
+        self.client.restore(lender)
+    }
+}
+
+#[async_trait]
+impl C3p0Savepoint for PgConnection {
+    async fn savepoint<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &mut self,
+        f: F,
+    ) -> Result<T, E> {
+        c3p0_common::pool::sql_savepoint(self, f).await
     }
 }
 
 impl PgConnection {
+    /// The statements are prepared without the statement cache of the pool
+    fn client(&self) -> &crate::tokio_postgres::Client {
+        &self.client
+    }
+
+    /// Whether the connection is in a transaction started by the pool
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
     pub async fn execute(
         &mut self,
         sql: &str,
         params: &[&(dyn ToSql + Sync)],
     ) -> Result<u64, C3p0Error> {
-        match self {
-            PgConnection::Tx(tx) => tx.execute(sql, params).await.map_err(into_c3p0_error),
-        }
+        TraceSpan::sql("postgres", sql)
+            .run(async {
+                self.client()
+                    .execute(sql, params)
+                    .await
+                    .map_err(into_c3p0_error)
+            })
+            .await
     }
 
     pub async fn fetch_one_value<T: FromSqlOwned>(
@@ -108,21 +508,20 @@ impl PgConnection {
         params: &[&(dyn ToSql + Sync)],
         mapper: F,
     ) -> Result<Option<T>, C3p0Error> {
-        match self {
-            PgConnection::Tx(tx) => {
-                let stmt = tx.prepare(sql).await.map_err(into_c3p0_error)?;
-                tx.query(&stmt, params)
-                    .await
-                    .map_err(into_c3p0_error)?
-                    .iter()
+        TraceSpan::sql("postgres", sql)
+            .run(async {
+                let client = self.client();
+                let stmt = client.prepare(sql).await.map_err(into_c3p0_error)?;
+                let rows = client.query(&stmt, params).await.map_err(into_c3p0_error)?;
+                rows.iter()
                     .next()
                     .map(|row| mapper(&row))
                     .transpose()
                     .map_err(|err| C3p0Error::RowMapperError {
                         cause: format!("{}", err),
                     })
-            }
-        }
+            })
+            .await
     }
 
     pub async fn fetch_all<T, F: Fn(&Row) -> Result<T, Box<dyn std::error::Error>>>(
@@ -131,20 +530,19 @@ impl PgConnection {
         params: &[&(dyn ToSql + Sync)],
         mapper: F,
     ) -> Result<Vec<T>, C3p0Error> {
-        match self {
-            PgConnection::Tx(tx) => {
-                let stmt = tx.prepare(sql).await.map_err(into_c3p0_error)?;
-                tx.query(&stmt, params)
-                    .await
-                    .map_err(into_c3p0_error)?
-                    .iter()
+        TraceSpan::sql("postgres", sql)
+            .run(async {
+                let client = self.client();
+                let stmt = client.prepare(sql).await.map_err(into_c3p0_error)?;
+                let rows = client.query(&stmt, params).await.map_err(into_c3p0_error)?;
+                rows.iter()
                     .map(|row| mapper(&row))
                     .collect::<Result<Vec<T>, Box<dyn std::error::Error>>>()
                     .map_err(|err| C3p0Error::RowMapperError {
                         cause: format!("{}", err),
                     })
-            }
-        }
+            })
+            .await
     }
 
     pub async fn fetch_all_values<T: FromSqlOwned>(
@@ -155,3 +553,89 @@ impl PgConnection {
         self.fetch_all(sql, params, to_value_mapper).await
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn should_start_a_transaction_without_options() {
+        assert_eq!("START TRANSACTION", begin_sql(&TxOptions::default()));
+    }
+
+    #[test]
+    fn should_start_a_transaction_with_every_combination_of_options() {
+        let isolations = [
+            None,
+            Some((IsolationLevel::ReadUncommitted, "READ UNCOMMITTED")),
+            Some((IsolationLevel::ReadCommitted, "READ COMMITTED")),
+            Some((IsolationLevel::RepeatableRead, "REPEATABLE READ")),
+            Some((IsolationLevel::Serializable, "SERIALIZABLE")),
+        ];
+        let timeouts = [
+            None,
+            Some((Duration::from_millis(0), "1ms")),
+            Some((Duration::from_millis(1500), "1500ms")),
+        ];
+
+        for isolation in &isolations {
+            for read_only in &[false, true] {
+                for deferrable in &[false, true] {
+                    for statement_timeout in &timeouts {
+                        for lock_timeout in &timeouts {
+                            let options = TxOptions {
+                                isolation: isolation.map(|(isolation, _)| isolation),
+                                read_only: *read_only,
+                                deferrable: *deferrable,
+                                statement_timeout: statement_timeout.map(|(timeout, _)| timeout),
+                                lock_timeout: lock_timeout.map(|(timeout, _)| timeout),
+                                ..TxOptions::default()
+                            };
+
+                            let mut modes = vec![];
+                            if let Some((_, isolation)) = isolation {
+                                modes.push(format!("ISOLATION LEVEL {}", isolation));
+                            }
+                            if *read_only {
+                                modes.push("READ ONLY".to_owned());
+                            }
+                            if *deferrable {
+                                modes.push("DEFERRABLE".to_owned());
+                            }
+                            let mut statements = vec![if modes.is_empty() {
+                                "START TRANSACTION".to_owned()
+                            } else {
+                                format!("START TRANSACTION {}", modes.join(", "))
+                            }];
+                            if let Some((_, timeout)) = statement_timeout {
+                                statements
+                                    .push(format!("SET LOCAL statement_timeout = '{}'", timeout));
+                            }
+                            if let Some((_, timeout)) = lock_timeout {
+                                statements.push(format!("SET LOCAL lock_timeout = '{}'", timeout));
+                            }
+
+                            assert_eq!(statements.join("; "), begin_sql(&options));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_start_a_transaction_with_all_the_options() {
+        let options = TxOptions::default()
+            .with_isolation(IsolationLevel::Serializable)
+            .with_read_only(true)
+            .with_deferrable(true)
+            .with_statement_timeout(Duration::from_secs(2))
+            .with_lock_timeout(Duration::from_millis(250));
+        assert_eq!(
+            "START TRANSACTION ISOLATION LEVEL SERIALIZABLE, READ ONLY, DEFERRABLE; \
+             SET LOCAL statement_timeout = '2000ms'; SET LOCAL lock_timeout = '250ms'",
+            begin_sql(&options)
+        );
+    }
+}
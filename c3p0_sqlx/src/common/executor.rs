@@ -1,9 +1,10 @@
 use crate::common::to_model;
 use crate::error::into_c3p0_error;
 use c3p0_common::json::Queries;
-use c3p0_common::{C3p0Error, JsonCodec, Model};
+use c3p0_common::trace::TraceSpan;
+use c3p0_common::{C3p0Error, DataColumn, Model};
 use sqlx::query::Query;
-use sqlx::{ColumnIndex, Database, Done, Executor, IntoArguments};
+use sqlx::{ColumnIndex, Database, Done, Execute, Executor, IntoArguments};
 
 #[inline]
 pub async fn batch_execute<'e, 'q: 'e, E, DB>(query: &'q str, executor: E) -> Result<(), C3p0Error>
@@ -12,11 +13,15 @@ where
     <DB as sqlx::database::HasArguments<'q>>::Arguments: sqlx::IntoArguments<'q, DB>,
     E: Executor<'e, Database = DB>,
 {
-    executor
-        .execute(query)
+    TraceSpan::sql("sqlx", query)
+        .run(async {
+            executor
+                .execute(query)
+                .await
+                .map_err(into_c3p0_error)
+                .map(|_| ())
+        })
         .await
-        .map_err(into_c3p0_error)
-        .map(|_| ())
 }
 
 #[inline]
@@ -29,17 +34,23 @@ where
     A: 'q + IntoArguments<'q, DB>,
     E: Executor<'e, Database = DB>,
 {
-    query
-        .execute(executor)
+    TraceSpan::sql("sqlx", query.sql())
+        .run(async {
+            query
+                .execute(executor)
+                .await
+                .map_err(into_c3p0_error)
+                .map(|done| done.rows_affected())
+        })
         .await
-        .map_err(into_c3p0_error)
         .map(|_| ())
 }
 
 #[inline]
-pub async fn fetch_one_optional_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC: JsonCodec<DATA>>(
+pub async fn fetch_one_optional_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC, COLUMN>(
     query: Query<'q, DB, A>,
     executor: E,
+    column: &COLUMN,
     codec: &CODEC,
 ) -> Result<Option<Model<DATA>>, C3p0Error>
 where
@@ -49,21 +60,27 @@ where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
     for<'c> i32: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     for<'c> i64: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
-    for<'c> serde_json::value::Value: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     usize: ColumnIndex<DB::Row>,
 {
-    query
-        .fetch_optional(executor)
+    TraceSpan::sql("sqlx", query.sql())
+        .run(async {
+            query
+                .fetch_optional(executor)
+                .await
+                .map_err(into_c3p0_error)?
+                .map(|row| to_model(column, codec, &row, 0, 1, 2))
+                .transpose()
+        })
         .await
-        .map_err(into_c3p0_error)?
-        .map(|row| to_model(codec, &row, 0, 1, 2))
-        .transpose()
 }
 
 #[inline]
-pub async fn fetch_one_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC: JsonCodec<DATA>>(
+pub async fn fetch_one_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC, COLUMN>(
     query: Query<'q, DB, A>,
     executor: E,
+    column: &COLUMN,
     codec: &CODEC,
 ) -> Result<Model<DATA>, C3p0Error>
 where
@@ -73,20 +90,26 @@ where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
     for<'c> i32: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     for<'c> i64: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
-    for<'c> serde_json::value::Value: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     usize: ColumnIndex<DB::Row>,
 {
-    query
-        .fetch_one(executor)
+    TraceSpan::sql("sqlx", query.sql())
+        .run(async {
+            query
+                .fetch_one(executor)
+                .await
+                .map_err(into_c3p0_error)
+                .and_then(|row| to_model(column, codec, &row, 0, 1, 2))
+        })
         .await
-        .map_err(into_c3p0_error)
-        .and_then(|row| to_model(codec, &row, 0, 1, 2))
 }
 
 #[inline]
-pub async fn fetch_all_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC: JsonCodec<DATA>>(
+pub async fn fetch_all_with_sql<'e, 'q: 'e, A, E, DB, DATA, CODEC, COLUMN>(
     query: Query<'q, DB, A>,
     executor: E,
+    column: &COLUMN,
     codec: &CODEC,
 ) -> Result<Vec<Model<DATA>>, C3p0Error>
 where
@@ -96,16 +119,21 @@ where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
     for<'c> i32: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     for<'c> i64: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
-    for<'c> serde_json::value::Value: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     usize: ColumnIndex<DB::Row>,
 {
-    query
-        .fetch_all(executor)
+    TraceSpan::sql("sqlx", query.sql())
+        .run(async {
+            query
+                .fetch_all(executor)
+                .await
+                .map_err(into_c3p0_error)?
+                .iter()
+                .map(|row| to_model(column, codec, row, 0, 1, 2))
+                .collect::<Result<Vec<_>, C3p0Error>>()
+        })
         .await
-        .map_err(into_c3p0_error)?
-        .iter()
-        .map(|row| to_model(codec, row, 0, 1, 2))
-        .collect::<Result<Vec<_>, C3p0Error>>()
 }
 
 #[inline]
@@ -123,17 +151,20 @@ where
         sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
     for<'c> i64:
         sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
-    for<'c> serde_json::value::Value:
-        sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
     usize: ColumnIndex<DB::Row>,
 {
-    let result = sqlx::query(&queries.delete_sql_query)
-        .bind(obj.id)
-        .bind(obj.version)
-        .execute(executor)
-        .await
-        .map_err(into_c3p0_error)?
-        .rows_affected();
+    let result = TraceSpan::sql("sqlx", &queries.delete_sql_query)
+        .with_table(&queries.qualified_table_name)
+        .run(async {
+            sqlx::query(&queries.delete_sql_query)
+                .bind(obj.id)
+                .bind(obj.version)
+                .execute(executor)
+                .await
+                .map_err(into_c3p0_error)
+                .map(|done| done.rows_affected())
+        })
+        .await?;
 
     if result == 0 {
         return Err(C3p0Error::OptimisticLockError {
@@ -148,10 +179,11 @@ where
 }
 
 #[inline]
-pub async fn update<'e, 'q: 'e, E, DB, DATA, CODEC: JsonCodec<DATA>>(
+pub async fn update<'e, 'q: 'e, E, DB, DATA, CODEC, COLUMN>(
     obj: Model<DATA>,
     executor: E,
     queries: &'q Queries,
+    column: &COLUMN,
     codec: &CODEC,
 ) -> Result<Model<DATA>, C3p0Error>
 where
@@ -163,13 +195,14 @@ where
         sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
     for<'c> i64:
         sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
-    for<'c> serde_json::value::Value:
-        sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB> + sqlx::encode::Encode<'c, DB>,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored: sqlx::types::Type<DB> + sqlx::encode::Encode<'c, DB>,
     usize: ColumnIndex<DB::Row>,
 {
-    let json_data = codec.to_value(&obj.data)?;
+    let stored_data = column.encode(codec, &obj.data)?;
 
     let id = obj.id;
+    let version = obj.version;
     let new_version = obj.version + 1;
 
     let updated_model = Model {
@@ -178,17 +211,20 @@ where
         data: obj.data,
     };
 
-    let result = {
-        sqlx::query(&queries.update_sql_query)
-            .bind(new_version)
-            .bind(json_data)
-            .bind(id)
-            .bind(obj.version)
-            .execute(executor)
-            .await
-            .map_err(into_c3p0_error)
-            .map(|done| done.rows_affected())?
-    };
+    let result = TraceSpan::sql("sqlx", &queries.update_sql_query)
+        .with_table(&queries.qualified_table_name)
+        .run(async {
+            sqlx::query(&queries.update_sql_query)
+                .bind(new_version)
+                .bind(stored_data)
+                .bind(id)
+                .bind(version)
+                .execute(executor)
+                .await
+                .map_err(into_c3p0_error)
+                .map(|done| done.rows_affected())
+        })
+        .await?;
 
     if result == 0 {
         return Err(C3p0Error::OptimisticLockError {
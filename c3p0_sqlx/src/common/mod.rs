@@ -1,18 +1,134 @@
 pub mod executor;
 
-use c3p0_common::{C3p0Error, JsonCodec, Model};
-use sqlx::{ColumnIndex, Database, Row};
+use c3p0_common::pool::Waiters;
+use c3p0_common::{C3p0Error, DataColumn, Model, PoolStatus, TxOptions};
+use sqlx::pool::PoolConnection;
+use sqlx::{ColumnIndex, Database, Executor, Pool, Row};
 
+/// Returns the status of the pool; the waiters are the tasks waiting for a connection
+/// requested by c3p0.
+/// This version of sqlx exposes the number of connections of a pool only in its Debug output,
+/// `Pool { size: 1, num_idle: 1, .. }`; the tests of this module fail if its format changes.
+pub fn pool_status<DB: Database>(pool: &Pool<DB>, waiters: &Waiters) -> PoolStatus {
+    let debug = format!("{:?}", pool);
+    let size = pool_debug_field(&debug, "size").unwrap_or_default();
+    let idle = pool_debug_field(&debug, "num_idle").unwrap_or_default();
+    PoolStatus {
+        size,
+        idle,
+        in_use: size.saturating_sub(idle),
+        waiters: waiters.count(),
+    }
+}
+
+/// Returns the value of a numeric field of the Debug output of a pool
+fn pool_debug_field(debug: &str, name: &str) -> Option<usize> {
+    let prefix = format!(" {}: ", name);
+    debug
+        .find(&prefix)
+        .map(|start| &debug[start + prefix.len()..])
+        .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+        .and_then(|value| value.parse::<usize>().ok())
+}
+
+/// Closes a connection that cannot be returned to the pool as it is, like the one of a transaction
+/// that could still be open or of a session lock that was not released.
+/// `PoolConnection::release` would detach the connection without freeing its slot in the pool,
+/// so `terminate_sql` ends its session on the server and then the connection is dropped:
+/// the pool discards it when it fails the ping before the next acquire, or when the pool is closed.
+/// If the server does not end the session, like TiDB that ignores a `KILL` without `TIDB`,
+/// the `reset_sql` statements roll back its transaction and reset it before it is returned
+/// to the pool.
+/// The statements run after the one that could still be running on the connection.
+pub fn close_connection<DB: Database>(
+    mut conn: PoolConnection<DB>,
+    terminate_sql: &'static str,
+    reset_sql: &'static [&'static str],
+) where
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+{
+    tokio::spawn(async move {
+        // The statement fails when the server ends the session
+        if conn.execute(terminate_sql).await.is_ok() {
+            for sql in reset_sql {
+                // The statements that the server does not support are skipped
+                let _ = conn.execute(*sql).await;
+            }
+        }
+    });
+}
+
+/// Holds the pooled connection of a session lock being acquired.
+/// If it is dropped before `into_conn`, because the acquisition failed or its future
+/// was dropped, the connection is closed by `close`: the server could still grant it the lock,
+/// or it could keep the settings of the acquisition.
+pub struct LockAcquisition<DB: Database> {
+    conn: Option<PoolConnection<DB>>,
+    close: fn(PoolConnection<DB>),
+}
+
+impl<DB: Database> LockAcquisition<DB> {
+    pub fn new(conn: PoolConnection<DB>, close: fn(PoolConnection<DB>)) -> Self {
+        LockAcquisition {
+            conn: Some(conn),
+            close,
+        }
+    }
+
+    pub fn conn(&mut self) -> &mut DB::Connection {
+        self.conn
+            .as_mut()
+            .expect("The connection is taken only by into_conn")
+    }
+
+    pub fn into_conn(mut self) -> PoolConnection<DB> {
+        self.conn
+            .take()
+            .expect("The connection is taken only by into_conn")
+    }
+}
+
+impl<DB: Database> Drop for LockAcquisition<DB> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            (self.close)(conn);
+        }
+    }
+}
+
+/// Returns the `SET TRANSACTION` statement that applies the options, if any.
+/// The deferrable mode is skipped if the database does not support it.
+pub fn set_transaction_sql(options: &TxOptions, deferrable_supported: bool) -> Option<String> {
+    let mut modes = vec![];
+    if let Some(isolation) = options.isolation {
+        modes.push(format!("ISOLATION LEVEL {}", isolation.as_sql()));
+    }
+    if options.read_only {
+        modes.push("READ ONLY".to_owned());
+    }
+    if options.deferrable && deferrable_supported {
+        modes.push("DEFERRABLE".to_owned());
+    }
+    if modes.is_empty() {
+        None
+    } else {
+        Some(format!("SET TRANSACTION {}", modes.join(", ")))
+    }
+}
+
+/// Returns the model of the row, whose data is decoded from the value stored in the column
 #[inline]
 pub fn to_model<
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send,
-    CODEC: JsonCodec<DATA>,
+    CODEC,
+    COLUMN: DataColumn<DATA, CODEC>,
     R: Row<Database = DB>,
     IdIdx: ColumnIndex<R>,
     VersionIdx: ColumnIndex<R>,
     DataIdx: ColumnIndex<R>,
     DB: Database,
 >(
+    column: &COLUMN,
     codec: &CODEC,
     row: &R,
     id_index: IdIdx,
@@ -22,8 +138,36 @@ pub fn to_model<
 where
     for<'c> i32: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
     for<'c> i64: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
-    for<'c> serde_json::value::Value: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
-    //<DB as HasArguments<'_>>::Arguments
+    for<'c> COLUMN::Stored: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+{
+    let model = to_stored_model(row, id_index, version_index, data_index)?;
+    Ok(Model {
+        id: model.id,
+        version: model.version,
+        data: column.decode(codec, model.data)?,
+    })
+}
+
+/// Returns the model of the row with the value stored in its data column
+#[inline]
+pub fn to_stored_model<
+    STORED,
+    R: Row<Database = DB>,
+    IdIdx: ColumnIndex<R>,
+    VersionIdx: ColumnIndex<R>,
+    DataIdx: ColumnIndex<R>,
+    DB: Database,
+>(
+    row: &R,
+    id_index: IdIdx,
+    version_index: VersionIdx,
+    data_index: DataIdx,
+) -> Result<Model<STORED>, C3p0Error>
+where
+    for<'c> i32: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+    for<'c> i64: sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
+    for<'c> STORED:
+        Clone + serde::ser::Serialize + Send + sqlx::types::Type<DB> + sqlx::decode::Decode<'c, DB>,
 {
     let id = row
         .try_get(id_index)
@@ -35,12 +179,48 @@ where
         .map_err(|err| C3p0Error::RowMapperError {
             cause: format!("Row contains no values for version index. Err: {}", err),
         })?;
-    let data =
-        codec.from_value(
-            row.try_get(data_index)
-                .map_err(|err| C3p0Error::RowMapperError {
-                    cause: format!("Row contains no values for data index. Err: {}", err),
-                })?,
-        )?;
+    let data = row
+        .try_get(data_index)
+        .map_err(|err| C3p0Error::RowMapperError {
+            cause: format!("Row contains no values for data index. Err: {}", err),
+        })?;
     Ok(Model { id, version, data })
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn should_find_the_status_fields_in_the_debug_output_of_a_postgres_pool() {
+        // The pool is never connected
+        let pool =
+            Pool::<sqlx::Postgres>::connect_lazy("postgres://c3p0@127.0.0.1:1/c3p0").unwrap();
+        assert_status_fields(&pool);
+    }
+
+    #[cfg(feature = "mysql")]
+    #[tokio::test]
+    async fn should_find_the_status_fields_in_the_debug_output_of_a_mysql_pool() {
+        // The pool is never connected
+        let pool = Pool::<sqlx::MySql>::connect_lazy("mysql://c3p0@127.0.0.1:1/c3p0").unwrap();
+        assert_status_fields(&pool);
+    }
+
+    fn assert_status_fields<DB: Database>(pool: &Pool<DB>) {
+        let debug = format!("{:?}", pool);
+        assert!(pool_debug_field(&debug, "size").is_some(), "{}", debug);
+        assert_eq!(Some(0), pool_debug_field(&debug, "num_idle"), "{}", debug);
+    }
+
+    #[test]
+    fn should_not_find_the_missing_fields() {
+        let debug = "Pool { size: 2, num_idle: 1, is_closed: false }";
+        assert_eq!(Some(2), pool_debug_field(debug, "size"));
+        assert_eq!(Some(1), pool_debug_field(debug, "num_idle"));
+        assert_eq!(None, pool_debug_field(debug, "idle"));
+        assert_eq!(None, pool_debug_field(debug, "is_closed"));
+    }
+}
@@ -1,9 +1,22 @@
 use c3p0_common::C3p0Error;
+use sqlx::error::DatabaseError;
 
 pub fn into_c3p0_error(error: sqlx::Error) -> C3p0Error {
-    C3p0Error::DbError {
-        db: "sqlx",
-        code: None,
-        cause: format!("{}", &error),
+    C3p0Error::from_db(
+        "sqlx",
+        format!("{}", &error),
+        error.as_database_error().and_then(error_code),
+    )
+}
+
+/// Returns the code of a database error: the error number for MySQL,
+/// where many errors share the generic SQLSTATE HY000, otherwise the SQLSTATE
+fn error_code(error: &dyn DatabaseError) -> Option<String> {
+    #[cfg(feature = "mysql")]
+    {
+        if let Some(error) = error.try_downcast_ref::<sqlx::mysql::MySqlDatabaseError>() {
+            return Some(error.number().to_string());
+        }
     }
+    error.code().map(|code| code.into_owned())
 }
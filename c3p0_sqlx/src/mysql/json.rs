@@ -2,13 +2,24 @@ use crate::common::executor::{
     batch_execute, delete, fetch_all_with_sql, fetch_one_optional_with_sql, fetch_one_with_sql,
     update,
 };
-use crate::common::to_model;
+use crate::common::{to_model, to_stored_model};
 use crate::error::into_c3p0_error;
-use crate::mysql::queries::build_mysql_queries;
+use crate::mysql::queries::{
+    build_mysql_binary_queries, build_mysql_create_unique_index_sql_queries,
+    build_mysql_find_all_by_field_prefix_sql_query, build_mysql_find_all_by_field_sql_query,
+    build_mysql_find_all_by_max_field_sql_query, build_mysql_find_by_ids_sql_query,
+    build_mysql_queries,
+};
 use crate::mysql::{Db, DbRow, SqlxMySqlC3p0Pool, SqlxMySqlConnection};
 use async_trait::async_trait;
-use c3p0_common::json::Queries;
+use c3p0_common::json::column::skip_index;
+use c3p0_common::json::{
+    like_prefix_pattern, select_by_field, select_by_field_prefix, select_by_max_field,
+    with_documents, Queries,
+};
 use c3p0_common::*;
+use log::*;
+use serde_json::Value;
 use sqlx::query::Query;
 use sqlx::Done;
 use sqlx::{IntoArguments, Row};
@@ -24,19 +35,37 @@ pub trait SqlxMySqlC3p0JsonBuilder {
         self,
         codec: CODEC,
     ) -> SqlxMySqlC3p0Json<DATA, CODEC>;
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> SqlxMySqlC3p0Binary<DATA, CODEC>;
 }
 
+/// A C3p0Json implementation that stores the documents in the data column `COLUMN`:
+/// a JSON column by default, or a LONGBLOB column for the binary documents.
+///
+/// The unique indexes of the JSON documents are created on generated columns holding the
+/// JSON text of the field, so the text of an indexed value cannot be longer than
+/// 255 characters; the not unique indexes are skipped.
 #[derive(Clone)]
-pub struct SqlxMySqlC3p0Json<DATA, CODEC: JsonCodec<DATA>>
+pub struct SqlxMySqlC3p0Json<DATA, CODEC, COLUMN = JsonColumn>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
 {
     phantom_data: std::marker::PhantomData<DATA>,
 
     codec: CODEC,
+    column: COLUMN,
     queries: Queries,
 }
 
+/// A C3p0Json implementation that stores the data in a LONGBLOB column
+/// encoded by a BinaryCodec.
+pub type SqlxMySqlC3p0Binary<DATA, CODEC> = SqlxMySqlC3p0Json<DATA, CODEC, BinaryColumn>;
+
 impl SqlxMySqlC3p0JsonBuilder for C3p0JsonBuilder<SqlxMySqlC3p0Pool> {
     fn build<DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync>(
         self,
@@ -54,14 +83,36 @@ impl SqlxMySqlC3p0JsonBuilder for C3p0JsonBuilder<SqlxMySqlC3p0Pool> {
         SqlxMySqlC3p0Json {
             phantom_data: std::marker::PhantomData,
             codec,
+            column: JsonColumn {
+                compression: self.compression.clone(),
+                schema: self.schema.clone(),
+            },
             queries: build_mysql_queries(self),
         }
     }
+
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> SqlxMySqlC3p0Binary<DATA, CODEC> {
+        SqlxMySqlC3p0Json {
+            phantom_data: std::marker::PhantomData,
+            codec,
+            column: BinaryColumn {},
+            queries: build_mysql_binary_queries(self),
+        }
+    }
 }
 
-impl<DATA, CODEC: JsonCodec<DATA>> SqlxMySqlC3p0Json<DATA, CODEC>
+impl<DATA, CODEC, COLUMN> SqlxMySqlC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored:
+        sqlx::types::Type<Db> + sqlx::decode::Decode<'c, Db> + sqlx::encode::Encode<'c, Db>,
 {
     pub fn queries(&self) -> &Queries {
         &self.queries
@@ -69,7 +120,7 @@ where
 
     #[inline]
     pub fn to_model(&self, row: &DbRow) -> Result<Model<DATA>, C3p0Error> {
-        to_model(&self.codec, row, 0, 1, 2)
+        to_model(&self.column, &self.codec, row, 0, 1, 2)
     }
 
     /// Allows the execution of a custom sql query and returns the first entry in the result set.
@@ -81,7 +132,7 @@ where
         conn: &mut SqlxMySqlConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Option<Model<DATA>>, C3p0Error> {
-        fetch_one_optional_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_one_optional_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 
     /// Allows the execution of a custom sql query and returns the first entry in the result set.
@@ -93,7 +144,7 @@ where
         conn: &mut SqlxMySqlConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        fetch_one_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_one_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 
     /// Allows the execution of a custom sql query and returns all the entries in the result set.
@@ -105,14 +156,18 @@ where
         conn: &mut SqlxMySqlConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Vec<Model<DATA>>, C3p0Error> {
-        fetch_all_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_all_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 }
 
 #[async_trait]
-impl<DATA, CODEC: JsonCodec<DATA>> C3p0Json<DATA, CODEC> for SqlxMySqlC3p0Json<DATA, CODEC>
+impl<DATA, CODEC, COLUMN> C3p0Json<DATA, CODEC> for SqlxMySqlC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Clone + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored:
+        sqlx::types::Type<Db> + sqlx::decode::Decode<'c, Db> + sqlx::encode::Encode<'c, Db>,
 {
     type Conn = SqlxMySqlConnection;
 
@@ -120,10 +175,44 @@ where
         &self.codec
     }
 
+    fn qualified_table_name(&self) -> &str {
+        &self.queries.qualified_table_name
+    }
+
     async fn create_table_if_not_exists(&self, conn: &mut Self::Conn) -> Result<(), C3p0Error> {
         batch_execute(&self.queries.create_table_sql_query, conn.get_conn()).await
     }
 
+    async fn create_index_if_not_exists(
+        &self,
+        conn: &mut Self::Conn,
+        index: &C3p0Index,
+    ) -> Result<(), C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "create_index_if_not_exists",
+        )? {
+            return skip_index(&self.queries.qualified_table_name, index);
+        }
+        if index.unique {
+            let (add_column_sql, create_index_sql) =
+                build_mysql_create_unique_index_sql_queries(&self.queries, &index.field_name);
+            ignore_mysql_error(
+                batch_execute(&add_column_sql, conn.get_conn()).await,
+                MYSQL_DUPLICATE_COLUMN_ERROR,
+            )?;
+            return ignore_mysql_error(
+                batch_execute(&create_index_sql, conn.get_conn()).await,
+                MYSQL_DUPLICATE_KEY_NAME_ERROR,
+            );
+        }
+        warn!(
+            "Not unique indexes are not supported on MySql JSON documents. Index on table [{}], field [{}] skipped",
+            &self.queries.qualified_table_name, &index.field_name
+        );
+        Ok(())
+    }
+
     async fn drop_table_if_exists(
         &self,
         conn: &mut Self::Conn,
@@ -177,6 +266,134 @@ where
         self.fetch_all_with_sql(conn, sqlx::query(&sql)).await
     }
 
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        self.fetch_all_with_sql(
+            conn,
+            sqlx::query(&self.queries.find_all_after_id_sql_query)
+                .bind(after_id)
+                .bind(limit as i64),
+        )
+        .await
+    }
+
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error> {
+        sqlx::query(&self.queries.find_all_after_id_sql_query)
+            .bind(after_id)
+            .bind(limit as i64)
+            .fetch_all(conn.get_conn())
+            .await
+            .map_err(into_c3p0_error)?
+            .iter()
+            .map(|row| {
+                let model = to_stored_model::<COLUMN::Stored, _, _, _, _, _>(row, 0, 1, 2)?;
+                Ok(Model {
+                    id: model.id,
+                    version: model.version,
+                    data: self.column.to_value(model.data)?,
+                })
+            })
+            .collect::<Result<Vec<_>, C3p0Error>>()
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.column.is_current(&self.codec, value)
+    }
+
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self
+            .column
+            .fields_queryable(&self.queries.qualified_table_name, "fetch_all_by_field")?
+        {
+            return select_by_field(self.fetch_all(conn).await?, field_name, value);
+        }
+        let sql = build_mysql_find_all_by_field_sql_query(&self.queries, field_name);
+        self.fetch_all_with_sql(conn, sqlx::query(&sql).bind(value.to_string()))
+            .await
+    }
+
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_field_prefix",
+        )? {
+            return select_by_field_prefix(self.fetch_all(conn).await?, field_name, prefix);
+        }
+        let sql = build_mysql_find_all_by_field_prefix_sql_query(&self.queries, field_name);
+        self.fetch_all_with_sql(conn, sqlx::query(&sql).bind(like_prefix_pattern(prefix)))
+            .await
+    }
+
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_max_field_for_update",
+        )? {
+            let sql = build_mysql_find_all_by_max_field_sql_query(
+                &self.queries,
+                field_name,
+                order_by_field,
+                for_update,
+            );
+            return self
+                .fetch_all_with_sql(conn, sqlx::query(&sql).bind(max_value).bind(limit as i64))
+                .await;
+        }
+
+        // The documents cannot be queried, so the entries are selected in memory
+        // without locking them; then only the selected entries are locked, in their order,
+        // and they are selected again because they could have changed in the meantime
+        let selected = select_by_max_field(
+            with_documents(self.fetch_all(conn).await?)?,
+            field_name,
+            max_value,
+            order_by_field,
+            u32::MAX,
+        );
+        if selected.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids = selected.iter().map(|model| model.id).collect::<Vec<_>>();
+        let sql = build_mysql_find_by_ids_sql_query(&self.queries, &ids, for_update);
+        let locked = self
+            .fetch_all_with_sql(conn, sqlx::query(&sql).bind(limit as i64))
+            .await?;
+        Ok(select_by_max_field(
+            with_documents(locked)?,
+            field_name,
+            max_value,
+            order_by_field,
+            limit,
+        ))
+    }
+
     async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
         &'a self,
         conn: &mut Self::Conn,
@@ -269,11 +486,11 @@ where
         conn: &mut Self::Conn,
         obj: NewModel<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let json_data = self.codec().to_value(&obj.data)?;
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
 
         let id = sqlx::query(&self.queries.save_sql_query)
             .bind(&obj.version)
-            .bind(&json_data)
+            .bind(stored_data)
             .execute(conn.get_conn())
             .await
             .map(|done| done.last_insert_id())
@@ -286,11 +503,52 @@ where
         })
     }
 
+    async fn save_with_id(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<DATA>,
+    ) -> Result<Model<DATA>, C3p0Error> {
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
+
+        sqlx::query(&self.queries.save_with_id_sql_query)
+            .bind(obj.id)
+            .bind(obj.version)
+            .bind(stored_data)
+            .execute(conn.get_conn())
+            .await
+            .map_err(into_c3p0_error)?;
+
+        Ok(obj)
+    }
+
     async fn update(
         &self,
         conn: &mut Self::Conn,
         obj: Model<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        update(obj, conn.get_conn(), &self.queries, self.codec()).await
+        update(
+            obj,
+            conn.get_conn(),
+            &self.queries,
+            &self.column,
+            &self.codec,
+        )
+        .await
+    }
+}
+
+/// The MySql error number of a column added twice
+const MYSQL_DUPLICATE_COLUMN_ERROR: &str = "1060";
+/// The MySql error number of an index created twice
+const MYSQL_DUPLICATE_KEY_NAME_ERROR: &str = "1061";
+
+/// Ignores the MySql error with the given number, returned by the DDL statements
+/// that have no IF NOT EXISTS clause
+fn ignore_mysql_error(result: Result<(), C3p0Error>, number: &str) -> Result<(), C3p0Error> {
+    match result {
+        Err(C3p0Error::DbError {
+            code: Some(code), ..
+        }) if code == number => Ok(()),
+        result => result,
     }
 }
@@ -0,0 +1,154 @@
+use async_trait::async_trait;
+use c3p0_common::*;
+use std::time::Duration;
+
+use crate::common::LockAcquisition;
+use crate::error::into_c3p0_error;
+use crate::mysql::pool::close_connection;
+use crate::mysql::{Db, SqlxMySqlC3p0Pool, SqlxMySqlConnection};
+use sqlx::pool::PoolConnection;
+use sqlx::Row;
+
+/// The GET_LOCK timeout that waits forever
+const NO_TIMEOUT: i64 = -1;
+
+/// MySQL locks are held by the session, not by the transaction;
+/// the pool releases them when the transaction commits or rolls back.
+#[async_trait]
+impl C3p0Lock for SqlxMySqlConnection {
+    async fn lock(&mut self, key: &LockKey) -> Result<(), C3p0Error> {
+        if self.lock_with_seconds(key, NO_TIMEOUT).await? {
+            Ok(())
+        } else {
+            Err(not_locked_error(key))
+        }
+    }
+
+    async fn try_lock(&mut self, key: &LockKey) -> Result<bool, C3p0Error> {
+        self.lock_with_seconds(key, 0).await
+    }
+
+    async fn lock_with_timeout(
+        &mut self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<bool, C3p0Error> {
+        self.lock_with_seconds(key, to_seconds(timeout)).await
+    }
+}
+
+impl SqlxMySqlConnection {
+    async fn lock_with_seconds(&mut self, key: &LockKey, seconds: i64) -> Result<bool, C3p0Error> {
+        self.set_locked(key)?;
+        get_lock(self.get_conn(), key, seconds).await
+    }
+}
+
+#[async_trait]
+impl C3p0SessionLock for SqlxMySqlC3p0Pool {
+    async fn session_lock(&self, key: &LockKey) -> Result<SessionLock, C3p0Error> {
+        self.session_lock_with_seconds(key, NO_TIMEOUT)
+            .await?
+            .ok_or_else(|| not_locked_error(key))
+    }
+
+    async fn try_session_lock(&self, key: &LockKey) -> Result<Option<SessionLock>, C3p0Error> {
+        self.session_lock_with_seconds(key, 0).await
+    }
+
+    async fn session_lock_with_timeout(
+        &self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<Option<SessionLock>, C3p0Error> {
+        self.session_lock_with_seconds(key, to_seconds(timeout))
+            .await
+    }
+}
+
+impl SqlxMySqlC3p0Pool {
+    async fn session_lock_with_seconds(
+        &self,
+        key: &LockKey,
+        seconds: i64,
+    ) -> Result<Option<SessionLock>, C3p0Error> {
+        let mut acquisition = LockAcquisition::new(self.acquire().await?, close_connection);
+        let locked = get_lock(acquisition.conn(), key, seconds).await?;
+        let conn = acquisition.into_conn();
+        Ok(if locked {
+            Some(SessionLock::new(
+                key.clone(),
+                Box::new(SqlxMySqlLockRelease {
+                    conn: Some(conn),
+                    name: key.name(),
+                }),
+            ))
+        } else {
+            None
+        })
+    }
+}
+
+/// Holds the pooled connection of a session lock.
+/// If it is dropped without being released, the connection is closed by `close_connection`,
+/// so that MySQL releases the lock.
+struct SqlxMySqlLockRelease {
+    conn: Option<PoolConnection<Db>>,
+    name: String,
+}
+
+#[async_trait]
+impl LockRelease for SqlxMySqlLockRelease {
+    async fn release(mut self: Box<Self>) -> Result<(), C3p0Error> {
+        if let Some(mut conn) = self.conn.take() {
+            if let Err(err) = sqlx::query("DO RELEASE_LOCK(?)")
+                .bind(&self.name)
+                .execute(&mut *conn)
+                .await
+            {
+                close_connection(conn);
+                return Err(into_c3p0_error(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqlxMySqlLockRelease {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            close_connection(conn);
+        }
+    }
+}
+
+async fn get_lock<'e, E: sqlx::Executor<'e, Database = Db>>(
+    executor: E,
+    key: &LockKey,
+    seconds: i64,
+) -> Result<bool, C3p0Error> {
+    let locked: Option<i64> = sqlx::query("SELECT GET_LOCK(?, ?)")
+        .bind(key.name())
+        .bind(seconds)
+        .fetch_one(executor)
+        .await
+        .and_then(|row| row.try_get(0))
+        .map_err(into_c3p0_error)?;
+    match locked {
+        Some(locked) => Ok(locked == 1),
+        None => Err(not_locked_error(key)),
+    }
+}
+
+/// GET_LOCK waits for whole seconds, so the timeout is rounded up
+fn to_seconds(timeout: Duration) -> i64 {
+    timeout.as_secs() as i64 + if timeout.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+fn not_locked_error(key: &LockKey) -> C3p0Error {
+    C3p0Error::DbError {
+        db: "mysql",
+        cause: format!("Cannot acquire the lock [{}]", key),
+        code: None,
+    }
+}
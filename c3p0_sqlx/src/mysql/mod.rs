@@ -1,4 +1,5 @@
 mod json;
+mod lock;
 mod pool;
 mod queries;
 
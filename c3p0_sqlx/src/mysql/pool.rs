@@ -1,20 +1,39 @@
 use async_trait::async_trait;
+use c3p0_common::lend::{lend, Lender, Lent};
+use c3p0_common::pool::{SqlSavepointConnection, Waiters};
+use c3p0_common::trace::TraceSpan;
 use c3p0_common::*;
 use futures::Future;
 
 use crate::common::executor::batch_execute;
+use crate::common::{pool_status, set_transaction_sql};
 use crate::error::into_c3p0_error;
 use crate::mysql::Db;
-use sqlx::{Pool, Transaction};
+use sqlx::pool::PoolConnection;
+use sqlx::{Database, Pool};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct SqlxMySqlC3p0Pool {
     pool: Pool<Db>,
+    waiters: Waiters,
 }
 
 impl SqlxMySqlC3p0Pool {
     pub fn new(pool: Pool<Db>) -> Self {
-        SqlxMySqlC3p0Pool { pool }
+        SqlxMySqlC3p0Pool {
+            pool,
+            waiters: Default::default(),
+        }
+    }
+
+    /// Returns a connection of the pool outside of a transaction
+    pub(crate) async fn acquire(&self) -> Result<PoolConnection<Db>, C3p0Error> {
+        self.waiters
+            .wait(self.pool.acquire())
+            .await
+            .map_err(into_c3p0_error)
     }
 }
 
@@ -28,37 +47,265 @@ impl Into<SqlxMySqlC3p0Pool> for Pool<Db> {
 impl C3p0Pool for SqlxMySqlC3p0Pool {
     type Conn = SqlxMySqlConnection;
 
-    async fn transaction<
+    fn status(&self) -> PoolStatus {
+        pool_status(&self.pool, &self.waiters)
+    }
+
+    async fn ping(&self) -> Result<(), C3p0Error> {
+        batch_execute("SELECT 1", &mut *self.acquire().await?).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn transaction_with_options<
         T: Send,
         E: Send + From<C3p0Error>,
         F: Send + FnOnce(Self::Conn) -> Fut,
         Fut: Send + Future<Output = Result<T, E>>,
     >(
         &self,
+        options: &TxOptions,
         tx: F,
     ) -> Result<T, E> {
-        let mut native_transaction = self.pool.begin().await.map_err(into_c3p0_error)?;
+        TraceSpan::transaction("sqlx", options)
+            .run_transaction(async move {
+                let conn = self.acquire().await?;
+                let (conn, lender) = lend(conn, close_connection);
+                let locked = Arc::new(AtomicBool::new(false));
+                let hooks = TransactionHooks::new();
 
-        // ToDo: To avoid this unsafe we need GAT
-        let transaction =
-            SqlxMySqlConnection::Tx(unsafe { ::std::mem::transmute(&mut native_transaction) });
+                let mut transaction = SqlxMySqlConnection {
+                    conn,
+                    in_transaction: true,
+                    locked: locked.clone(),
+                    hooks: hooks.clone(),
+                };
 
-        let result = { (tx)(transaction).await? };
+                let (result, conn) = match begin(&mut transaction, options).await {
+                    // If the client timeout expires the connection is dropped with `tx`
+                    // and closed by `close_connection`, because a statement could still be running;
+                    // this also releases its named locks
+                    Ok(()) => {
+                        match c3p0_common::pool::with_client_timeout(options, (tx)(transaction))
+                            .await
+                        {
+                            Some(result) => end_transaction(lender, result).await,
+                            None => {
+                                drop(lender);
+                                (
+                                    Err(c3p0_common::pool::client_timeout_error("sqlx", options)
+                                        .into()),
+                                    None,
+                                )
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        drop(transaction);
+                        end_transaction(lender, Err(err.into())).await
+                    }
+                };
+                let committed = result.is_ok();
 
-        native_transaction.commit().await.map_err(into_c3p0_error)?;
+                let result = match conn {
+                    // The named locks and the timeouts belong to the session,
+                    // so they are reset when the transaction ends
+                    Some(conn) => {
+                        let mut statements = vec![];
+                        if locked.load(Ordering::SeqCst) {
+                            statements.push("DO RELEASE_ALL_LOCKS()".to_owned());
+                        }
+                        if let Some(sql) = reset_session_timeouts_sql(options) {
+                            statements.push(sql);
+                        }
+                        reset_session(conn, &statements)
+                            .await
+                            .map_err(E::from)
+                            .and(result)
+                    }
+                    None => result,
+                };
 
-        Ok(result)
+                if committed {
+                    hooks.committed().await;
+                } else {
+                    hooks.rolled_back().await;
+                }
+                result
+            })
+            .await
+    }
+
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let conn = self.acquire().await?;
+        // Outside of a transaction the connection can be returned to the pool when it is dropped,
+        // even if `f` kept it
+        let (conn, _) = lend(conn, drop);
+
+        let hooks = TransactionHooks::new();
+
+        let conn = SqlxMySqlConnection {
+            conn,
+            in_transaction: false,
+            locked: Default::default(),
+            hooks: hooks.clone(),
+        };
+
+        let result = (f)(conn).await;
+
+        // Every statement is already committed
+        hooks.committed().await;
+        result
     }
 }
 
-pub enum SqlxMySqlConnection {
-    Tx(&'static mut Transaction<'static, Db>),
+/// Starts a transaction with the options
+async fn begin(conn: &mut SqlxMySqlConnection, options: &TxOptions) -> Result<(), C3p0Error> {
+    // MySQL applies the options to the next transaction, so they are set before it starts
+    if let Some(sql) = set_transaction_sql(options, false) {
+        batch_execute(&sql, conn.get_conn()).await?;
+    }
+    batch_execute("BEGIN", conn.get_conn()).await?;
+    if let Some(sql) = set_session_timeouts_sql(options) {
+        batch_execute(&sql, conn.get_conn()).await?;
+    }
+    Ok(())
+}
+
+/// Commits the transaction if `result` is Ok, otherwise rolls it back.
+/// It returns the connection if its session must be reset, or None if it was closed.
+async fn end_transaction<T, E: From<C3p0Error>>(
+    lender: Lender<PoolConnection<Db>>,
+    result: Result<T, E>,
+) -> (Result<T, E>, Option<PoolConnection<Db>>) {
+    let mut conn = match lender.take_back() {
+        Some(conn) => conn,
+        // The connection is closed by `close_connection` when it is dropped
+        None => {
+            return (
+                Err(c3p0_common::pool::connection_not_released_error().into()),
+                None,
+            )
+        }
+    };
+    let sql = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    match batch_execute(sql, &mut *conn).await {
+        Ok(()) => (result, Some(conn)),
+        Err(err) => {
+            // The transaction could still be open, so the connection is closed
+            close_connection(conn);
+            // The error of `tx` is more relevant than a failed rollback
+            (result.and_then(|_| Err(err.into())), None)
+        }
+    }
+}
+
+/// Closes the connection instead of returning it to the pool as it is; this also releases
+/// its locks
+pub(crate) fn close_connection(conn: PoolConnection<Db>) {
+    crate::common::close_connection(
+        conn,
+        "KILL CONNECTION_ID()",
+        &[
+            "ROLLBACK",
+            "DO RELEASE_ALL_LOCKS()",
+            "SET SESSION max_execution_time = DEFAULT, innodb_lock_wait_timeout = DEFAULT",
+        ],
+    );
+}
+
+/// Executes the statements that reset the session of the connection before it is returned to the pool
+async fn reset_session(
+    mut conn: PoolConnection<Db>,
+    statements: &[String],
+) -> Result<(), C3p0Error> {
+    for sql in statements {
+        if let Err(err) = batch_execute(sql, &mut *conn).await {
+            // Closing the connection discards its session
+            close_connection(conn);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `SET SESSION` statement of the timeouts of the options, if any
+fn set_session_timeouts_sql(options: &TxOptions) -> Option<String> {
+    let mut variables = vec![];
+    if let Some(timeout) = options.statement_timeout {
+        // A max_execution_time of zero disables the timeout
+        variables.push(format!(
+            "max_execution_time = {}",
+            timeout.as_millis().max(1)
+        ));
+    }
+    if let Some(timeout) = options.lock_timeout {
+        // innodb_lock_wait_timeout is in whole seconds, at least one
+        let seconds = timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 };
+        variables.push(format!("innodb_lock_wait_timeout = {}", seconds.max(1)));
+    }
+    session_variables_sql(variables)
+}
+
+/// Returns the `SET SESSION` statement that restores the defaults of the timeouts set by
+/// `set_session_timeouts_sql`, if any
+fn reset_session_timeouts_sql(options: &TxOptions) -> Option<String> {
+    let mut variables = vec![];
+    if options.statement_timeout.is_some() {
+        variables.push("max_execution_time = DEFAULT".to_owned());
+    }
+    if options.lock_timeout.is_some() {
+        variables.push("innodb_lock_wait_timeout = DEFAULT".to_owned());
+    }
+    session_variables_sql(variables)
+}
+
+fn session_variables_sql(variables: Vec<String>) -> Option<String> {
+    if variables.is_empty() {
+        None
+    } else {
+        Some(format!("SET SESSION {}", variables.join(", ")))
+    }
+}
+
+pub struct SqlxMySqlConnection {
+    conn: Lent<PoolConnection<Db>>,
+    /// Whether the connection is in a transaction started by the pool
+    in_transaction: bool,
+    /// Whether a named lock was requested in the transaction
+    locked: Arc<AtomicBool>,
+    hooks: TransactionHooks,
+}
+
+impl C3p0Hooks for SqlxMySqlConnection {
+    fn hooks(&self) -> &TransactionHooks {
+        &self.hooks
+    }
 }
 
 impl SqlxMySqlConnection {
-    pub fn get_conn(&mut self) -> &mut Transaction<'static, Db> {
-        match self {
-            SqlxMySqlConnection::Tx(tx) => tx,
+    pub fn get_conn(&mut self) -> &mut <Db as Database>::Connection {
+        &mut self.conn
+    }
+
+    /// Marks the transaction as holding named locks, that must be released when it ends.
+    /// It fails outside of a transaction, where nothing would release them.
+    pub(crate) fn set_locked(&mut self, key: &LockKey) -> Result<(), C3p0Error> {
+        if self.in_transaction {
+            self.locked.store(true, Ordering::SeqCst);
+            Ok(())
+        } else {
+            Err(c3p0_common::lock::no_transaction_error(key))
         }
     }
 }
@@ -69,3 +316,42 @@ impl SqlConnection for SqlxMySqlConnection {
         batch_execute(sql, self.get_conn()).await
     }
 }
+
+impl SqlSavepointConnection for SqlxMySqlConnection {
+    type Lender = Lender<PoolConnection<Db>>;
+
+    fn lend_transaction(&mut self) -> Result<(Self, Self::Lender), C3p0Error> {
+        if !self.in_transaction {
+            return Err(c3p0_common::pool::no_transaction_savepoint_error());
+        }
+        let (conn, lender) = self.conn.lend_again();
+        Ok((
+            SqlxMySqlConnection {
+                conn,
+                in_transaction: true,
+                locked: self.locked.clone(),
+                hooks: self.hooks.clone(),
+            },
+            lender,
+        ))
+    }
+
+    fn restore_transaction(&mut self, lender: Self::Lender) -> bool {
+        self.conn.restore(lender)
+    }
+}
+
+#[async_trait]
+impl C3p0Savepoint for SqlxMySqlConnection {
+    async fn savepoint<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &mut self,
+        f: F,
+    ) -> Result<T, E> {
+        c3p0_common::pool::sql_savepoint(self, f).await
+    }
+}
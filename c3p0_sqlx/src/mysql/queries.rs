@@ -1,5 +1,18 @@
 pub fn build_mysql_queries<C3P0>(
     json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+) -> c3p0_common::json::Queries {
+    build_mysql_queries_with_data_type(json_builder, "JSON")
+}
+
+pub fn build_mysql_binary_queries<C3P0>(
+    json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+) -> c3p0_common::json::Queries {
+    build_mysql_queries_with_data_type(json_builder, "LONGBLOB")
+}
+
+fn build_mysql_queries_with_data_type<C3P0>(
+    json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+    data_type: &str,
 ) -> c3p0_common::json::Queries {
     let qualified_table_name = match &json_builder.schema_name {
         Some(schema_name) => format!(r#"{}."{}""#, schema_name, json_builder.table_name),
@@ -23,6 +36,16 @@ pub fn build_mysql_queries<C3P0>(
             json_builder.id_field_name,
         ),
 
+        find_all_after_id_sql_query: format!(
+            "SELECT {}, {}, {} FROM {} WHERE {} > ? ORDER BY {} ASC LIMIT ?",
+            json_builder.id_field_name,
+            json_builder.version_field_name,
+            json_builder.data_field_name,
+            qualified_table_name,
+            json_builder.id_field_name,
+            json_builder.id_field_name,
+        ),
+
         find_by_id_sql_query: format!(
             "SELECT {}, {}, {} FROM {} WHERE {} = ? LIMIT 1",
             json_builder.id_field_name,
@@ -49,6 +72,14 @@ pub fn build_mysql_queries<C3P0>(
             qualified_table_name, json_builder.version_field_name, json_builder.data_field_name
         ),
 
+        save_with_id_sql_query: format!(
+            "INSERT INTO {} ({}, {}, {}) VALUES (?, ?, ?)",
+            qualified_table_name,
+            json_builder.id_field_name,
+            json_builder.version_field_name,
+            json_builder.data_field_name
+        ),
+
         update_sql_query: format!(
             "UPDATE {} SET {} = ?, {} = ? WHERE {} = ? AND {} = ?",
             qualified_table_name,
@@ -63,13 +94,14 @@ pub fn build_mysql_queries<C3P0>(
                 CREATE TABLE IF NOT EXISTS {} (
                     {} BIGINT primary key NOT NULL AUTO_INCREMENT,
                     {} int not null,
-                    {} JSON
+                    {} {}
                 )
                 "#,
             qualified_table_name,
             json_builder.id_field_name,
             json_builder.version_field_name,
-            json_builder.data_field_name
+            json_builder.data_field_name,
+            data_type
         ),
 
         drop_table_sql_query: format!("DROP TABLE IF EXISTS {}", qualified_table_name),
@@ -79,6 +111,7 @@ pub fn build_mysql_queries<C3P0>(
         ),
 
         lock_table_sql_query: Some(format!("LOCK TABLES {} WRITE", qualified_table_name)),
+        change_feed_sql_query: None,
 
         qualified_table_name,
         table_name: json_builder.table_name,
@@ -88,3 +121,141 @@ pub fn build_mysql_queries<C3P0>(
         schema_name: json_builder.schema_name,
     }
 }
+
+/// Returns the query that selects the entries whose document has the given top level field
+/// equal to the JSON parameter
+pub fn build_mysql_find_all_by_field_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+) -> String {
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE {} = CAST(? AS JSON) ORDER BY {} ASC",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        mysql_extract_field(queries, field_name),
+        queries.id_field_name,
+    )
+}
+
+/// Returns the query that selects the entries whose document has the given top level field
+/// set to a string matching the LIKE pattern parameter, escaped with `LIKE_ESCAPE`
+pub fn build_mysql_find_all_by_field_prefix_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+) -> String {
+    let field = mysql_extract_field(queries, field_name);
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE JSON_TYPE({}) = 'STRING' AND JSON_UNQUOTE({}) LIKE ? ESCAPE '{}' ORDER BY {} ASC",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        field,
+        field,
+        c3p0_common::json::LIKE_ESCAPE,
+        queries.id_field_name,
+    )
+}
+
+/// Returns the query that selects and locks at most ? entries whose document has the given
+/// top level field set to a number lower than or equal to the first parameter
+pub fn build_mysql_find_all_by_max_field_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+    order_by_field: Option<(&str, &c3p0_common::OrderBy)>,
+    for_update: &c3p0_common::ForUpdate,
+) -> String {
+    let field = mysql_extract_field(queries, field_name);
+    let order_by = match order_by_field {
+        Some((order_by_field, order_by)) => format!(
+            "{} {}, ",
+            mysql_extract_field(queries, order_by_field),
+            order_by.to_sql()
+        ),
+        None => "".to_owned(),
+    };
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE JSON_TYPE({}) IN ('INTEGER', 'UNSIGNED INTEGER', 'DOUBLE', 'DECIMAL') \
+         AND {} <= ? ORDER BY {}{} ASC, {} ASC LIMIT ?\n{}",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        field,
+        field,
+        order_by,
+        field,
+        queries.id_field_name,
+        for_update.to_sql(),
+    )
+}
+
+/// Returns the query that selects and locks at most ? entries with the given ids,
+/// in the same order
+pub fn build_mysql_find_by_ids_sql_query(
+    queries: &c3p0_common::json::Queries,
+    ids: &[c3p0_common::IdType],
+    for_update: &c3p0_common::ForUpdate,
+) -> String {
+    let ids = ids
+        .iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE {} IN ({}) ORDER BY FIELD({}, {}) LIMIT ?\n{}",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        queries.id_field_name,
+        ids,
+        queries.id_field_name,
+        ids,
+        for_update.to_sql(),
+    )
+}
+
+/// The max length of the text of the values of a field with a unique index
+pub const MYSQL_UNIQUE_INDEX_MAX_LENGTH: usize = 255;
+
+/// Returns the statements that create a unique index on a top level field of the documents:
+/// the first one adds a generated column with the JSON text of the field,
+/// the second one indexes the column
+pub fn build_mysql_create_unique_index_sql_queries(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+) -> (String, String) {
+    let field_suffix = field_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let column_name = format!("{}_{}", queries.data_field_name, field_suffix);
+    (
+        format!(
+            "ALTER TABLE {} ADD COLUMN {} VARCHAR({}) GENERATED ALWAYS AS ({}) VIRTUAL",
+            queries.qualified_table_name,
+            column_name,
+            MYSQL_UNIQUE_INDEX_MAX_LENGTH,
+            mysql_extract_field(queries, field_name),
+        ),
+        format!(
+            "CREATE UNIQUE INDEX {}_{}_idx ON {} ({})",
+            queries.table_name, field_suffix, queries.qualified_table_name, column_name,
+        ),
+    )
+}
+
+fn mysql_extract_field(queries: &c3p0_common::json::Queries, field_name: &str) -> String {
+    format!(
+        r#"JSON_EXTRACT({}, '$."{}"')"#,
+        queries.data_field_name,
+        // the name is escaped for the JSON path and then for the SQL string literal
+        field_name
+            .replace('\\', "\\\\\\\\")
+            .replace('"', "\\\\\"")
+            .replace('\'', "''"),
+    )
+}
@@ -2,13 +2,22 @@ use crate::common::executor::{
     batch_execute, delete, fetch_all_with_sql, fetch_one_optional_with_sql, fetch_one_with_sql,
     update,
 };
-use crate::common::to_model;
+use crate::common::{to_model, to_stored_model};
 use crate::error::into_c3p0_error;
-use crate::postgres::queries::build_pg_queries;
+use crate::postgres::queries::{
+    build_pg_binary_queries, build_pg_create_index_sql_query,
+    build_pg_find_all_by_field_prefix_sql_query, build_pg_find_all_by_field_sql_query,
+    build_pg_find_all_by_max_field_sql_query, build_pg_find_by_ids_sql_query, build_pg_queries,
+};
 use crate::postgres::{Db, DbRow, SqlxPgC3p0Pool, SqlxPgConnection};
 use async_trait::async_trait;
-use c3p0_common::json::Queries;
+use c3p0_common::json::column::skip_index;
+use c3p0_common::json::{
+    like_prefix_pattern, select_by_field, select_by_field_prefix, select_by_max_field,
+    with_documents, Queries,
+};
 use c3p0_common::*;
+use serde_json::Value;
 use sqlx::query::Query;
 use sqlx::Done;
 use sqlx::{IntoArguments, Row};
@@ -24,19 +33,33 @@ pub trait SqlxPgC3p0JsonBuilder {
         self,
         codec: CODEC,
     ) -> SqlxPgC3p0Json<DATA, CODEC>;
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> SqlxPgC3p0Binary<DATA, CODEC>;
 }
 
+/// A C3p0Json implementation that stores the documents in the data column `COLUMN`:
+/// a JSONB column by default, or a bytea column for the binary documents
 #[derive(Clone)]
-pub struct SqlxPgC3p0Json<DATA, CODEC: JsonCodec<DATA>>
+pub struct SqlxPgC3p0Json<DATA, CODEC, COLUMN = JsonColumn>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
 {
     phantom_data: std::marker::PhantomData<DATA>,
 
     codec: CODEC,
+    column: COLUMN,
     queries: Queries,
 }
 
+/// A C3p0Json implementation that stores the data in a bytea column
+/// encoded by a BinaryCodec.
+pub type SqlxPgC3p0Binary<DATA, CODEC> = SqlxPgC3p0Json<DATA, CODEC, BinaryColumn>;
+
 impl SqlxPgC3p0JsonBuilder for C3p0JsonBuilder<SqlxPgC3p0Pool> {
     fn build<DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync>(
         self,
@@ -54,14 +77,36 @@ impl SqlxPgC3p0JsonBuilder for C3p0JsonBuilder<SqlxPgC3p0Pool> {
         SqlxPgC3p0Json {
             phantom_data: std::marker::PhantomData,
             codec,
+            column: JsonColumn {
+                compression: self.compression.clone(),
+                schema: self.schema.clone(),
+            },
             queries: build_pg_queries(self),
         }
     }
+
+    fn build_binary_with_codec<
+        DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+        CODEC: BinaryCodec<DATA>,
+    >(
+        self,
+        codec: CODEC,
+    ) -> SqlxPgC3p0Binary<DATA, CODEC> {
+        SqlxPgC3p0Json {
+            phantom_data: std::marker::PhantomData,
+            codec,
+            column: BinaryColumn {},
+            queries: build_pg_binary_queries(self),
+        }
+    }
 }
 
-impl<DATA, CODEC: JsonCodec<DATA>> SqlxPgC3p0Json<DATA, CODEC>
+impl<DATA, CODEC, COLUMN> SqlxPgC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored:
+        sqlx::types::Type<Db> + sqlx::decode::Decode<'c, Db> + sqlx::encode::Encode<'c, Db>,
 {
     pub fn queries(&self) -> &Queries {
         &self.queries
@@ -69,7 +114,7 @@ where
 
     #[inline]
     pub fn to_model(&self, row: &DbRow) -> Result<Model<DATA>, C3p0Error> {
-        to_model(&self.codec, row, 0, 1, 2)
+        to_model(&self.column, &self.codec, row, 0, 1, 2)
     }
 
     /// Allows the execution of a custom sql query and returns the first entry in the result set.
@@ -81,7 +126,7 @@ where
         conn: &mut SqlxPgConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Option<Model<DATA>>, C3p0Error> {
-        fetch_one_optional_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_one_optional_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 
     /// Allows the execution of a custom sql query and returns the first entry in the result set.
@@ -93,7 +138,7 @@ where
         conn: &mut SqlxPgConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        fetch_one_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_one_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 
     /// Allows the execution of a custom sql query and returns all the entries in the result set.
@@ -105,14 +150,18 @@ where
         conn: &mut SqlxPgConnection,
         sql: Query<'a, Db, A>,
     ) -> Result<Vec<Model<DATA>>, C3p0Error> {
-        fetch_all_with_sql(sql, conn.get_conn(), self.codec()).await
+        fetch_all_with_sql(sql, conn.get_conn(), &self.column, &self.codec).await
     }
 }
 
 #[async_trait]
-impl<DATA, CODEC: JsonCodec<DATA>> C3p0Json<DATA, CODEC> for SqlxPgC3p0Json<DATA, CODEC>
+impl<DATA, CODEC, COLUMN> C3p0Json<DATA, CODEC> for SqlxPgC3p0Json<DATA, CODEC, COLUMN>
 where
     DATA: Clone + serde::ser::Serialize + serde::de::DeserializeOwned + Send + Sync,
+    CODEC: Clone + Send + Sync,
+    COLUMN: DataColumn<DATA, CODEC>,
+    for<'c> COLUMN::Stored:
+        sqlx::types::Type<Db> + sqlx::decode::Decode<'c, Db> + sqlx::encode::Encode<'c, Db>,
 {
     type Conn = SqlxPgConnection;
 
@@ -120,8 +169,31 @@ where
         &self.codec
     }
 
+    fn qualified_table_name(&self) -> &str {
+        &self.queries.qualified_table_name
+    }
+
     async fn create_table_if_not_exists(&self, conn: &mut Self::Conn) -> Result<(), C3p0Error> {
-        batch_execute(&self.queries.create_table_sql_query, conn.get_conn()).await
+        batch_execute(&self.queries.create_table_sql_query, conn.get_conn()).await?;
+        if let Some(change_feed_sql_query) = &self.queries.change_feed_sql_query {
+            batch_execute(change_feed_sql_query, conn.get_conn()).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_index_if_not_exists(
+        &self,
+        conn: &mut Self::Conn,
+        index: &C3p0Index,
+    ) -> Result<(), C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "create_index_if_not_exists",
+        )? {
+            return skip_index(&self.queries.qualified_table_name, index);
+        }
+        let sql = build_pg_create_index_sql_query(&self.queries, index);
+        batch_execute(&sql, conn.get_conn()).await
     }
 
     async fn drop_table_if_exists(
@@ -177,6 +249,134 @@ where
         self.fetch_all_with_sql(conn, sqlx::query(&sql)).await
     }
 
+    async fn fetch_all_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        self.fetch_all_with_sql(
+            conn,
+            sqlx::query(&self.queries.find_all_after_id_sql_query)
+                .bind(after_id)
+                .bind(limit as i64),
+        )
+        .await
+    }
+
+    async fn fetch_all_values_after_id(
+        &self,
+        conn: &mut Self::Conn,
+        after_id: IdType,
+        limit: u32,
+    ) -> Result<Vec<Model<Value>>, C3p0Error> {
+        sqlx::query(&self.queries.find_all_after_id_sql_query)
+            .bind(after_id)
+            .bind(limit as i64)
+            .fetch_all(conn.get_conn())
+            .await
+            .map_err(into_c3p0_error)?
+            .iter()
+            .map(|row| {
+                let model = to_stored_model::<COLUMN::Stored, _, _, _, _, _>(row, 0, 1, 2)?;
+                Ok(Model {
+                    id: model.id,
+                    version: model.version,
+                    data: self.column.to_value(model.data)?,
+                })
+            })
+            .collect::<Result<Vec<_>, C3p0Error>>()
+    }
+
+    fn is_current(&self, value: &Value) -> bool {
+        self.column.is_current(&self.codec, value)
+    }
+
+    async fn fetch_all_by_field(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        value: &Value,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self
+            .column
+            .fields_queryable(&self.queries.qualified_table_name, "fetch_all_by_field")?
+        {
+            return select_by_field(self.fetch_all(conn).await?, field_name, value);
+        }
+        let sql = build_pg_find_all_by_field_sql_query(&self.queries, field_name);
+        self.fetch_all_with_sql(conn, sqlx::query(&sql).bind(value.clone()))
+            .await
+    }
+
+    async fn fetch_all_by_field_prefix(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if !self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_field_prefix",
+        )? {
+            return select_by_field_prefix(self.fetch_all(conn).await?, field_name, prefix);
+        }
+        let sql = build_pg_find_all_by_field_prefix_sql_query(&self.queries, field_name);
+        self.fetch_all_with_sql(conn, sqlx::query(&sql).bind(like_prefix_pattern(prefix)))
+            .await
+    }
+
+    async fn fetch_all_by_max_field_for_update(
+        &self,
+        conn: &mut Self::Conn,
+        field_name: &str,
+        max_value: i64,
+        order_by_field: Option<(&str, &OrderBy)>,
+        limit: u32,
+        for_update: &ForUpdate,
+    ) -> Result<Vec<Model<DATA>>, C3p0Error> {
+        if self.column.fields_queryable(
+            &self.queries.qualified_table_name,
+            "fetch_all_by_max_field_for_update",
+        )? {
+            let sql = build_pg_find_all_by_max_field_sql_query(
+                &self.queries,
+                field_name,
+                order_by_field,
+                for_update,
+            );
+            return self
+                .fetch_all_with_sql(conn, sqlx::query(&sql).bind(max_value).bind(limit as i64))
+                .await;
+        }
+
+        // The documents cannot be queried, so the entries are selected in memory
+        // without locking them; then only the selected entries are locked, in their order,
+        // and they are selected again because they could have changed in the meantime
+        let selected = select_by_max_field(
+            with_documents(self.fetch_all(conn).await?)?,
+            field_name,
+            max_value,
+            order_by_field,
+            u32::MAX,
+        );
+        if selected.is_empty() {
+            return Ok(vec![]);
+        }
+        let ids = selected.iter().map(|model| model.id).collect::<Vec<_>>();
+        let sql = build_pg_find_by_ids_sql_query(&self.queries, for_update);
+        let locked = self
+            .fetch_all_with_sql(conn, sqlx::query(&sql).bind(ids).bind(limit as i64))
+            .await?;
+        Ok(select_by_max_field(
+            with_documents(locked)?,
+            field_name,
+            max_value,
+            order_by_field,
+            limit,
+        ))
+    }
+
     async fn fetch_one_optional_by_id<'a, ID: Into<&'a IdType> + Send>(
         &'a self,
         conn: &mut Self::Conn,
@@ -269,11 +469,11 @@ where
         conn: &mut Self::Conn,
         obj: NewModel<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        let json_data = self.codec().to_value(&obj.data)?;
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
 
         let id = sqlx::query(&self.queries.save_sql_query)
             .bind(&obj.version)
-            .bind(&json_data)
+            .bind(stored_data)
             .fetch_one(conn.get_conn())
             .await
             .and_then(|row| row.try_get(0))
@@ -286,11 +486,36 @@ where
         })
     }
 
+    async fn save_with_id(
+        &self,
+        conn: &mut Self::Conn,
+        obj: Model<DATA>,
+    ) -> Result<Model<DATA>, C3p0Error> {
+        let stored_data = self.column.encode(&self.codec, &obj.data)?;
+
+        sqlx::query(&self.queries.save_with_id_sql_query)
+            .bind(obj.id)
+            .bind(obj.version)
+            .bind(stored_data)
+            .execute(conn.get_conn())
+            .await
+            .map_err(into_c3p0_error)?;
+
+        Ok(obj)
+    }
+
     async fn update(
         &self,
         conn: &mut Self::Conn,
         obj: Model<DATA>,
     ) -> Result<Model<DATA>, C3p0Error> {
-        update(obj, conn.get_conn(), &self.queries, self.codec()).await
+        update(
+            obj,
+            conn.get_conn(),
+            &self.queries,
+            &self.column,
+            &self.codec,
+        )
+        .await
     }
 }
@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use c3p0_common::*;
+use std::time::Duration;
+
+use crate::common::executor::batch_execute;
+use crate::common::LockAcquisition;
+use crate::error::into_c3p0_error;
+use crate::postgres::pool::close_connection;
+use crate::postgres::{Db, SqlxPgC3p0Pool, SqlxPgConnection};
+use sqlx::pool::PoolConnection;
+use sqlx::Row;
+
+/// The SQLSTATE of a lock that was not acquired within the `lock_timeout`
+const LOCK_NOT_AVAILABLE: &str = "55P03";
+
+#[async_trait]
+impl C3p0Lock for SqlxPgConnection {
+    async fn lock(&mut self, key: &LockKey) -> Result<(), C3p0Error> {
+        require_transaction(self, key)?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(key.id())
+            .execute(self.get_conn())
+            .await
+            .map_err(into_c3p0_error)
+            .map(|_| ())
+    }
+
+    async fn try_lock(&mut self, key: &LockKey) -> Result<bool, C3p0Error> {
+        require_transaction(self, key)?;
+        sqlx::query("SELECT pg_try_advisory_xact_lock($1)")
+            .bind(key.id())
+            .fetch_one(self.get_conn())
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(into_c3p0_error)
+    }
+
+    async fn lock_with_timeout(
+        &mut self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<bool, C3p0Error> {
+        require_transaction(self, key)?;
+        // The failed lock aborts the transaction, so it is attempted in a savepoint
+        let lock_timeout: String = sqlx::query("SELECT current_setting('lock_timeout')")
+            .fetch_one(self.get_conn())
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(into_c3p0_error)?;
+        batch_execute("SAVEPOINT c3p0_lock_with_timeout", self.get_conn()).await?;
+        set_local_lock_timeout(self, &to_pg_timeout(timeout)).await?;
+        match self.lock(key).await {
+            Ok(()) => {
+                batch_execute("RELEASE SAVEPOINT c3p0_lock_with_timeout", self.get_conn()).await?;
+                set_local_lock_timeout(self, &lock_timeout).await?;
+                Ok(true)
+            }
+            Err(err) if is_lock_not_available(&err) => {
+                batch_execute(
+                    "ROLLBACK TO SAVEPOINT c3p0_lock_with_timeout; RELEASE SAVEPOINT c3p0_lock_with_timeout",
+                    self.get_conn(),
+                )
+                .await?;
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+async fn set_local_lock_timeout(
+    conn: &mut SqlxPgConnection,
+    lock_timeout: &str,
+) -> Result<(), C3p0Error> {
+    sqlx::query("SELECT set_config('lock_timeout', $1, true)")
+        .bind(lock_timeout)
+        .execute(conn.get_conn())
+        .await
+        .map_err(into_c3p0_error)
+        .map(|_| ())
+}
+
+#[async_trait]
+impl C3p0SessionLock for SqlxPgC3p0Pool {
+    async fn session_lock(&self, key: &LockKey) -> Result<SessionLock, C3p0Error> {
+        let mut acquisition = LockAcquisition::new(self.acquire().await?, close_connection);
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(key.id())
+            .execute(acquisition.conn())
+            .await
+            .map_err(into_c3p0_error)?;
+        Ok(SqlxPgLockRelease::lock(key, acquisition.into_conn()))
+    }
+
+    async fn try_session_lock(&self, key: &LockKey) -> Result<Option<SessionLock>, C3p0Error> {
+        let mut acquisition = LockAcquisition::new(self.acquire().await?, close_connection);
+        let locked: bool = sqlx::query("SELECT pg_try_advisory_lock($1)")
+            .bind(key.id())
+            .fetch_one(acquisition.conn())
+            .await
+            .and_then(|row| row.try_get(0))
+            .map_err(into_c3p0_error)?;
+        let conn = acquisition.into_conn();
+        Ok(if locked {
+            Some(SqlxPgLockRelease::lock(key, conn))
+        } else {
+            None
+        })
+    }
+
+    async fn session_lock_with_timeout(
+        &self,
+        key: &LockKey,
+        timeout: Duration,
+    ) -> Result<Option<SessionLock>, C3p0Error> {
+        let mut acquisition = LockAcquisition::new(self.acquire().await?, close_connection);
+        batch_execute(
+            &format!("SET lock_timeout = '{}'", to_pg_timeout(timeout)),
+            acquisition.conn(),
+        )
+        .await?;
+        let result = sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(key.id())
+            .execute(acquisition.conn())
+            .await
+            .map_err(into_c3p0_error);
+        batch_execute("RESET lock_timeout", acquisition.conn()).await?;
+        let conn = acquisition.into_conn();
+        match result {
+            Ok(_) => Ok(Some(SqlxPgLockRelease::lock(key, conn))),
+            Err(err) if is_lock_not_available(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Holds the pooled connection of a session lock.
+/// If it is dropped without being released, the connection is closed by `close_connection`,
+/// so that Postgres releases the lock.
+struct SqlxPgLockRelease {
+    conn: Option<PoolConnection<Db>>,
+    id: i64,
+}
+
+impl SqlxPgLockRelease {
+    fn lock(key: &LockKey, conn: PoolConnection<Db>) -> SessionLock {
+        SessionLock::new(
+            key.clone(),
+            Box::new(SqlxPgLockRelease {
+                conn: Some(conn),
+                id: key.id(),
+            }),
+        )
+    }
+}
+
+#[async_trait]
+impl LockRelease for SqlxPgLockRelease {
+    async fn release(mut self: Box<Self>) -> Result<(), C3p0Error> {
+        if let Some(mut conn) = self.conn.take() {
+            if let Err(err) = sqlx::query("SELECT pg_advisory_unlock($1)")
+                .bind(self.id)
+                .execute(&mut *conn)
+                .await
+            {
+                close_connection(conn);
+                return Err(into_c3p0_error(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqlxPgLockRelease {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            close_connection(conn);
+        }
+    }
+}
+
+/// The transaction locks of a connection outside of a transaction would be released
+/// at the end of the statement, so they are refused
+fn require_transaction(conn: &SqlxPgConnection, key: &LockKey) -> Result<(), C3p0Error> {
+    if conn.in_transaction() {
+        Ok(())
+    } else {
+        Err(c3p0_common::lock::no_transaction_error(key))
+    }
+}
+
+/// Returns the value of a Postgres timeout setting
+pub(crate) fn to_pg_timeout(timeout: Duration) -> String {
+    // A timeout of zero disables the timeout
+    format!("{}ms", timeout.as_millis().max(1))
+}
+
+fn is_lock_not_available(err: &C3p0Error) -> bool {
+    match err {
+        C3p0Error::TimeoutError {
+            code: Some(code), ..
+        } => code == LOCK_NOT_AVAILABLE,
+        _ => false,
+    }
+}
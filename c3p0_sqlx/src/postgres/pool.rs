@@ -1,20 +1,39 @@
 use async_trait::async_trait;
+use c3p0_common::lend::{lend, Lender, Lent};
+use c3p0_common::pool::{SqlSavepointConnection, Waiters};
+use c3p0_common::trace::TraceSpan;
 use c3p0_common::*;
-use futures::Future;
+use futures::{stream, Future};
 
 use crate::common::executor::batch_execute;
+use crate::common::{pool_status, set_transaction_sql};
 use crate::error::into_c3p0_error;
+use crate::postgres::lock::to_pg_timeout;
 use crate::postgres::Db;
-use sqlx::{Pool, Transaction};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgListener;
+use sqlx::{Database, Pool};
 
 #[derive(Clone)]
 pub struct SqlxPgC3p0Pool {
     pool: Pool<Db>,
+    waiters: Waiters,
 }
 
 impl SqlxPgC3p0Pool {
     pub fn new(pool: Pool<Db>) -> Self {
-        SqlxPgC3p0Pool { pool }
+        SqlxPgC3p0Pool {
+            pool,
+            waiters: Default::default(),
+        }
+    }
+
+    /// Returns a connection of the pool outside of a transaction
+    pub(crate) async fn acquire(&self) -> Result<PoolConnection<Db>, C3p0Error> {
+        self.waiters
+            .wait(self.pool.acquire())
+            .await
+            .map_err(into_c3p0_error)
     }
 }
 
@@ -28,38 +47,216 @@ impl Into<SqlxPgC3p0Pool> for Pool<Db> {
 impl C3p0Pool for SqlxPgC3p0Pool {
     type Conn = SqlxPgConnection;
 
-    async fn transaction<
+    fn status(&self) -> PoolStatus {
+        pool_status(&self.pool, &self.waiters)
+    }
+
+    async fn ping(&self) -> Result<(), C3p0Error> {
+        batch_execute("SELECT 1", &mut *self.acquire().await?).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn transaction_with_options<
         T: Send,
         E: Send + From<C3p0Error>,
         F: Send + FnOnce(Self::Conn) -> Fut,
         Fut: Send + Future<Output = Result<T, E>>,
     >(
         &self,
+        options: &TxOptions,
         tx: F,
     ) -> Result<T, E> {
-        let mut native_transaction = self.pool.begin().await.map_err(into_c3p0_error)?;
+        TraceSpan::transaction("sqlx", options)
+            .run_transaction(async move {
+                let conn = self.acquire().await?;
+                let (conn, lender) = lend(conn, close_connection);
+                let hooks = TransactionHooks::new();
 
-        // ToDo: To avoid this unsafe we need GAT
-        let transaction =
-            SqlxPgConnection::Tx(unsafe { ::std::mem::transmute(&mut native_transaction) });
+                let mut transaction = SqlxPgConnection {
+                    conn,
+                    in_transaction: true,
+                    hooks: hooks.clone(),
+                };
 
-        let result = { (tx)(transaction).await? };
+                let result = match batch_execute(&begin_sql(options), transaction.get_conn()).await
+                {
+                    // If the client timeout expires the connection is dropped with `tx`
+                    // and closed by `close_connection`, because a statement could still be running
+                    Ok(()) => {
+                        match c3p0_common::pool::with_client_timeout(options, (tx)(transaction))
+                            .await
+                        {
+                            Some(result) => end_transaction(lender, result).await,
+                            None => {
+                                drop(lender);
+                                Err(c3p0_common::pool::client_timeout_error("sqlx", options).into())
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        // The transaction is aborted if one of the statements that follow BEGIN failed
+                        drop(transaction);
+                        end_transaction(lender, Err(err.into())).await
+                    }
+                };
 
-        native_transaction.commit().await.map_err(into_c3p0_error)?;
+                match result {
+                    Ok(_) => hooks.committed().await,
+                    Err(_) => hooks.rolled_back().await,
+                }
+                result
+            })
+            .await
+    }
 
-        Ok(result)
+    async fn connection<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self::Conn) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &self,
+        f: F,
+    ) -> Result<T, E> {
+        let conn = self.acquire().await?;
+        // Outside of a transaction the connection can be returned to the pool when it is dropped,
+        // even if `f` kept it
+        let (conn, _) = lend(conn, drop);
+
+        let hooks = TransactionHooks::new();
+
+        let conn = SqlxPgConnection {
+            conn,
+            in_transaction: false,
+            hooks: hooks.clone(),
+        };
+
+        let result = (f)(conn).await;
+
+        // Every statement is already committed
+        hooks.committed().await;
+        result
     }
 }
 
-pub enum SqlxPgConnection {
-    Tx(&'static mut Transaction<'static, Db>),
+/// Commits the transaction if `result` is Ok, otherwise rolls it back
+async fn end_transaction<T, E: From<C3p0Error>>(
+    lender: Lender<PoolConnection<Db>>,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    let mut conn = match lender.take_back() {
+        Some(conn) => conn,
+        // The connection is closed by `close_connection` when it is dropped
+        None => return Err(c3p0_common::pool::connection_not_released_error().into()),
+    };
+    let sql = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    match batch_execute(sql, &mut *conn).await {
+        Ok(()) => result,
+        Err(err) => {
+            // The transaction could still be open, so the connection is closed
+            close_connection(conn);
+            // The error of `tx` is more relevant than a failed rollback
+            result.and_then(|_| Err(err.into()))
+        }
+    }
+}
+
+/// Closes the connection instead of returning it to the pool as it is; this also releases
+/// its locks
+pub(crate) fn close_connection(conn: PoolConnection<Db>) {
+    crate::common::close_connection(
+        conn,
+        "SELECT pg_terminate_backend(pg_backend_pid())",
+        &["ROLLBACK", "SELECT pg_advisory_unlock_all()", "RESET ALL"],
+    );
+}
+
+#[async_trait]
+impl C3p0ChangeFeed for SqlxPgC3p0Pool {
+    async fn subscribe(&self, table_name: &str) -> Result<ChangeStream, C3p0Error> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .map_err(into_c3p0_error)?;
+        listener
+            .listen(&change_feed_channel(table_name))
+            .await
+            .map_err(into_c3p0_error)?;
+
+        // `PgListener::into_stream` would reconnect without notifying the lost changes,
+        // so the stream ends with an error when the connection is lost
+        let table_name = table_name.to_owned();
+        Ok(Box::pin(stream::unfold(Some(listener), move |listener| {
+            let table_name = table_name.clone();
+            async move {
+                let mut listener = listener?;
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => Some((
+                        ChangeEvent::from_payload(notification.payload()),
+                        Some(listener),
+                    )),
+                    Ok(None) => Some((Err(listener_closed_error("postgres", &table_name)), None)),
+                    Err(err) => Some((Err(into_c3p0_error(err)), None)),
+                }
+            }
+        })))
+    }
+}
+
+/// Returns the statements that start a transaction with the options
+fn begin_sql(options: &TxOptions) -> String {
+    let mut statements = vec!["BEGIN".to_owned()];
+    // The options are set by the first statement of the transaction
+    statements.extend(set_transaction_sql(options, true));
+    statements.extend(set_local_timeouts_sql(options));
+    statements.join("; ")
+}
+
+/// Returns the `SET LOCAL` statements of the timeouts of the options, if any
+fn set_local_timeouts_sql(options: &TxOptions) -> Option<String> {
+    let mut statements = vec![];
+    if let Some(timeout) = options.statement_timeout {
+        statements.push(format!(
+            "SET LOCAL statement_timeout = '{}'",
+            to_pg_timeout(timeout)
+        ));
+    }
+    if let Some(timeout) = options.lock_timeout {
+        statements.push(format!(
+            "SET LOCAL lock_timeout = '{}'",
+            to_pg_timeout(timeout)
+        ));
+    }
+    if statements.is_empty() {
+        None
+    } else {
+        Some(statements.join("; "))
+    }
+}
+
+pub struct SqlxPgConnection {
+    conn: Lent<PoolConnection<Db>>,
+    /// Whether the connection is in a transaction started by the pool
+    in_transaction: bool,
+    hooks: TransactionHooks,
 }
 
 impl SqlxPgConnection {
-    pub fn get_conn(&mut self) -> &mut Transaction<'static, Db> {
-        match self {
-            SqlxPgConnection::Tx(tx) => tx,
-        }
+    pub fn get_conn(&mut self) -> &mut <Db as Database>::Connection {
+        &mut self.conn
+    }
+
+    /// Whether the connection is in a transaction started by the pool
+    pub(crate) fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+}
+
+impl C3p0Hooks for SqlxPgConnection {
+    fn hooks(&self) -> &TransactionHooks {
+        &self.hooks
     }
 }
 
@@ -69,3 +266,41 @@ impl SqlConnection for SqlxPgConnection {
         batch_execute(sql, self.get_conn()).await
     }
 }
+
+impl SqlSavepointConnection for SqlxPgConnection {
+    type Lender = Lender<PoolConnection<Db>>;
+
+    fn lend_transaction(&mut self) -> Result<(Self, Self::Lender), C3p0Error> {
+        if !self.in_transaction {
+            return Err(c3p0_common::pool::no_transaction_savepoint_error());
+        }
+        let (conn, lender) = self.conn.lend_again();
+        Ok((
+            SqlxPgConnection {
+                conn,
+                in_transaction: true,
+                hooks: self.hooks.clone(),
+            },
+            lender,
+        ))
+    }
+
+    fn restore_transaction(&mut self, lender: Self::Lender) -> bool {
+        self.conn.restore(lender)
+    }
+}
+
+#[async_trait]
+impl C3p0Savepoint for SqlxPgConnection {
+    async fn savepoint<
+        T: Send,
+        E: Send + From<C3p0Error>,
+        F: Send + FnOnce(Self) -> Fut,
+        Fut: Send + Future<Output = Result<T, E>>,
+    >(
+        &mut self,
+        f: F,
+    ) -> Result<T, E> {
+        c3p0_common::pool::sql_savepoint(self, f).await
+    }
+}
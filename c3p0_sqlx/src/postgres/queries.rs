@@ -1,5 +1,22 @@
 pub fn build_pg_queries<C3P0>(
     json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+) -> c3p0_common::json::Queries {
+    let data_type = match json_builder.pg_schema_check_constraint() {
+        Some(check) => format!("JSONB CHECK ({})", check),
+        None => "JSONB".to_owned(),
+    };
+    build_pg_queries_with_data_type(json_builder, &data_type)
+}
+
+pub fn build_pg_binary_queries<C3P0>(
+    json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+) -> c3p0_common::json::Queries {
+    build_pg_queries_with_data_type(json_builder, "bytea")
+}
+
+fn build_pg_queries_with_data_type<C3P0>(
+    json_builder: c3p0_common::C3p0JsonBuilder<C3P0>,
+    data_type: &str,
 ) -> c3p0_common::json::Queries {
     let qualified_table_name = match &json_builder.schema_name {
         Some(schema_name) => format!(r#"{}."{}""#, schema_name, json_builder.table_name),
@@ -23,6 +40,16 @@ pub fn build_pg_queries<C3P0>(
             json_builder.id_field_name,
         ),
 
+        find_all_after_id_sql_query: format!(
+            "SELECT {}, {}, {} FROM {} WHERE {} > $1 ORDER BY {} ASC LIMIT $2",
+            json_builder.id_field_name,
+            json_builder.version_field_name,
+            json_builder.data_field_name,
+            qualified_table_name,
+            json_builder.id_field_name,
+            json_builder.id_field_name,
+        ),
+
         find_by_id_sql_query: format!(
             "SELECT {}, {}, {} FROM {} WHERE {} = $1 LIMIT 1",
             json_builder.id_field_name,
@@ -52,6 +79,16 @@ pub fn build_pg_queries<C3P0>(
             json_builder.id_field_name
         ),
 
+        save_with_id_sql_query: format!(
+            "WITH saved AS (INSERT INTO {table} ({id}, {version}, {data}) VALUES ($1, $2, $3)) SELECT setval(pg_get_serial_sequence({table_name}, {id_name}), GREATEST($1, nextval(pg_get_serial_sequence({table_name}, {id_name}))))",
+            table = qualified_table_name,
+            id = json_builder.id_field_name,
+            version = json_builder.version_field_name,
+            data = json_builder.data_field_name,
+            table_name = pg_string(&qualified_table_name),
+            id_name = pg_string(&json_builder.id_field_name),
+        ),
+
         update_sql_query: format!(
             "UPDATE {} SET {} = $1, {} = $2 WHERE {} = $3 AND {} = $4",
             qualified_table_name,
@@ -66,13 +103,14 @@ pub fn build_pg_queries<C3P0>(
                 CREATE TABLE IF NOT EXISTS {} (
                     {} bigserial primary key,
                     {} int not null,
-                    {} JSONB
+                    {} {}
                 )
                 "#,
             qualified_table_name,
             json_builder.id_field_name,
             json_builder.version_field_name,
-            json_builder.data_field_name
+            json_builder.data_field_name,
+            data_type
         ),
 
         drop_table_sql_query: format!("DROP TABLE IF EXISTS {}", qualified_table_name),
@@ -86,6 +124,15 @@ pub fn build_pg_queries<C3P0>(
             qualified_table_name
         )),
 
+        change_feed_sql_query: if json_builder.change_feed {
+            Some(build_pg_change_feed_sql_query(
+                &json_builder,
+                &qualified_table_name,
+            ))
+        } else {
+            None
+        },
+
         qualified_table_name,
         table_name: json_builder.table_name,
         id_field_name: json_builder.id_field_name,
@@ -94,3 +141,203 @@ pub fn build_pg_queries<C3P0>(
         schema_name: json_builder.schema_name,
     }
 }
+
+/// Returns the query that selects the entries whose document has the given top level field
+/// equal to the jsonb parameter $1
+pub fn build_pg_find_all_by_field_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+) -> String {
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE {} -> {} = $1 ORDER BY {} ASC",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        queries.data_field_name,
+        pg_string(field_name),
+        queries.id_field_name,
+    )
+}
+
+/// Returns the query that selects the entries whose document has the given top level field
+/// set to a string matching the LIKE pattern $1, escaped with `LIKE_ESCAPE`
+pub fn build_pg_find_all_by_field_prefix_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+) -> String {
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE jsonb_typeof({} -> {}) = 'string' AND {} ->> {} LIKE $1 ESCAPE {} ORDER BY {} ASC",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        queries.data_field_name,
+        pg_string(field_name),
+        queries.data_field_name,
+        pg_string(field_name),
+        pg_string(&c3p0_common::json::LIKE_ESCAPE.to_string()),
+        queries.id_field_name,
+    )
+}
+
+/// Returns the query that selects and locks at most $2 entries whose document has the given
+/// top level field set to a number lower than or equal to the bigint parameter $1
+pub fn build_pg_find_all_by_max_field_sql_query(
+    queries: &c3p0_common::json::Queries,
+    field_name: &str,
+    order_by_field: Option<(&str, &c3p0_common::OrderBy)>,
+    for_update: &c3p0_common::ForUpdate,
+) -> String {
+    let field = pg_numeric_field(queries, field_name);
+    let order_by = match order_by_field {
+        Some((order_by_field, order_by)) => format!(
+            "{} {}, ",
+            pg_numeric_field(queries, order_by_field),
+            order_by.to_sql()
+        ),
+        None => "".to_owned(),
+    };
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE {} <= CAST($1 AS BIGINT) ORDER BY {}{} ASC, {} ASC LIMIT $2\n{}",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        field,
+        order_by,
+        field,
+        queries.id_field_name,
+        for_update.to_sql(),
+    )
+}
+
+/// Returns the query that selects and locks at most $2 entries with the ids of the
+/// bigint array $1, in the same order
+pub fn build_pg_find_by_ids_sql_query(
+    queries: &c3p0_common::json::Queries,
+    for_update: &c3p0_common::ForUpdate,
+) -> String {
+    format!(
+        "SELECT {}, {}, {} FROM {} WHERE {} = ANY($1) ORDER BY array_position($1, {}) LIMIT $2\n{}",
+        queries.id_field_name,
+        queries.version_field_name,
+        queries.data_field_name,
+        queries.qualified_table_name,
+        queries.id_field_name,
+        queries.id_field_name,
+        for_update.to_sql(),
+    )
+}
+
+/// Returns the value of a top level field of the document if it is a number, NULL otherwise
+fn pg_numeric_field(queries: &c3p0_common::json::Queries, field_name: &str) -> String {
+    format!(
+        "(CASE WHEN jsonb_typeof({data} -> {name}) = 'number' THEN ({data} ->> {name})::numeric END)",
+        data = queries.data_field_name,
+        name = pg_string(field_name),
+    )
+}
+
+pub fn build_pg_create_index_sql_query(
+    queries: &c3p0_common::json::Queries,
+    index: &c3p0_common::C3p0Index,
+) -> String {
+    let field_suffix = index
+        .field_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if index.numeric {
+        // The same expression and order of the queries of fetch_all_by_max_field_for_update
+        return format!(
+            "CREATE INDEX IF NOT EXISTS {}_{}_num_idx ON {} ({}, {})",
+            queries.table_name,
+            field_suffix,
+            queries.qualified_table_name,
+            pg_numeric_field(queries, &index.field_name),
+            queries.id_field_name,
+        );
+    }
+    format!(
+        "CREATE {}INDEX IF NOT EXISTS {}_{}_idx ON {} (({} -> {}))",
+        if index.unique { "UNIQUE " } else { "" },
+        queries.table_name,
+        field_suffix,
+        queries.qualified_table_name,
+        queries.data_field_name,
+        pg_string(&index.field_name),
+    )
+}
+
+/// Returns the statements that install the trigger notifying the changes of the table
+/// on its change feed channel.
+/// The trigger is created only if it does not exist, so that the table is not locked
+/// every time it is created if not exists.
+fn build_pg_change_feed_sql_query<C3P0>(
+    json_builder: &c3p0_common::C3p0JsonBuilder<C3P0>,
+    qualified_table_name: &str,
+) -> String {
+    let table_name = c3p0_common::change_feed_table_name(json_builder);
+    let function_name = match &json_builder.schema_name {
+        Some(schema_name) => format!(
+            r#"{}."{}_c3p0_notify""#,
+            schema_name, json_builder.table_name
+        ),
+        None => format!("{}_c3p0_notify", json_builder.table_name),
+    };
+    let trigger_name = format!("{}_c3p0_notify", json_builder.table_name);
+    format!(
+        r#"
+            CREATE OR REPLACE FUNCTION {function_name}() RETURNS trigger AS $c3p0$
+            DECLARE
+                entry RECORD;
+            BEGIN
+                IF TG_OP = 'DELETE' THEN
+                    entry := OLD;
+                ELSE
+                    entry := NEW;
+                END IF;
+                PERFORM pg_notify({channel}, json_build_object(
+                    'table_name', {table_name},
+                    'operation', TG_OP,
+                    'id', entry.{id},
+                    'version', entry.{version}
+                )::text);
+                RETURN NULL;
+            END;
+            $c3p0$ LANGUAGE plpgsql;
+
+            DO $c3p0$
+            BEGIN
+                IF NOT EXISTS (
+                    SELECT 1 FROM pg_trigger
+                    WHERE tgrelid = {qualified_table_name_string}::regclass
+                    AND tgname = {trigger_name_string}
+                ) THEN
+                    CREATE TRIGGER {trigger_name}
+                        AFTER INSERT OR UPDATE OR DELETE ON {qualified_table_name}
+                        FOR EACH ROW EXECUTE PROCEDURE {function_name}();
+                END IF;
+            EXCEPTION
+                -- The trigger was created concurrently
+                WHEN duplicate_object THEN NULL;
+            END
+            $c3p0$;
+            "#,
+        function_name = function_name,
+        channel = pg_string(&c3p0_common::change_feed_channel(&table_name)),
+        table_name = pg_string(&table_name),
+        id = json_builder.id_field_name,
+        version = json_builder.version_field_name,
+        trigger_name = trigger_name,
+        // The unquoted trigger name is stored in lowercase
+        trigger_name_string = pg_string(&trigger_name.to_ascii_lowercase()),
+        qualified_table_name = qualified_table_name,
+        qualified_table_name_string = pg_string(qualified_table_name),
+    )
+}
+
+fn pg_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
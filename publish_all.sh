@@ -6,6 +6,7 @@ set -e
 declare -a publish_list=(
     "c3p0_common"
     "c3p0_in_memory"
+    "c3p0_macro"
     "c3p0_postgres"
     "c3p0_sqlx"
     "c3p0"